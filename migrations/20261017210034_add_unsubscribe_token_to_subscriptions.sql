@@ -0,0 +1,9 @@
+-- Add migration script here
+ALTER TABLE subscriptions ADD COLUMN unsubscribe_token TEXT NULL;
+
+UPDATE subscriptions
+    SET unsubscribe_token = replace(gen_random_uuid()::text, '-', '')
+    WHERE unsubscribe_token IS NULL;
+
+ALTER TABLE subscriptions ALTER COLUMN unsubscribe_token SET NOT NULL;
+ALTER TABLE subscriptions ADD UNIQUE (unsubscribe_token);
@@ -0,0 +1,6 @@
+-- Add migration script here
+ALTER TABLE subscription_tokens
+ADD COLUMN created_at timestamptz NOT NULL DEFAULT now();
+
+ALTER TABLE subscription_tokens
+ADD COLUMN used_at timestamptz NULL;
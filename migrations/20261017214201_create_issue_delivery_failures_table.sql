@@ -0,0 +1,13 @@
+-- Add migration script here
+ALTER TABLE issue_delivery_queue
+    ADD COLUMN enqueued_at timestamptz NOT NULL DEFAULT now();
+
+CREATE TABLE issue_delivery_failures (
+    newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues (newsletter_issue_id),
+    subscriber_email text NOT NULL,
+    last_error text NOT NULL,
+    n_attempts SMALLINT NOT NULL,
+    enqueued_at timestamptz NOT NULL,
+    failed_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (newsletter_issue_id, subscriber_email)
+);
@@ -0,0 +1,11 @@
+-- Add migration script here
+CREATE TABLE email_events (
+    event_id uuid PRIMARY KEY,
+    provider_event_id BIGINT,
+    record_type TEXT NOT NULL,
+    event_type TEXT,
+    email TEXT NOT NULL,
+    payload JSONB NOT NULL,
+    received_at timestamptz NOT NULL DEFAULT now(),
+    UNIQUE (record_type, provider_event_id)
+);
@@ -0,0 +1,7 @@
+-- Add migration script here
+ALTER TABLE newsletter_issues
+    ADD COLUMN n_sent INTEGER NOT NULL DEFAULT 0,
+    ADD COLUMN n_failed INTEGER NOT NULL DEFAULT 0,
+    ADD COLUMN n_skipped INTEGER NOT NULL DEFAULT 0,
+    ADD COLUMN first_sent_at timestamptz NULL,
+    ADD COLUMN last_sent_at timestamptz NULL;
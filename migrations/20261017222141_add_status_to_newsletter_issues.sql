@@ -0,0 +1,4 @@
+-- Add migration script here
+-- Scheduled issues use published_at as the time they go out
+ALTER TABLE newsletter_issues
+    ADD COLUMN status TEXT NOT NULL DEFAULT 'published';
@@ -0,0 +1,4 @@
+-- Add migration script here
+-- Drafts have not been published, nor scheduled, yet
+ALTER TABLE newsletter_issues
+    ALTER COLUMN published_at DROP NOT NULL;
@@ -0,0 +1,3 @@
+-- Add migration script here
+ALTER TABLE newsletter_issues
+    ADD COLUMN n_cancelled INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,4 @@
+-- Add migration script here
+-- The source of the bodies for issues authored in Markdown
+ALTER TABLE newsletter_issues
+    ADD COLUMN markdown_content TEXT NULL;
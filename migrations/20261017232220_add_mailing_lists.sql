@@ -0,0 +1,37 @@
+-- Add migration script here
+CREATE TABLE lists (
+    list_id uuid PRIMARY KEY,
+    slug TEXT NOT NULL UNIQUE,
+    name TEXT NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT now()
+);
+
+-- Every existing subscriber and issue belongs to the default list
+INSERT INTO lists (list_id, slug, name)
+VALUES (gen_random_uuid(), 'newsletter', 'Newsletter');
+
+-- Each list is confirmed separately; status uses the subscriber statuses
+CREATE TABLE list_memberships (
+    list_id uuid NOT NULL REFERENCES lists (list_id),
+    subscription_id uuid NOT NULL REFERENCES subscriptions (id),
+    status TEXT NOT NULL,
+    joined_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (list_id, subscription_id)
+);
+
+INSERT INTO list_memberships (list_id, subscription_id, status)
+SELECT l.list_id, s.id, s.status
+FROM subscriptions s, lists l
+WHERE l.slug = 'newsletter';
+
+CREATE TABLE newsletter_issue_lists (
+    newsletter_issue_id uuid NOT NULL
+        REFERENCES newsletter_issues (newsletter_issue_id),
+    list_id uuid NOT NULL REFERENCES lists (list_id),
+    PRIMARY KEY (newsletter_issue_id, list_id)
+);
+
+INSERT INTO newsletter_issue_lists (newsletter_issue_id, list_id)
+SELECT i.newsletter_issue_id, l.list_id
+FROM newsletter_issues i, lists l
+WHERE l.slug = 'newsletter' AND i.status <> 'draft';
@@ -0,0 +1,9 @@
+-- Add migration script here
+ALTER TABLE subscriptions
+    ADD COLUMN tags TEXT[] NOT NULL DEFAULT '{}',
+    ADD COLUMN attributes JSONB NOT NULL DEFAULT '{}';
+
+CREATE INDEX subscriptions_tags_idx ON subscriptions USING GIN (tags);
+
+-- The segment an issue was sent to, if narrower than its lists
+ALTER TABLE newsletter_issues ADD COLUMN segment JSONB NULL;
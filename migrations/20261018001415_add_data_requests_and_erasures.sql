@@ -0,0 +1,13 @@
+-- Add migration script here
+CREATE TABLE data_request_tokens (
+    data_request_token TEXT PRIMARY KEY,
+    subscription_id uuid NOT NULL REFERENCES subscriptions (id),
+    created_at timestamptz NOT NULL DEFAULT now()
+);
+
+-- Nothing that could identify the subscriber is kept: the id only matches
+-- what was logged or exported before the erasure.
+CREATE TABLE subscriber_erasures (
+    subscription_id uuid PRIMARY KEY,
+    erased_at timestamptz NOT NULL DEFAULT now()
+);
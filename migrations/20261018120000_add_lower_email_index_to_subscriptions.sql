@@ -0,0 +1,4 @@
+-- Add migration script here
+-- Addresses are stored as typed but matched regardless of case: one
+-- subscriber per address, and an index for the lookups.
+CREATE UNIQUE INDEX subscriptions_lower_email_key ON subscriptions (lower(email));
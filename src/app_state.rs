@@ -1,9 +1,15 @@
+use std::time::Duration;
+
 use sqlx::{Pool, Postgres};
 
+use crate::configuration::WebhookSettings;
 use crate::email_client::EmailClient;
 
 pub struct AppState {
     pub pool: Pool<Postgres>,
     pub email_client: EmailClient,
     pub base_url: String,
+    pub subscription_token_ttl: Duration,
+    pub data_request_token_ttl: Duration,
+    pub webhook_credentials: Option<WebhookSettings>,
 }
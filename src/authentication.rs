@@ -1,6 +1,8 @@
+mod basic;
 mod middleware;
 mod password;
 
+pub use basic::basic_authentication;
 pub use middleware::*;
 pub use password::{
     AuthError, Credentials, change_password, validate_credentials,
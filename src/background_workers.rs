@@ -1,2 +1,10 @@
+pub mod email_outbox_worker;
 pub mod idempotency_expire_wroker;
 pub mod issue_delivery_worker;
+pub mod subscription_token_expire_worker;
+
+pub enum ExecutionOutput {
+    TaskCompleted,
+    NoAvaliableTask,
+    RetryLater,
+}
@@ -0,0 +1,253 @@
+use crate::background_workers::ExecutionOutput;
+use crate::configuration::{BackoffPolicy, Settings};
+use crate::domain::subscriber_email::SubscriberEmail;
+use crate::email_client::EmailClient;
+use sqlx::{PgPool, Postgres, Transaction};
+use time::{Duration, OffsetDateTime};
+use tracing::{Span, field::display, instrument};
+use uuid::Uuid;
+
+pub async fn run_outbox_worker_until_stop(
+    settings: Settings,
+) -> Result<(), anyhow::Error> {
+    let db_url = settings.database.get_connection();
+    let pool = PgPool::connect_lazy(&db_url)
+        .expect("Failed to connect to the database");
+
+    let email_client = settings.email_client.client();
+
+    work_loop(pool, email_client).await
+}
+
+async fn work_loop(
+    pool: PgPool,
+    email_client: EmailClient,
+) -> Result<(), anyhow::Error> {
+    let mut consecutive_errors = 0;
+    loop {
+        match try_send_outbox_email(&pool, &email_client).await {
+            Ok(ExecutionOutput::NoAvaliableTask) => {
+                tokio::time::sleep(Duration::seconds(1).try_into().unwrap())
+                    .await
+            }
+            Err(_) => {
+                let delay = email_client
+                    .retry_policy
+                    .delay(consecutive_errors, &mut rand::rng());
+                consecutive_errors = consecutive_errors.saturating_add(1);
+                tokio::time::sleep(delay).await;
+                continue;
+            }
+            Ok(ExecutionOutput::TaskCompleted) => {}
+            Ok(ExecutionOutput::RetryLater) => {}
+        }
+        consecutive_errors = 0;
+    }
+}
+
+/// Stores an email in the outbox as part of the caller's transaction.
+/// It is sent by the outbox worker once the transaction commits, so the
+/// caller never waits on the email provider.
+#[instrument(skip_all)]
+pub async fn enqueue_email(
+    tx: &mut Transaction<'_, Postgres>,
+    recipient: &SubscriberEmail,
+    subject: &str,
+    text_body: &str,
+    html_body: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO email_outbox (
+            email_id,
+            recipient,
+            subject,
+            text_body,
+            html_body
+        )
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        Uuid::new_v4(),
+        recipient.as_ref(),
+        subject,
+        text_body,
+        html_body,
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+#[instrument(
+    skip_all,
+    fields(
+        email_id = tracing::field::Empty,
+        recipient = tracing::field::Empty,
+    ),
+)]
+pub async fn try_send_outbox_email(
+    pool: &PgPool,
+    email_client: &EmailClient,
+) -> Result<ExecutionOutput, anyhow::Error> {
+    let Some((tx, email)) = dequeue_email(pool).await? else {
+        return Ok(ExecutionOutput::NoAvaliableTask);
+    };
+
+    Span::current()
+        .record("email_id", display(&email.email_id))
+        .record("recipient", display(&email.recipient));
+
+    match email.recipient.parse::<SubscriberEmail>() {
+        Ok(recipient) => {
+            let re = email_client
+                .send_email(
+                    &recipient,
+                    &email.subject,
+                    &email.text_body,
+                    &email.html_body,
+                    None,
+                )
+                .await;
+
+            if let Err(e) = re {
+                if e.is_permanent() {
+                    tracing::warn!(
+                        error.cause_chain = ?e,
+                        error.message = %e,
+                        "The email provider permanently rejected an outbox \
+                        email. Cancel delivery",
+                    );
+                } else if email_client
+                    .retry_policy
+                    .is_exhausted(email.failed_attempts())
+                {
+                    tracing::warn!(
+                        error.cause_chain = ?e,
+                        error.message = %e,
+                        "Failed to send an outbox email. \
+                        Exceed max retry times. Cancel delivery",
+                    );
+                } else {
+                    tracing::warn!(
+                        error.cause_chain = ?e,
+                        error.message = %e,
+                        "Failed to send an outbox email. Retrying later.",
+                    );
+                    schedule_next_retry(tx, &email, &email_client.retry_policy)
+                        .await?;
+
+                    return Ok(ExecutionOutput::RetryLater);
+                }
+            }
+        }
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain=?e,
+                error.message=%e,
+                "Skipping an outbox email. The recipient is invalid",
+            )
+        }
+    }
+
+    delete_email(tx, email).await?;
+
+    Ok(ExecutionOutput::TaskCompleted)
+}
+
+struct OutboxEmail {
+    email_id: Uuid,
+    recipient: String,
+    subject: String,
+    text_body: String,
+    html_body: String,
+    n_retries: i16,
+}
+
+impl OutboxEmail {
+    /// Including the attempt that just failed.
+    fn failed_attempts(&self) -> u32 {
+        u32::try_from(self.n_retries).unwrap_or(0) + 1
+    }
+}
+
+#[instrument(skip_all)]
+async fn dequeue_email(
+    pool: &PgPool,
+) -> Result<Option<(Transaction<'static, Postgres>, OutboxEmail)>, anyhow::Error>
+{
+    let mut tx = pool.begin().await?;
+
+    let record = sqlx::query_as!(
+        OutboxEmail,
+        r#"
+        SELECT
+            email_id,
+            recipient,
+            subject,
+            text_body,
+            html_body,
+            n_retries
+        FROM email_outbox
+        WHERE execute_after < NOW()
+        ORDER BY created_at
+        FOR UPDATE SKIP LOCKED
+        LIMIT 1
+        "#
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    Ok(record.map(|r| (tx, r)))
+}
+
+#[instrument(skip_all)]
+async fn schedule_next_retry(
+    mut tx: Transaction<'static, Postgres>,
+    email: &OutboxEmail,
+    retry_policy: &BackoffPolicy,
+) -> Result<(), anyhow::Error> {
+    let delay = retry_policy.delay(
+        u32::try_from(email.n_retries).unwrap_or(0),
+        &mut rand::rng(),
+    );
+    let next_delivery = OffsetDateTime::now_utc() + delay;
+    sqlx::query!(
+        r#"
+        UPDATE email_outbox
+        SET
+            n_retries = $1,
+            execute_after = $2
+        WHERE email_id = $3
+        "#,
+        email.n_retries + 1,
+        next_delivery,
+        email.email_id,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+#[instrument(skip_all)]
+async fn delete_email(
+    mut tx: Transaction<'static, Postgres>,
+    email: OutboxEmail,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM email_outbox
+        WHERE email_id = $1
+        "#,
+        email.email_id,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
@@ -1,9 +1,22 @@
-use crate::{configuration::Settings, email_client::EmailClient};
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+
+use crate::background_workers::ExecutionOutput;
+use crate::configuration::{BackoffPolicy, Settings};
+use crate::email_client::{Email, EmailClient, SendEmailError};
+use anyhow::Context;
+use reqwest::Url;
 use sqlx::{PgPool, Postgres, Transaction};
 use time::{Duration, OffsetDateTime};
-use tracing::{Span, field::display, instrument};
+use tracing::{Span, instrument};
 
-use crate::domain::subscriber_email::SubscriberEmail;
+use crate::domain::{
+    newsletter_html::append_to_body,
+    newsletter_issue::{IssueStatus, web_version_url},
+    newsletter_template::{self, TemplateContext},
+    subscriber::SubscriberStatus,
+    subscriber_email::SubscriberEmail,
+};
 
 pub async fn run_worker_until_stop(
     settings: Settings,
@@ -14,159 +27,304 @@ pub async fn run_worker_until_stop(
 
     let email_client = settings.email_client.client();
 
-    work_loop(pool, email_client).await
+    work_loop(pool, email_client, settings.app_settings.base_url).await
 }
 
 async fn work_loop(
     pool: PgPool,
     email_client: EmailClient,
+    base_url: String,
 ) -> Result<(), anyhow::Error> {
+    let mut consecutive_errors = 0;
     loop {
-        match try_execute_task(&pool, &email_client).await {
+        match try_execute_task(&pool, &email_client, &base_url).await {
             Ok(ExecutionOutput::NoAvaliableTask) => {
                 tokio::time::sleep(Duration::seconds(10).try_into().unwrap())
                     .await
             }
             Err(_) => {
-                // Improvement:
-                // https://aws.amazon.com/blogs/architecture/exponential-backoff-and-jitter/
-                tokio::time::sleep(Duration::seconds(2).try_into().unwrap())
-                    .await
+                let delay = email_client
+                    .retry_policy
+                    .delay(consecutive_errors, &mut rand::rng());
+                consecutive_errors = consecutive_errors.saturating_add(1);
+                tokio::time::sleep(delay).await;
+                continue;
             }
             Ok(ExecutionOutput::TaskCompleted) => {}
             Ok(ExecutionOutput::RetryLater) => {}
         }
+        consecutive_errors = 0;
     }
 }
 
-pub enum ExecutionOutput {
-    TaskCompleted,
-    NoAvaliableTask,
-    RetryLater,
-}
-
-#[instrument(
-    skip_all,
-    fields(
-        issue_id = tracing::field::Empty,
-        subscriber_email = tracing::field::Empty,
-    ),
-)]
+#[instrument(skip_all, fields(n_tasks = tracing::field::Empty))]
 pub async fn try_execute_task(
     pool: &PgPool,
     email_client: &EmailClient,
+    base_url: &str,
 ) -> Result<ExecutionOutput, anyhow::Error> {
-    if let Some((tx, task)) = dequeue_task(pool).await? {
-        Span::current()
-            .record("issue_id", display(&task.newsletter_issue_id))
-            .record("subscriber_email", display(&task.subscriber_email));
-
-        match task.subscriber_email.parse::<SubscriberEmail>() {
-            Ok(subscriber_email) => {
-                let (title, text_content, html_content) =
-                    get_issue(pool, &task.newsletter_issue_id).await?;
-                let re = email_client
-                    .send_email(
-                        &subscriber_email,
-                        &title,
-                        &text_content,
-                        &html_content,
-                    )
-                    .await;
-
-                if let Err(e) = re {
-                    if u16::try_from(task.n_retries).unwrap() + 1
-                        >= email_client.retries_limit
-                    {
-                        tracing::warn!(
-                            error.cause_chain = ?e,
-                            error.message = %e,
-                            "Failed to deliver issue to a confirmed subscriber. \
-                            Exceed max retry times. Cancel delivery",
-                        );
-                    } else {
-                        tracing::warn!(
+    let Some((mut tx, tasks)) =
+        dequeue_tasks(pool, email_client.batch_size).await?
+    else {
+        return Ok(ExecutionOutput::NoAvaliableTask);
+    };
+    Span::current().record("n_tasks", tasks.len());
+
+    let recipients: Vec<String> =
+        tasks.iter().map(|t| t.subscriber_email.clone()).collect();
+    let recipients = get_recipients(pool, &recipients).await?;
+
+    let mut issues = HashMap::new();
+    let mut deliveries = vec![];
+    let mut sent = vec![];
+    let mut skipped = vec![];
+    let mut failed = vec![];
+    for task in tasks {
+        match (
+            task.subscriber_email.parse::<SubscriberEmail>(),
+            recipients.get(&task.subscriber_email),
+        ) {
+            (Ok(_), None) => {
+                tracing::info!(
+                    issue_id = %task.newsletter_issue_id,
+                    subscriber_email = %task.subscriber_email,
+                    "Skipping a subscriber who is no longer deliverable \
+                    since the issue was published",
+                );
+                skipped.push(task);
+            }
+            (Ok(subscriber_email), Some(recipient)) => {
+                let issue = match issues.entry(task.newsletter_issue_id) {
+                    Entry::Occupied(entry) => entry.into_mut(),
+                    Entry::Vacant(entry) => entry.insert(
+                        get_issue(pool, &task.newsletter_issue_id).await?,
+                    ),
+                };
+                match issue.personalize(base_url, recipient)? {
+                    Ok(content) => {
+                        deliveries.push((task, subscriber_email, content))
+                    }
+                    Err(e) => {
+                        tracing::error!(
+                            issue_id = %task.newsletter_issue_id,
+                            subscriber_email = %task.subscriber_email,
                             error.cause_chain = ?e,
                             error.message = %e,
-                            "Failed to deliver issue to a confirmed subscriber. \
-                            Retrying later.",
+                            "Failed to render the issue for a subscriber",
                         );
-                        schedule_next_retry(
-                            tx,
-                            &task,
-                            email_client.retry_wait_seconds,
-                        )
-                        .await?;
-
-                        return Ok(ExecutionOutput::RetryLater);
+                        failed.push((
+                            task,
+                            format!("{:#}", anyhow::Error::from(e)),
+                        ));
                     }
                 }
             }
-            Err(e) => {
+            (Err(e), _) => {
                 tracing::error!(
+                    issue_id = %task.newsletter_issue_id,
+                    subscriber_email = %task.subscriber_email,
                     error.cause_chain=?e,
                     error.message=%e,
                     "Skipping a confirmed subscriber. \
                     Their stored contact details are invalid",
-                )
+                );
+                failed.push((task, format!("Invalid subscriber email: {e}")));
             }
         }
+    }
+
+    let results = send_deliveries(email_client, &issues, &deliveries).await;
+
+    let mut retry_later = false;
+    for ((task, _, _), result) in deliveries.into_iter().zip(results) {
+        let Err(e) = result else {
+            sent.push(task);
+            continue;
+        };
+
+        if e.is_permanent() {
+            tracing::warn!(
+                issue_id = %task.newsletter_issue_id,
+                subscriber_email = %task.subscriber_email,
+                error.cause_chain = ?e,
+                error.message = %e,
+                "The email provider permanently rejected the issue for a \
+                confirmed subscriber. Moving it to the failures",
+            );
+            failed.push((task, error_message(e)));
+        } else if email_client
+            .retry_policy
+            .is_exhausted(task.failed_attempts())
+        {
+            tracing::warn!(
+                issue_id = %task.newsletter_issue_id,
+                subscriber_email = %task.subscriber_email,
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to deliver issue to a confirmed subscriber. \
+                Exceed max retry times. Moving it to the failures",
+            );
+            failed.push((task, error_message(e)));
+        } else {
+            tracing::warn!(
+                issue_id = %task.newsletter_issue_id,
+                subscriber_email = %task.subscriber_email,
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to deliver issue to a confirmed subscriber. \
+                Retrying later.",
+            );
+            schedule_next_retry(&mut tx, &task, &email_client.retry_policy)
+                .await?;
+            retry_later = true;
+        }
+    }
+
+    let mut counts: HashMap<uuid::Uuid, DeliveryCounts> = HashMap::new();
+    for task in &sent {
+        counts.entry(task.newsletter_issue_id).or_default().sent += 1;
+    }
+    for task in &skipped {
+        counts.entry(task.newsletter_issue_id).or_default().skipped += 1;
+    }
+    for (task, _) in &failed {
+        counts.entry(task.newsletter_issue_id).or_default().failed += 1;
+    }
 
-        delete_task(tx, task).await?;
+    record_failures(&mut tx, &failed).await?;
+    let finished: Vec<_> = sent
+        .into_iter()
+        .chain(skipped)
+        .chain(failed.into_iter().map(|(task, _)| task))
+        .collect();
+    delete_tasks(&mut tx, &finished).await?;
+    for (issue_id, counts) in counts {
+        record_delivery_counts(&mut tx, issue_id, &counts).await?;
+    }
+    tx.commit().await?;
+
+    if retry_later {
+        Ok(ExecutionOutput::RetryLater)
     } else {
-        return Ok(ExecutionOutput::NoAvaliableTask);
+        Ok(ExecutionOutput::TaskCompleted)
     }
+}
 
-    Ok(ExecutionOutput::TaskCompleted)
+/// Sends the whole batch in one call and returns one result per delivery.
+/// If the batch itself fails every delivery is reported as failed.
+async fn send_deliveries(
+    email_client: &EmailClient,
+    issues: &HashMap<uuid::Uuid, Issue>,
+    deliveries: &[(DeliveryTask, SubscriberEmail, PersonalizedContent)],
+) -> Vec<Result<(), SendEmailError>> {
+    if deliveries.is_empty() {
+        return vec![];
+    }
+
+    let emails: Vec<Email> = deliveries
+        .iter()
+        .map(|(task, recipient, content)| Email {
+            recipient,
+            subject: &issues[&task.newsletter_issue_id].title,
+            text_body: &content.text_content,
+            html_body: &content.html_content,
+            list_unsubscribe_url: Some(content.unsubscribe_url.as_str()),
+        })
+        .collect();
+
+    match email_client.send_email_batch(&emails).await {
+        Ok(results) => results,
+        Err(e) => {
+            tracing::warn!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to send a batch of issue deliveries",
+            );
+            let permanent = e.is_permanent();
+            let reason = error_message(e);
+            deliveries
+                .iter()
+                .map(|_| {
+                    let e = anyhow::anyhow!("The whole batch failed: {reason}");
+                    if permanent {
+                        Err(SendEmailError::Permanent(e))
+                    } else {
+                        Err(SendEmailError::Transient(e))
+                    }
+                })
+                .collect()
+        }
+    }
+}
+
+/// The error with all its causes on one line, as stored with a failure.
+fn error_message(e: SendEmailError) -> String {
+    format!("{:#}", anyhow::Error::from(e))
 }
 
 struct DeliveryTask {
     newsletter_issue_id: uuid::Uuid,
     subscriber_email: String,
     n_retries: i16,
-    execute_after: OffsetDateTime,
+    enqueued_at: OffsetDateTime,
 }
 
+impl DeliveryTask {
+    /// Including the attempt that just failed.
+    fn failed_attempts(&self) -> u32 {
+        u32::try_from(self.n_retries).unwrap_or(0) + 1
+    }
+}
+
+/// The dequeued rows stay locked until the transaction is committed.
+type DequeuedTasks = (Transaction<'static, Postgres>, Vec<DeliveryTask>);
+
 #[instrument(skip_all)]
-async fn dequeue_task(
+async fn dequeue_tasks(
     pool: &PgPool,
-) -> Result<Option<(Transaction<'static, Postgres>, DeliveryTask)>, anyhow::Error>
-{
+    batch_size: u16,
+) -> Result<Option<DequeuedTasks>, anyhow::Error> {
     let mut tx = pool.begin().await?;
 
     // KEY: FOR UPDATE SKIP LOCKED
-    let record = sqlx::query_as!(
+    // Paused issues keep their tasks in the queue until resumed
+    let records = sqlx::query_as!(
         DeliveryTask,
         r#"
         SELECT
-            newsletter_issue_id,
-            subscriber_email,
-            n_retries,
-            execute_after
-        FROM issue_delivery_queue
-        WHERE execute_after < NOW()
-        FOR UPDATE SKIP LOCKED
-        LIMIT 1
-        "#
+            q.newsletter_issue_id,
+            q.subscriber_email,
+            q.n_retries,
+            q.enqueued_at
+        FROM issue_delivery_queue q
+        JOIN newsletter_issues i
+            ON i.newsletter_issue_id = q.newsletter_issue_id
+        WHERE q.execute_after < NOW() AND i.status = ANY($2)
+        FOR UPDATE OF q SKIP LOCKED
+        LIMIT $1
+        "#,
+        i64::from(batch_size),
+        &IssueStatus::to_strings(IssueStatus::SENDING),
     )
-    .fetch_optional(&mut *tx)
+    .fetch_all(&mut *tx)
     .await?;
 
-    if let Some(r) = record {
-        return Ok(Some((tx, r)));
+    if records.is_empty() {
+        return Ok(None);
     }
 
-    Ok(None)
+    Ok(Some((tx, records)))
 }
 
 #[instrument(skip_all)]
 async fn schedule_next_retry(
-    mut tx: Transaction<'static, Postgres>,
+    tx: &mut Transaction<'static, Postgres>,
     task: &DeliveryTask,
-    retry_wait_seconds: u16,
+    retry_policy: &BackoffPolicy,
 ) -> Result<(), anyhow::Error> {
-    let next_delivery =
-        task.execute_after + Duration::seconds(retry_wait_seconds.into());
+    let delay = retry_policy
+        .delay(u32::try_from(task.n_retries).unwrap_or(0), &mut rand::rng());
+    let next_delivery = OffsetDateTime::now_utc() + delay;
     sqlx::query!(
         r#"
         UPDATE issue_delivery_queue
@@ -182,47 +340,273 @@ async fn schedule_next_retry(
         task.newsletter_issue_id,
         task.subscriber_email,
     )
-    .execute(&mut *tx)
+    .execute(&mut **tx)
     .await?;
 
-    let _ = tx.commit().await;
+    Ok(())
+}
+
+/// How the deliveries of one issue in a batch ended.
+#[derive(Default)]
+struct DeliveryCounts {
+    sent: i32,
+    failed: i32,
+    skipped: i32,
+}
+
+/// Adds the outcome of a batch to the issue's running totals. A scheduled
+/// issue counts as published once its first batch goes out.
+#[instrument(skip(tx, counts))]
+async fn record_delivery_counts(
+    tx: &mut Transaction<'static, Postgres>,
+    issue_id: uuid::Uuid,
+    counts: &DeliveryCounts,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET
+            n_sent = n_sent + $2,
+            n_failed = n_failed + $3,
+            n_skipped = n_skipped + $4,
+            first_sent_at = CASE
+                WHEN $2 > 0 THEN COALESCE(first_sent_at, NOW())
+                ELSE first_sent_at
+            END,
+            last_sent_at = CASE
+                WHEN $2 > 0 THEN NOW()
+                ELSE last_sent_at
+            END,
+            status = CASE
+                WHEN status = $5 THEN $6
+                ELSE status
+            END
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id,
+        counts.sent,
+        counts.failed,
+        counts.skipped,
+        IssueStatus::Scheduled.to_string(),
+        IssueStatus::Published.to_string(),
+    )
+    .execute(&mut **tx)
+    .await?;
 
     Ok(())
 }
 
+/// Keeps a record of deliveries that will not be attempted again, so they
+/// can be inspected and re-enqueued from the admin API.
 #[instrument(skip_all)]
-async fn delete_task(
-    mut tx: Transaction<'static, Postgres>,
-    task: DeliveryTask,
+async fn record_failures(
+    tx: &mut Transaction<'static, Postgres>,
+    failures: &[(DeliveryTask, String)],
 ) -> Result<(), anyhow::Error> {
+    if failures.is_empty() {
+        return Ok(());
+    }
+
+    let mut issue_ids = Vec::with_capacity(failures.len());
+    let mut subscriber_emails = Vec::with_capacity(failures.len());
+    let mut last_errors = Vec::with_capacity(failures.len());
+    let mut n_attempts = Vec::with_capacity(failures.len());
+    let mut enqueued_ats = Vec::with_capacity(failures.len());
+    for (task, last_error) in failures {
+        issue_ids.push(task.newsletter_issue_id);
+        subscriber_emails.push(task.subscriber_email.clone());
+        last_errors.push(last_error.clone());
+        n_attempts.push(task.n_retries.saturating_add(1));
+        enqueued_ats.push(task.enqueued_at);
+    }
+
     sqlx::query!(
         r#"
-        DELETE FROM issue_delivery_queue
-        WHERE
-            newsletter_issue_id = $1 AND
-            subscriber_email = $2
+        INSERT INTO issue_delivery_failures (
+            newsletter_issue_id,
+            subscriber_email,
+            last_error,
+            n_attempts,
+            enqueued_at
+        )
+        SELECT * FROM UNNEST(
+            $1::uuid[],
+            $2::text[],
+            $3::text[],
+            $4::smallint[],
+            $5::timestamptz[]
+        )
+        ON CONFLICT (newsletter_issue_id, subscriber_email) DO UPDATE
+        SET
+            last_error = EXCLUDED.last_error,
+            n_attempts = EXCLUDED.n_attempts,
+            enqueued_at = EXCLUDED.enqueued_at,
+            failed_at = NOW()
         "#,
-        task.newsletter_issue_id,
-        task.subscriber_email,
+        &issue_ids,
+        &subscriber_emails,
+        &last_errors,
+        &n_attempts,
+        &enqueued_ats,
     )
-    .execute(&mut *tx)
+    .execute(&mut **tx)
     .await?;
 
-    tx.commit().await?;
+    Ok(())
+}
+
+#[instrument(skip_all)]
+async fn delete_tasks(
+    tx: &mut Transaction<'static, Postgres>,
+    tasks: &[DeliveryTask],
+) -> Result<(), anyhow::Error> {
+    if tasks.is_empty() {
+        return Ok(());
+    }
+
+    let (issue_ids, subscriber_emails): (Vec<_>, Vec<_>) = tasks
+        .iter()
+        .map(|t| (t.newsletter_issue_id, t.subscriber_email.clone()))
+        .unzip();
+    sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_queue
+        WHERE (newsletter_issue_id, subscriber_email) IN (
+            SELECT * FROM UNNEST($1::uuid[], $2::text[])
+        )
+        "#,
+        &issue_ids,
+        &subscriber_emails,
+    )
+    .execute(&mut **tx)
+    .await?;
 
     Ok(())
 }
 
+struct Recipient {
+    name: String,
+    unsubscribe_token: String,
+}
+
+/// The deliverable subscribers among `subscriber_emails`, by email.
+#[instrument(skip_all)]
+async fn get_recipients(
+    pool: &PgPool,
+    subscriber_emails: &[String],
+) -> Result<HashMap<String, Recipient>, anyhow::Error> {
+    let records = sqlx::query!(
+        r#"
+        SELECT email, name, unsubscribe_token
+        FROM subscriptions
+        WHERE email = ANY($1) AND status = ANY($2)
+        "#,
+        subscriber_emails,
+        &SubscriberStatus::to_strings(SubscriberStatus::DELIVERABLE),
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(records
+        .into_iter()
+        .map(|r| {
+            let recipient = Recipient {
+                name: r.name,
+                unsubscribe_token: r.unsubscribe_token,
+            };
+            (r.email, recipient)
+        })
+        .collect())
+}
+
+fn unsubscribe_url(
+    base_url: &str,
+    unsubscribe_token: &str,
+) -> Result<Url, anyhow::Error> {
+    let mut url = Url::parse(base_url).context("Invalid base url")?;
+    url.set_path("/subscriptions/unsubscribe");
+    url.query_pairs_mut()
+        .append_pair("token", unsubscribe_token);
+
+    Ok(url)
+}
+
+struct Issue {
+    newsletter_issue_id: uuid::Uuid,
+    title: String,
+    text_content: String,
+    html_content: String,
+}
+
+struct PersonalizedContent {
+    text_content: String,
+    html_content: String,
+    unsubscribe_url: Url,
+}
+
+impl Issue {
+    /// Renders both bodies for the recipient, then adds the web version
+    /// link and their own unsubscribe link. A template that fails to render
+    /// is only a problem for this delivery, hence the nested result.
+    fn personalize(
+        &self,
+        base_url: &str,
+        recipient: &Recipient,
+    ) -> Result<
+        Result<PersonalizedContent, newsletter_template::TemplateError>,
+        anyhow::Error,
+    > {
+        let web_version_url =
+            web_version_url(base_url, self.newsletter_issue_id)?;
+        let unsubscribe_url =
+            unsubscribe_url(base_url, &recipient.unsubscribe_token)?;
+        let context = TemplateContext {
+            name: &recipient.name,
+            unsubscribe_url: unsubscribe_url.as_str(),
+            view_online_url: web_version_url.as_str(),
+        };
+        let content = match newsletter_template::render(
+            &self.text_content,
+            &self.html_content,
+            &context,
+        ) {
+            Ok(content) => content,
+            Err(e) => return Ok(Err(e)),
+        };
+
+        let text_content = format!(
+            "{}\n\nView in browser: {web_version_url}\n\
+            Unsubscribe: {unsubscribe_url}",
+            content.text
+        );
+        let html_content = append_to_body(
+            &content.html,
+            &format!(
+                "<p><a href=\"{web_version_url}\">View in browser</a> | \
+                <a href=\"{unsubscribe_url}\">Unsubscribe</a></p>",
+            ),
+        );
+
+        Ok(Ok(PersonalizedContent {
+            text_content,
+            html_content,
+            unsubscribe_url,
+        }))
+    }
+}
+
 #[instrument(skip_all)]
 async fn get_issue(
     // some read-only queries should use the pool instead of the transaction
     // to avoid deadlocks and improve performance
     pool: &PgPool,
     issue_id: &uuid::Uuid,
-) -> Result<(String, String, String), anyhow::Error> {
-    let record = sqlx::query!(
+) -> Result<Issue, anyhow::Error> {
+    let issue = sqlx::query_as!(
+        Issue,
         r#"
         SELECT
+            newsletter_issue_id,
             title,
             text_content,
             html_content
@@ -234,5 +618,5 @@ async fn get_issue(
     .fetch_one(pool)
     .await?;
 
-    Ok((record.title, record.text_content, record.html_content))
+    Ok(issue)
 }
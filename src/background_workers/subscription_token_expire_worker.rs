@@ -0,0 +1,105 @@
+use std::time::Duration;
+
+use crate::configuration::Settings;
+use sqlx::PgPool;
+use time::OffsetDateTime;
+use tracing::instrument;
+
+pub async fn run_subscription_token_clean_worker_until_stop(
+    settings: Settings,
+) -> Result<(), anyhow::Error> {
+    let db_url = settings.database.get_connection();
+    let pool = PgPool::connect_lazy(&db_url)
+        .expect("Failed to connect to the database");
+
+    work_loop(
+        pool,
+        settings.app_settings.subscription_token_ttl,
+        settings.app_settings.data_request_token_ttl,
+    )
+    .await
+}
+
+#[instrument(skip_all)]
+async fn work_loop(
+    pool: PgPool,
+    ttl: Duration,
+    data_request_ttl: Duration,
+) -> Result<(), anyhow::Error> {
+    loop {
+        match try_clean_expired_data_request_tokens(&pool, &data_request_ttl)
+            .await
+        {
+            Err(e) => {
+                tracing::error!(
+                    error.cause_chain=?e,
+                    error.message=%e,
+                    "failed to clean expired data request tokens"
+                )
+            }
+            Ok(deleted_rows) => tracing::info!(
+                "{} expired data request tokens are cleaned",
+                deleted_rows,
+            ),
+        }
+        match try_clean_expired_subscription_tokens(&pool, &ttl).await {
+            Err(e) => {
+                tracing::error!(
+                    error.cause_chain=?e,
+                    error.message=%e,
+                    "failed to clean expired subscription tokens"
+                )
+            }
+            Ok(deleted_rows) => tracing::info!(
+                "{} expired subscription tokens are cleaned",
+                deleted_rows,
+            ),
+        }
+
+        tokio::time::sleep(std::time::Duration::from_secs(60)).await;
+    }
+}
+
+/// Used tokens are kept until they expire as well, so that replaying a
+/// confirmation link keeps being reported as such rather than as unknown.
+#[instrument(skip_all)]
+pub async fn try_clean_expired_subscription_tokens(
+    pool: &PgPool,
+    ttl: &Duration,
+) -> Result<u64, anyhow::Error> {
+    let expired_at = OffsetDateTime::now_utc() - *ttl;
+    let deleted_rows = sqlx::query!(
+        r#"
+        DELETE
+        FROM subscription_tokens
+        WHERE created_at < $1
+        "#,
+        expired_at
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+
+    Ok(deleted_rows)
+}
+
+#[instrument(skip_all)]
+pub async fn try_clean_expired_data_request_tokens(
+    pool: &PgPool,
+    ttl: &Duration,
+) -> Result<u64, anyhow::Error> {
+    let expired_at = OffsetDateTime::now_utc() - *ttl;
+    let deleted_rows = sqlx::query!(
+        r#"
+        DELETE
+        FROM data_request_tokens
+        WHERE created_at < $1
+        "#,
+        expired_at
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+
+    Ok(deleted_rows)
+}
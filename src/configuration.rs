@@ -1,13 +1,19 @@
+use std::path::PathBuf;
+use std::sync::Arc;
 use std::time::Duration;
 use std::{fmt, str::FromStr};
 
 use config::{Config, ConfigError, Environment, File};
+use rand::Rng;
 use serde::Deserialize;
 
 use secrecy::{ExposeSecret, SecretString};
 
 use crate::domain::subscriber_email::SubscriberEmail;
-use crate::email_client::EmailClient;
+use crate::email_client::{
+    EmailClient, EmailTransport, FileSinkTransport, PostmarkTransport,
+    SmtpTransport,
+};
 
 #[derive(Deserialize, Clone)]
 pub struct Settings {
@@ -27,6 +33,17 @@ pub struct AppSettings {
         deserialize_with = "secs_to_duration"
     )]
     pub idempotency_ttl: Duration,
+    #[serde(
+        default = "default_subscription_token_ttl",
+        deserialize_with = "secs_to_duration"
+    )]
+    pub subscription_token_ttl: Duration,
+    /// How long the link to download or erase a subscriber's data works
+    #[serde(
+        default = "default_data_request_token_ttl",
+        deserialize_with = "secs_to_duration"
+    )]
+    pub data_request_token_ttl: Duration,
 }
 
 fn secs_to_duration<'de, D>(deserializer: D) -> Result<Duration, D::Error>
@@ -41,6 +58,14 @@ fn default_idempotency_ttl() -> Duration {
     Duration::from_secs(120)
 }
 
+fn default_subscription_token_ttl() -> Duration {
+    Duration::from_secs(60 * 60 * 24)
+}
+
+fn default_data_request_token_ttl() -> Duration {
+    Duration::from_secs(60 * 60)
+}
+
 #[derive(Deserialize, Clone)]
 pub struct DBSettings {
     pub username: String,
@@ -52,27 +77,109 @@ pub struct DBSettings {
 
 #[derive(Deserialize, Clone)]
 pub struct EmailClientSettings {
+    #[serde(default)]
+    pub backend: EmailBackend,
     pub base_url: String,
     pub sender: SubscriberEmail,
     pub authorization_token: SecretString,
     #[serde(default = "default_timeout_milliseconds")]
     pub timeout_milliseconds: u64,
-    #[serde(default = "default_retries_limit")]
-    pub retries_limit: u16,
-    #[serde(default = "default_retry_wait_seconds")]
-    pub retry_wait_seconds: u16,
+    /// How failed deliveries and failing worker loops back off
+    #[serde(default)]
+    pub retry: BackoffPolicy,
+    /// How many queued deliveries the worker sends in one go
+    #[serde(default = "default_batch_size")]
+    pub batch_size: u16,
+    pub smtp: Option<SmtpSettings>,
+    pub file_sink: Option<FileSinkSettings>,
+    /// Basic auth credentials the provider uses to call our webhooks.
+    /// Webhooks reject every call when unset.
+    pub webhook: Option<WebhookSettings>,
+}
+
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum EmailBackend {
+    #[default]
+    Postmark,
+    Smtp,
+    FileSink,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct SmtpSettings {
+    pub host: String,
+    pub port: u16,
+    #[serde(default)]
+    pub tls: SmtpTls,
+    pub username: Option<String>,
+    pub password: Option<SecretString>,
+}
+
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SmtpTls {
+    /// Plain connection upgraded with STARTTLS, usually on port 587
+    #[default]
+    Starttls,
+    /// TLS from the first byte, usually on port 465
+    Tls,
+    /// No encryption at all, only for local relays
+    None,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct WebhookSettings {
+    pub username: String,
+    pub password: SecretString,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct FileSinkSettings {
+    pub directory: PathBuf,
 }
 
 impl EmailClientSettings {
     pub fn client(self) -> EmailClient {
-        EmailClient::new(
-            self.base_url,
-            self.sender,
-            self.authorization_token,
-            self.timeout_milliseconds,
-            self.retries_limit,
-            self.retry_wait_seconds,
-        )
+        let transport = self
+            .transport()
+            .expect("Failed to build the configured email transport");
+
+        EmailClient::new(transport, self.retry, self.batch_size)
+    }
+
+    fn transport(&self) -> Result<Arc<dyn EmailTransport>, anyhow::Error> {
+        let transport: Arc<dyn EmailTransport> = match self.backend {
+            EmailBackend::Postmark => Arc::new(PostmarkTransport::new(
+                self.base_url.clone(),
+                self.sender.clone(),
+                self.authorization_token.clone(),
+                self.timeout_milliseconds,
+            )),
+            EmailBackend::Smtp => {
+                let smtp = self.smtp.clone().ok_or_else(|| {
+                    anyhow::anyhow!("The smtp backend needs an `smtp` section")
+                })?;
+                Arc::new(SmtpTransport::new(
+                    smtp,
+                    self.sender.clone(),
+                    self.timeout_milliseconds,
+                )?)
+            }
+            EmailBackend::FileSink => {
+                let file_sink = self.file_sink.clone().ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "The file_sink backend needs a `file_sink` section"
+                    )
+                })?;
+                Arc::new(FileSinkTransport::new(
+                    file_sink.directory,
+                    self.sender.clone(),
+                )?)
+            }
+        };
+
+        Ok(transport)
     }
 }
 
@@ -80,12 +187,79 @@ fn default_timeout_milliseconds() -> u64 {
     10_000
 }
 
-fn default_retries_limit() -> u16 {
+/// Exponential backoff with full jitter, see
+/// https://aws.amazon.com/blogs/architecture/exponential-backoff-and-jitter/
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct BackoffPolicy {
+    #[serde(
+        default = "default_backoff_base",
+        deserialize_with = "secs_to_duration"
+    )]
+    pub base: Duration,
+    #[serde(
+        default = "default_backoff_cap",
+        deserialize_with = "secs_to_duration"
+    )]
+    pub cap: Duration,
+    #[serde(default = "default_backoff_multiplier")]
+    pub multiplier: f64,
+    #[serde(default = "default_backoff_max_attempts")]
+    pub max_attempts: u16,
+}
+
+impl Default for BackoffPolicy {
+    fn default() -> Self {
+        Self {
+            base: default_backoff_base(),
+            cap: default_backoff_cap(),
+            multiplier: default_backoff_multiplier(),
+            max_attempts: default_backoff_max_attempts(),
+        }
+    }
+}
+
+impl BackoffPolicy {
+    /// The longest wait after `attempt` failures (zero-based), i.e.
+    /// `min(cap, base * multiplier ^ attempt)`.
+    pub fn ceiling(&self, attempt: u32) -> Duration {
+        let exponent = i32::try_from(attempt).unwrap_or(i32::MAX);
+        let secs = self.base.as_secs_f64() * self.multiplier.powi(exponent);
+
+        Duration::from_secs_f64(secs.min(self.cap.as_secs_f64()))
+    }
+
+    /// A random wait between zero and the ceiling for `attempt`.
+    pub fn delay(&self, attempt: u32, rng: &mut impl Rng) -> Duration {
+        let ceiling = self.ceiling(attempt).as_secs_f64();
+
+        Duration::from_secs_f64(rng.random_range(0.0..=ceiling))
+    }
+
+    /// Whether a task that has failed `failed_attempts` times should be
+    /// given up on.
+    pub fn is_exhausted(&self, failed_attempts: u32) -> bool {
+        failed_attempts >= u32::from(self.max_attempts)
+    }
+}
+
+fn default_backoff_base() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_backoff_cap() -> Duration {
+    Duration::from_secs(60 * 60)
+}
+
+fn default_backoff_multiplier() -> f64 {
+    3.0
+}
+
+fn default_backoff_max_attempts() -> u16 {
     5
 }
 
-fn default_retry_wait_seconds() -> u16 {
-    60
+fn default_batch_size() -> u16 {
+    100
 }
 
 enum RunningEnv {
@@ -202,10 +376,25 @@ mod tests {
             settings.email_client.timeout_milliseconds, 10_000,
             "Failed to load local configuration"
         );
+        assert_eq!(
+            settings.email_client.backend,
+            EmailBackend::FileSink,
+            "Failed to load local email backend"
+        );
         assert_eq!(
             settings.app_settings.idempotency_ttl,
             Duration::from_secs(120),
             "Failed to load idempotency ttl"
+        );
+        assert_eq!(
+            settings.app_settings.subscription_token_ttl,
+            Duration::from_secs(86_400),
+            "Failed to load subscription token ttl"
+        );
+        assert_eq!(
+            settings.app_settings.data_request_token_ttl,
+            Duration::from_secs(3_600),
+            "Failed to load data request token ttl"
         )
     }
 
@@ -222,6 +411,11 @@ mod tests {
             [0, 0, 0, 0],
             "Failed to load production configuration"
         );
+        assert_eq!(
+            settings.email_client.backend,
+            EmailBackend::Postmark,
+            "Failed to load production email backend"
+        );
     }
 
     #[test]
@@ -244,4 +438,70 @@ mod tests {
             "Failed to load env configuration"
         );
     }
+
+    fn backoff_policy() -> BackoffPolicy {
+        BackoffPolicy {
+            base: Duration::from_secs(1),
+            cap: Duration::from_secs(10),
+            multiplier: 2.0,
+            max_attempts: 3,
+        }
+    }
+
+    #[test]
+    fn backoff_ceiling_grows_exponentially_up_to_the_cap() {
+        let policy = backoff_policy();
+
+        let ceilings: Vec<_> =
+            (0..6).map(|attempt| policy.ceiling(attempt)).collect();
+
+        assert_eq!(
+            ceilings,
+            [1, 2, 4, 8, 10, 10].map(Duration::from_secs).to_vec()
+        );
+        assert_eq!(policy.ceiling(u32::MAX), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn backoff_delay_is_jittered_below_the_ceiling() {
+        use rand::{SeedableRng, rngs::StdRng};
+
+        let policy = backoff_policy();
+        let mut rng = StdRng::seed_from_u64(42);
+
+        for attempt in 0..6 {
+            let delays: Vec<_> =
+                (0..100).map(|_| policy.delay(attempt, &mut rng)).collect();
+
+            assert!(delays.iter().all(|d| *d <= policy.ceiling(attempt)));
+            assert!(delays.iter().any(|d| *d != delays[0]));
+        }
+
+        let replay = |seed| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            (0..6)
+                .map(|a| policy.delay(a, &mut rng))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(replay(7), replay(7));
+    }
+
+    #[test]
+    fn backoff_is_exhausted_after_max_attempts() {
+        let policy = backoff_policy();
+
+        assert!(!policy.is_exhausted(2));
+        assert!(policy.is_exhausted(3));
+    }
+
+    #[test]
+    #[serial]
+    fn backoff_policy_defaults_when_not_configured() {
+        unsafe {
+            std::env::set_var("RUNNING_ENV", "local");
+        }
+
+        let settings = get_config().unwrap();
+        assert_eq!(settings.email_client.retry, BackoffPolicy::default());
+    }
 }
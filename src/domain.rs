@@ -1,3 +1,13 @@
+pub mod csv_reader;
+pub mod csv_writer;
+pub mod mailing_list;
+pub mod markdown;
+pub mod newsletter_html;
+pub mod newsletter_issue;
+pub mod newsletter_template;
+pub mod segment;
 pub mod subscriber;
+pub mod subscriber_attributes;
 pub mod subscriber_email;
 pub mod subscriber_name;
+pub mod subscriber_tag;
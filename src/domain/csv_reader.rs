@@ -0,0 +1,245 @@
+/// Longest record kept in memory; longer ones are reported and skipped.
+const MAX_RECORD_BYTES: usize = 4096;
+
+/// A record of the file, or why it could not be read.
+#[derive(Debug, PartialEq)]
+pub struct CsvRecord {
+    /// Line of the file the record starts on, from 1
+    pub line: usize,
+    pub fields: Result<Vec<String>, String>,
+}
+
+/// Splits a CSV file (RFC 4180) into records as its bytes come in, so
+/// that the file never has to be held in memory.
+///
+/// Quoted fields may contain commas, line breaks and doubled quotes; both
+/// `\n` and `\r\n` end a record, and blank lines are skipped.
+#[derive(Default)]
+pub struct CsvReader {
+    fields: Vec<Vec<u8>>,
+    field: Vec<u8>,
+    in_quotes: bool,
+    /// A quote was just read inside a quoted field: it is either escaped
+    /// by the next one, or closes the field
+    quote_pending: bool,
+    record_bytes: usize,
+    too_long: bool,
+    line: usize,
+    record_line: usize,
+}
+
+impl CsvReader {
+    pub fn new() -> Self {
+        Self {
+            line: 1,
+            record_line: 1,
+            ..Self::default()
+        }
+    }
+
+    /// The records completed by `chunk`.
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<CsvRecord> {
+        let mut records = Vec::new();
+        for &byte in chunk {
+            if let Some(record) = self.push(byte) {
+                records.push(record);
+            }
+        }
+        records
+    }
+
+    /// The last record, if the file does not end with a line break.
+    pub fn finish(mut self) -> Option<CsvRecord> {
+        if self.in_quotes && !self.quote_pending {
+            return Some(CsvRecord {
+                line: self.record_line,
+                fields: Err("Unterminated quoted field".to_string()),
+            });
+        }
+        self.end_record()
+    }
+
+    fn push(&mut self, byte: u8) -> Option<CsvRecord> {
+        if self.quote_pending {
+            self.quote_pending = false;
+            if byte == b'"' {
+                self.push_byte(byte);
+                return None;
+            }
+            self.in_quotes = false;
+        }
+
+        match byte {
+            b'"' if self.in_quotes => self.quote_pending = true,
+            b'"' if self.field.is_empty() => self.in_quotes = true,
+            b'\n' => {
+                self.line += 1;
+                if self.in_quotes {
+                    self.push_byte(byte);
+                } else {
+                    return self.end_record();
+                }
+            }
+            b'\r' if !self.in_quotes => {}
+            b',' if !self.in_quotes => {
+                let field = std::mem::take(&mut self.field);
+                // Separators count too, or a line of commas would grow
+                // `fields` without limit
+                if self.count_byte() {
+                    self.fields.push(field);
+                }
+            }
+            _ => self.push_byte(byte),
+        }
+        None
+    }
+
+    fn push_byte(&mut self, byte: u8) {
+        if self.count_byte() {
+            self.field.push(byte);
+        }
+    }
+
+    /// Whether the record is still short enough to be kept.
+    fn count_byte(&mut self) -> bool {
+        self.record_bytes += 1;
+        if self.record_bytes > MAX_RECORD_BYTES {
+            self.too_long = true;
+        }
+        !self.too_long
+    }
+
+    fn end_record(&mut self) -> Option<CsvRecord> {
+        let field = std::mem::take(&mut self.field);
+        let mut fields = std::mem::take(&mut self.fields);
+        fields.push(field);
+        let line = std::mem::replace(&mut self.record_line, self.line);
+        let too_long = std::mem::take(&mut self.too_long);
+        self.record_bytes = 0;
+
+        if too_long {
+            return Some(CsvRecord {
+                line,
+                fields: Err(format!(
+                    "The line is longer than {MAX_RECORD_BYTES} bytes"
+                )),
+            });
+        }
+        if let [field] = fields.as_slice()
+            && field.is_empty()
+        {
+            return None;
+        }
+        let fields = fields
+            .into_iter()
+            .map(String::from_utf8)
+            .collect::<Result<_, _>>()
+            .map_err(|_| "The line is not valid UTF-8".to_string());
+        Some(CsvRecord { line, fields })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CsvReader, CsvRecord, MAX_RECORD_BYTES};
+
+    fn read(chunks: &[&str]) -> Vec<CsvRecord> {
+        let mut reader = CsvReader::new();
+        let mut records: Vec<_> = chunks
+            .iter()
+            .flat_map(|chunk| reader.feed(chunk.as_bytes()))
+            .collect();
+        records.extend(reader.finish());
+        records
+    }
+
+    fn fields(record: &CsvRecord) -> Vec<&str> {
+        record
+            .fields
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(String::as_str)
+            .collect()
+    }
+
+    #[test]
+    fn records_are_split_on_commas_and_line_breaks() {
+        let records = read(&["email,name\r\na@example.com,Ann\nb@x.io,Bob"]);
+
+        assert_eq!(records.len(), 3);
+        assert_eq!(fields(&records[0]), ["email", "name"]);
+        assert_eq!(fields(&records[1]), ["a@example.com", "Ann"]);
+        assert_eq!(fields(&records[2]), ["b@x.io", "Bob"]);
+        assert_eq!(records[2].line, 3);
+    }
+
+    #[test]
+    fn records_can_span_chunks() {
+        let records = read(&["a@exam", "ple.com,\"Le ", "Guin\"\n", "b,c\n"]);
+
+        assert_eq!(fields(&records[0]), ["a@example.com", "Le Guin"]);
+        assert_eq!(fields(&records[1]), ["b", "c"]);
+    }
+
+    #[test]
+    fn quoted_fields_keep_commas_quotes_and_line_breaks() {
+        let records = read(&["\"a,b\",\"say \"\"hi\"\"\",\"two\nlines\"\nx\n"]);
+
+        assert_eq!(fields(&records[0]), ["a,b", "say \"hi\"", "two\nlines"]);
+        // The quoted line break still counts as a line
+        assert_eq!(records[1].line, 3);
+    }
+
+    #[test]
+    fn blank_lines_are_skipped() {
+        let records = read(&["a\n\n\r\nb\n"]);
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].line, 4);
+    }
+
+    #[test]
+    fn empty_fields_are_kept() {
+        let records = read(&[",x,\n"]);
+
+        assert_eq!(fields(&records[0]), ["", "x", ""]);
+    }
+
+    #[test]
+    fn overlong_records_are_reported_and_the_next_ones_still_read() {
+        let long = "a".repeat(5000);
+        let records = read(&[&long, "\nb\n"]);
+
+        assert!(records[0].fields.is_err());
+        assert_eq!(fields(&records[1]), ["b"]);
+    }
+
+    #[test]
+    fn lines_of_commas_are_bounded_too() {
+        let mut reader = CsvReader::new();
+        assert!(reader.feed(",".repeat(100_000).as_bytes()).is_empty());
+        assert!(reader.fields.len() <= MAX_RECORD_BYTES);
+
+        let records = reader.feed(b"\nb\n");
+        assert!(records[0].fields.is_err());
+        assert_eq!(fields(&records[1]), ["b"]);
+    }
+
+    #[test]
+    fn invalid_utf8_is_reported() {
+        let mut reader = CsvReader::new();
+        let records = reader.feed(b"\xff,a\nb\n");
+
+        assert!(records[0].fields.is_err());
+        assert_eq!(records[1].fields, Ok(vec!["b".to_string()]));
+    }
+
+    #[test]
+    fn unterminated_quotes_are_reported() {
+        let records = read(&["a\n\"b,c\n"]);
+
+        assert_eq!(records.len(), 2);
+        assert!(records[1].fields.is_err());
+    }
+}
@@ -0,0 +1,54 @@
+/// A CSV record (RFC 4180) ending with `\r\n`, the counterpart of
+/// [`CsvReader`](super::csv_reader::CsvReader).
+///
+/// Fields are quoted only when they hold a comma, a quote or a line break.
+pub fn csv_record(fields: &[&str]) -> String {
+    let mut record = String::new();
+    for (i, field) in fields.iter().enumerate() {
+        if i > 0 {
+            record.push(',');
+        }
+        if field.contains([',', '"', '\r', '\n']) {
+            record.push('"');
+            record.push_str(&field.replace('"', "\"\""));
+            record.push('"');
+        } else {
+            record.push_str(field);
+        }
+    }
+    record.push_str("\r\n");
+    record
+}
+
+#[cfg(test)]
+mod tests {
+    use super::csv_record;
+    use crate::domain::csv_reader::CsvReader;
+
+    #[test]
+    fn plain_fields_are_not_quoted() {
+        assert_eq!(
+            csv_record(&["a@example.com", "Ann", ""]),
+            "a@example.com,Ann,\r\n"
+        );
+    }
+
+    #[test]
+    fn fields_with_special_characters_are_quoted() {
+        assert_eq!(
+            csv_record(&["Butler, Octavia", "say \"hi\"", "two\nlines"]),
+            "\"Butler, Octavia\",\"say \"\"hi\"\"\",\"two\nlines\"\r\n"
+        );
+    }
+
+    #[test]
+    fn records_read_back_as_written() {
+        let fields = ["a,b", "\"", "x\r\ny", " spaced "];
+        let mut reader = CsvReader::new();
+
+        let records = reader.feed(csv_record(&fields).as_bytes());
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].fields, Ok(fields.map(String::from).to_vec()));
+    }
+}
@@ -0,0 +1,95 @@
+/// The list subscribers join and issues go to when none is named.
+const DEFAULT_LIST: &str = "newsletter";
+
+/// The public, URL-safe name of a mailing list, e.g. `weekly-digest`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListSlug(String);
+
+impl ListSlug {
+    pub fn default_list() -> Self {
+        Self(DEFAULT_LIST.to_string())
+    }
+}
+
+impl TryFrom<String> for ListSlug {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let is_too_long = value.len() > 64;
+        let is_well_formed = value
+            .split('-')
+            .all(|part| !part.is_empty() && part.chars().all(is_slug_char));
+
+        if value.is_empty() || is_too_long || !is_well_formed {
+            return Err(format!("{value} is not a valid list slug"));
+        }
+
+        Ok(Self(value))
+    }
+}
+
+fn is_slug_char(c: char) -> bool {
+    c.is_ascii_lowercase() || c.is_ascii_digit()
+}
+
+impl AsRef<str> for ListSlug {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for ListSlug {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for ListSlug {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.try_into().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ListSlug;
+    use claim::{assert_err, assert_ok};
+
+    #[test]
+    fn lowercase_words_joined_by_dashes_are_valid() {
+        assert_ok!(ListSlug::try_from("weekly-digest-2".to_string()));
+    }
+
+    #[test]
+    fn the_default_list_is_a_valid_slug() {
+        let slug = ListSlug::default_list();
+        assert_ok!(ListSlug::try_from(slug.to_string()));
+    }
+
+    #[test]
+    fn empty_slug_is_rejected() {
+        assert_err!(ListSlug::try_from(String::new()));
+    }
+
+    #[test]
+    fn a_slug_longer_than_64_characters_is_rejected() {
+        assert_err!(ListSlug::try_from("a".repeat(65)));
+    }
+
+    #[test]
+    fn uppercase_and_spaces_are_rejected() {
+        assert_err!(ListSlug::try_from("Weekly".to_string()));
+        assert_err!(ListSlug::try_from("weekly digest".to_string()));
+    }
+
+    #[test]
+    fn leading_trailing_or_doubled_dashes_are_rejected() {
+        for slug in ["-weekly", "weekly-", "weekly--digest"] {
+            assert_err!(ListSlug::try_from(slug.to_string()));
+        }
+    }
+}
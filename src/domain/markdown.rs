@@ -0,0 +1,300 @@
+use pulldown_cmark::{CowStr, Event, Options, Parser, Tag, TagEnd, html};
+use pulldown_cmark_escape::escape_html;
+
+fn parser(markdown: &str) -> Parser<'_> {
+    Parser::new_ext(
+        markdown,
+        Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TABLES,
+    )
+}
+
+/// Renders to HTML that is safe to embed in an email or the web version:
+/// raw HTML in the source goes through the same sanitizer as everything
+/// else.
+pub fn to_html(markdown: &str) -> String {
+    let mut unsafe_html = String::new();
+    html::push_html(&mut unsafe_html, keep_templated_targets(parser(markdown)));
+
+    ammonia::clean(&unsafe_html)
+}
+
+/// `push_html` percent-encodes the braces of link and image targets, which
+/// would turn `[Unsubscribe]({{unsubscribe_url}})` into a dead link: the
+/// tags of templated targets are written here instead, only HTML-escaped.
+fn keep_templated_targets<'a>(
+    events: impl Iterator<Item = Event<'a>>,
+) -> impl Iterator<Item = Event<'a>> {
+    let mut in_templated_link = false;
+    // The tag of the templated image being read, and its alt text so far
+    let mut image: Option<(String, String)> = None;
+    let mut nested_images = 0;
+
+    events.filter_map(move |event| {
+        if let Some((_, alt)) = &mut image {
+            match event {
+                Event::Start(Tag::Image { .. }) => nested_images += 1,
+                Event::End(TagEnd::Image) if nested_images > 0 => {
+                    nested_images -= 1
+                }
+                Event::End(TagEnd::Image) => {
+                    let (tag, alt) = image.take()?;
+                    return Some(Event::Html(
+                        format!("{tag} alt=\"{}\" />", escaped(&alt)).into(),
+                    ));
+                }
+                Event::Text(s) | Event::Code(s) => alt.push_str(&s),
+                _ => {}
+            }
+            return None;
+        }
+
+        match event {
+            Event::Start(Tag::Link {
+                ref dest_url,
+                ref title,
+                ..
+            }) if is_templated(dest_url) => {
+                in_templated_link = true;
+                Some(Event::Html(
+                    format!(
+                        "<a href=\"{}\"{}>",
+                        escaped(dest_url),
+                        title_attribute(title)
+                    )
+                    .into(),
+                ))
+            }
+            Event::End(TagEnd::Link) if in_templated_link => {
+                in_templated_link = false;
+                Some(Event::Html("</a>".into()))
+            }
+            Event::Start(Tag::Image {
+                ref dest_url,
+                ref title,
+                ..
+            }) if is_templated(dest_url) => {
+                image = Some((
+                    format!(
+                        "<img src=\"{}\"{}",
+                        escaped(dest_url),
+                        title_attribute(title)
+                    ),
+                    String::new(),
+                ));
+                None
+            }
+            event => Some(event),
+        }
+    })
+}
+
+fn is_templated(url: &str) -> bool {
+    url.contains("{{")
+}
+
+fn title_attribute(title: &CowStr<'_>) -> String {
+    if title.is_empty() {
+        String::new()
+    } else {
+        format!(" title=\"{}\"", escaped(title))
+    }
+}
+
+fn escaped(s: &str) -> String {
+    let mut out = String::new();
+    escape_html(&mut out, s).expect("Writing to a String cannot fail");
+    out
+}
+
+/// Renders a plain-text fallback that reads well in a mail client: one
+/// blank line between blocks, bulleted or numbered list items and link
+/// targets spelled out. Raw HTML is dropped.
+pub fn to_text(markdown: &str) -> String {
+    let mut text = TextWriter::default();
+    for event in parser(markdown) {
+        text.write(event);
+    }
+
+    text.out.trim().to_owned()
+}
+
+#[derive(Default)]
+struct TextWriter {
+    out: String,
+    /// The next number of every open list, `None` for bulleted ones
+    lists: Vec<Option<u64>>,
+    /// Targets of the open links, shown after their text
+    links: Vec<String>,
+    /// Where the text of the innermost open link starts
+    link_starts: Vec<usize>,
+}
+
+impl TextWriter {
+    fn write(&mut self, event: Event<'_>) {
+        match event {
+            Event::Start(Tag::List(start)) => {
+                self.line();
+                self.lists.push(start);
+            }
+            Event::Start(Tag::Item) => {
+                self.line();
+                let depth = self.lists.len().saturating_sub(1);
+                self.out.push_str(&"  ".repeat(depth));
+                match self.lists.last_mut() {
+                    Some(Some(n)) => {
+                        self.out.push_str(&format!("{n}. "));
+                        *n += 1;
+                    }
+                    _ => self.out.push_str("- "),
+                }
+            }
+            Event::Start(Tag::Link { dest_url, .. })
+            | Event::Start(Tag::Image { dest_url, .. }) => {
+                self.links.push(dest_url.into_string());
+                self.link_starts.push(self.out.len());
+            }
+            Event::Start(Tag::Paragraph)
+            | Event::Start(Tag::Heading { .. })
+            | Event::Start(Tag::CodeBlock(_))
+            | Event::Start(Tag::BlockQuote(_))
+            | Event::Start(Tag::Table(_)) => self.block(),
+            Event::Start(Tag::TableRow) | Event::Start(Tag::TableHead) => {
+                self.line()
+            }
+            Event::End(TagEnd::TableCell) => self.out.push('\t'),
+            Event::End(TagEnd::List(_)) => {
+                self.lists.pop();
+                if self.lists.is_empty() {
+                    self.block();
+                }
+            }
+            Event::End(TagEnd::Link) | Event::End(TagEnd::Image) => {
+                let (Some(url), Some(start)) =
+                    (self.links.pop(), self.link_starts.pop())
+                else {
+                    return;
+                };
+                if self.out[start..] != url {
+                    self.out.push_str(&format!(" ({url})"));
+                }
+            }
+            Event::End(TagEnd::Paragraph)
+            | Event::End(TagEnd::Heading(_))
+            | Event::End(TagEnd::CodeBlock)
+            | Event::End(TagEnd::BlockQuote(_))
+            | Event::End(TagEnd::Table) => self.block(),
+            Event::Text(s) | Event::Code(s) => self.out.push_str(&s),
+            Event::SoftBreak => self.out.push(' '),
+            Event::HardBreak => self.out.push('\n'),
+            Event::Rule => {
+                self.block();
+                self.out.push_str("---");
+                self.block();
+            }
+            _ => {}
+        }
+    }
+
+    /// Starts a new line unless at the start of one.
+    fn line(&mut self) {
+        if !self.out.is_empty() && !self.out.ends_with('\n') {
+            self.out.push('\n');
+        }
+    }
+
+    /// Leaves a blank line after the previous block, inside lists only a
+    /// line break.
+    fn block(&mut self) {
+        if self.out.is_empty() {
+            return;
+        }
+        self.line();
+        if self.lists.is_empty() && !self.out.ends_with("\n\n") {
+            self.out.push('\n');
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "\
+# Weekly news
+
+Hello {{ name }}, this week is *special*.
+Read [the post](https://example.com/post) or visit https://example.com.
+
+- First
+- Second
+  1. Nested
+
+Bye";
+
+    #[test]
+    fn html_is_rendered() {
+        let html = to_html(SOURCE);
+
+        assert!(html.contains("<h1>Weekly news</h1>"));
+        assert!(html.contains("<em>special</em>"));
+        assert!(html.contains(r#"href="https://example.com/post""#));
+        assert!(html.contains("<li>First</li>"));
+    }
+
+    #[test]
+    fn unsafe_html_is_stripped() {
+        let html = to_html(
+            "Hi <script>alert(1)</script>\n\n\
+            <a href=\"javascript:alert(1)\" onclick=\"x()\">link</a>",
+        );
+
+        assert!(!html.contains("<script"));
+        assert!(!html.contains("javascript:"));
+        assert!(!html.contains("onclick"));
+        assert!(html.contains("link</a>"));
+    }
+
+    #[test]
+    fn template_variables_survive_rendering() {
+        assert!(to_html(SOURCE).contains("Hello {{ name }}"));
+        assert!(to_text(SOURCE).contains("Hello {{ name }}"));
+    }
+
+    #[test]
+    fn templated_link_targets_are_kept() {
+        let html = to_html(
+            "[Unsubscribe]({{unsubscribe_url}} \"Bye\") \
+            ![Hi *you*]({{view_online_url}}/logo.png)",
+        );
+
+        assert!(html.contains(
+            r#"<a href="{{unsubscribe_url}}" title="Bye" rel="noopener noreferrer">Unsubscribe</a>"#
+        ));
+        assert!(html.contains(
+            r#"<img src="{{view_online_url}}/logo.png" alt="Hi you">"#
+        ));
+    }
+
+    #[test]
+    fn text_keeps_the_structure_readable() {
+        assert_eq!(
+            to_text(SOURCE),
+            "\
+Weekly news
+
+Hello {{ name }}, this week is special. \
+Read the post (https://example.com/post) or visit https://example.com.
+
+- First
+- Second
+  1. Nested
+
+Bye"
+        );
+    }
+
+    #[test]
+    fn raw_html_is_dropped_from_the_text() {
+        assert_eq!(to_text("Hi <b>there</b>"), "Hi there");
+    }
+}
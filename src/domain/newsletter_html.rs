@@ -0,0 +1,411 @@
+use std::borrow::Cow;
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use anyhow::Context;
+use lol_html::html_content::Element;
+use lol_html::{
+    ElementContentHandlers, RewriteStrSettings, Selector, element, rewrite_str,
+    text,
+};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+
+/// ammonia's allowlist, plus inline styles which is where the rules of
+/// `<style>` blocks end up.
+static SANITIZER: Lazy<ammonia::Builder<'static>> = Lazy::new(|| {
+    let mut builder = ammonia::Builder::default();
+    builder.add_generic_attributes(["style"]);
+    builder
+});
+
+/// The HTML body as it will be mailed, and what had to go to get there.
+#[derive(Debug)]
+pub struct PreparedHtml {
+    pub html: String,
+    pub report: PreparationReport,
+}
+
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct PreparationReport {
+    /// Tags outside the allowlist, e.g. `script`; the content of most is
+    /// kept
+    pub removed_elements: BTreeSet<String>,
+    /// Attributes outside the allowlist, or URLs with an unsafe scheme
+    pub removed_attributes: BTreeSet<String>,
+    /// `<style>` rules that cannot be inlined, e.g. `a:hover` or `@media`
+    pub skipped_css_rules: Vec<String>,
+}
+
+/// Inlines the rules of `<style>` blocks into `style` attributes, then
+/// sanitizes the result against the allowlist. Email clients drop most
+/// stylesheets, and nothing executable should reach an inbox.
+pub fn prepare_for_email(html: &str) -> Result<PreparedHtml, anyhow::Error> {
+    let mut report = PreparationReport::default();
+
+    let stylesheet = extract_stylesheet(html)?;
+    let (rules, skipped) = parse_stylesheet(&stylesheet);
+    report.skipped_css_rules = skipped;
+    let inlined = inline_rules(html, rules)?;
+
+    report_removals(&inlined, &mut report)?;
+    let html = SANITIZER.clean(&inlined).to_string();
+
+    Ok(PreparedHtml { html, report })
+}
+
+/// Sanitizes HTML against the same allowlist, without reporting what went.
+/// Issue templates are rendered after they were prepared, and what they
+/// output, e.g. through the `safe` filter, must not escape the allowlist.
+pub fn sanitize(html: &str) -> String {
+    SANITIZER.clean(html).to_string()
+}
+
+/// Inserts `footer` before the closing `</body>` of a full HTML document,
+/// which must stay last, or appends it to a fragment. Only bodies stored
+/// before they were prepared for email can still be full documents.
+pub fn append_to_body(html: &str, footer: &str) -> String {
+    // Lowercasing ASCII keeps the byte offsets of the original
+    match html.to_ascii_lowercase().rfind("</body>") {
+        Some(i) => format!("{}{footer}{}", &html[..i], &html[i..]),
+        None => format!("{html}{footer}"),
+    }
+}
+
+fn extract_stylesheet(html: &str) -> Result<String, anyhow::Error> {
+    let mut stylesheet = String::new();
+    rewrite_str(
+        html,
+        RewriteStrSettings {
+            element_content_handlers: vec![text!("style", |t| {
+                stylesheet.push_str(t.as_str());
+                if t.last_in_text_node() {
+                    stylesheet.push('\n');
+                }
+                Ok(())
+            })],
+            ..RewriteStrSettings::new()
+        },
+    )
+    .context("Failed to read the style blocks")?;
+
+    Ok(stylesheet)
+}
+
+#[derive(Debug)]
+struct CssRule {
+    selector: String,
+    declarations: String,
+    specificity: (u32, u32, u32),
+}
+
+/// Splits a stylesheet into one rule per selector, in source order. At-rules
+/// and selectors with pseudo-classes or -elements cannot be inlined and are
+/// returned apart.
+fn parse_stylesheet(css: &str) -> (Vec<CssRule>, Vec<String>) {
+    let css = strip_comments(css);
+    let mut rules = vec![];
+    let mut skipped = vec![];
+
+    let mut rest = css.as_str();
+    while let Some(open) = rest.find('{') {
+        let prelude = rest[..open].trim();
+        // A statement such as `@import url(...);` before the next block
+        if prelude.starts_with('@')
+            && let Some(end) = prelude.find(';')
+        {
+            skipped.push(prelude[..end].trim().to_owned());
+            rest = &rest[rest.find(';').map_or(rest.len(), |i| i + 1)..];
+            continue;
+        }
+        let Some(close) = matching_brace(&rest[open..]) else {
+            break;
+        };
+        let block = &rest[open + 1..open + close];
+        rest = &rest[open + close + 1..];
+
+        if prelude.starts_with('@') {
+            skipped.push(prelude.to_owned());
+            continue;
+        }
+        let declarations = block.trim().trim_end_matches(';').trim();
+        for selector in prelude.split(',').map(str::trim) {
+            if selector.contains(':') || selector.parse::<Selector>().is_err() {
+                skipped.push(selector.to_owned());
+                continue;
+            }
+            rules.push(CssRule {
+                selector: selector.to_owned(),
+                declarations: declarations.to_owned(),
+                specificity: specificity(selector),
+            });
+        }
+    }
+
+    (rules, skipped)
+}
+
+fn strip_comments(css: &str) -> String {
+    let mut out = String::with_capacity(css.len());
+    let mut rest = css;
+    while let Some(start) = rest.find("/*") {
+        out.push_str(&rest[..start]);
+        rest = rest[start + 2..]
+            .find("*/")
+            .map_or("", |end| &rest[start + 2 + end + 2..]);
+    }
+    out.push_str(rest);
+    out
+}
+
+/// The offset of the brace closing the block that `s` starts with.
+fn matching_brace(s: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Ids, then classes and attributes, then element names.
+fn specificity(selector: &str) -> (u32, u32, u32) {
+    let mut specificity = (0, 0, 0);
+    for compound in selector
+        .split(|c: char| c.is_whitespace() || matches!(c, '>' | '+' | '~'))
+        .filter(|c| !c.is_empty())
+    {
+        if compound.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            specificity.2 += 1;
+        }
+        for c in compound.chars() {
+            match c {
+                '#' => specificity.0 += 1,
+                '.' | '[' => specificity.1 += 1,
+                _ => {}
+            }
+        }
+    }
+    specificity
+}
+
+/// Every handler prepends its declarations, so registering the rules from
+/// the strongest to the weakest leaves them in cascade order, followed by
+/// the element's own `style` which wins over all of them.
+fn inline_rules(
+    html: &str,
+    mut rules: Vec<CssRule>,
+) -> Result<String, anyhow::Error> {
+    if rules.is_empty() {
+        return Ok(html.to_owned());
+    }
+    // Stable, so later rules of equal specificity stay stronger
+    rules.reverse();
+    rules.sort_by_key(|rule| std::cmp::Reverse(rule.specificity));
+
+    let element_content_handlers = rules
+        .into_iter()
+        .map(|rule| {
+            let selector: Selector = rule.selector.parse()?;
+            let handlers = ElementContentHandlers::default().element(
+                move |el: &mut Element| {
+                    let style = match el.get_attribute("style") {
+                        Some(existing) if !existing.trim().is_empty() => {
+                            format!("{}; {}", rule.declarations, existing)
+                        }
+                        _ => rule.declarations.clone(),
+                    };
+                    el.set_attribute("style", &style)?;
+                    Ok(())
+                },
+            );
+            Ok((Cow::Owned(selector), handlers))
+        })
+        .collect::<Result<Vec<_>, lol_html::errors::SelectorError>>()
+        .context("Failed to parse a style rule")?;
+
+    rewrite_str(
+        html,
+        RewriteStrSettings {
+            element_content_handlers,
+            ..RewriteStrSettings::new()
+        },
+    )
+    .context("Failed to inline the style rules")
+}
+
+/// Walks the HTML with the sanitizer's allowlist to tell what it is about
+/// to remove.
+fn report_removals(
+    html: &str,
+    report: &mut PreparationReport,
+) -> Result<(), anyhow::Error> {
+    let tags = SANITIZER.clone_tags();
+    let generic_attributes = SANITIZER.clone_generic_attributes();
+    let tag_attributes: HashMap<&str, HashSet<&str>> =
+        SANITIZER.clone_tag_attributes();
+    let url_schemes = SANITIZER.clone_url_schemes();
+
+    rewrite_str(
+        html,
+        RewriteStrSettings {
+            element_content_handlers: vec![element!("*", |el| {
+                let tag = el.tag_name();
+                if !tags.contains(tag.as_str()) {
+                    report.removed_elements.insert(tag);
+                    return Ok(());
+                }
+                for attribute in el.attributes() {
+                    let name = attribute.name();
+                    let allowed = generic_attributes.contains(name.as_str())
+                        || tag_attributes
+                            .get(tag.as_str())
+                            .is_some_and(|a| a.contains(name.as_str()));
+                    if !allowed {
+                        report.removed_attributes.insert(name);
+                    } else if matches!(name.as_str(), "href" | "src")
+                        && url_scheme(&attribute.value())
+                            .is_some_and(|s| !url_schemes.contains(s.as_str()))
+                    {
+                        report
+                            .removed_attributes
+                            .insert(format!("{name} (unsafe URL)"));
+                    }
+                }
+                Ok(())
+            })],
+            ..RewriteStrSettings::new()
+        },
+    )
+    .context("Failed to inspect the HTML")?;
+
+    Ok(())
+}
+
+/// The lowercased scheme of an absolute URL, `None` for relative ones.
+fn url_scheme(url: &str) -> Option<String> {
+    let url = url.trim();
+    let end = url.find([':', '/', '?', '#'])?;
+    (url[end..].starts_with(':') && end > 0)
+        .then(|| url[..end].to_ascii_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn style_blocks_are_inlined() {
+        let prepared = prepare_for_email(
+            r#"<style>
+                p { color: red; }
+                .lead { font-size: 20px }
+                /* a comment */
+                #intro { color: blue; }
+            </style>
+            <p id="intro" class="lead">Hi</p><p style="margin: 0">Bye</p>"#,
+        )
+        .unwrap();
+
+        assert!(prepared.html.contains(
+            r#"<p style="color: red; font-size: 20px; color: blue">Hi</p>"#
+        ));
+        assert!(
+            prepared
+                .html
+                .contains(r#"<p style="color: red; margin: 0">Bye</p>"#)
+        );
+        assert!(!prepared.html.contains("<style"));
+        assert!(prepared.report.skipped_css_rules.is_empty());
+    }
+
+    #[test]
+    fn rules_that_cannot_be_inlined_are_reported() {
+        let prepared = prepare_for_email(
+            "<style>\
+                @import url(https://example.com/a.css);\
+                a:hover { color: red }\
+                @media (max-width: 600px) { p { color: blue } }\
+                a, p { margin: 0 }\
+            </style><p>Hi</p>",
+        )
+        .unwrap();
+
+        assert_eq!(
+            prepared.report.skipped_css_rules,
+            vec![
+                "@import url(https://example.com/a.css)",
+                "a:hover",
+                "@media (max-width: 600px)",
+            ]
+        );
+        assert!(prepared.html.contains(r#"<p style="margin: 0">Hi</p>"#));
+    }
+
+    #[test]
+    fn unsafe_html_is_removed_and_reported() {
+        let prepared = prepare_for_email(
+            r#"<link rel="stylesheet" href="https://example.com/a.css">
+            <script>alert(1)</script>
+            <p onclick="alert(1)">Hi <a href="javascript:alert(1)">there</a></p>"#,
+        )
+        .unwrap();
+
+        assert!(!prepared.html.contains("script"));
+        assert!(!prepared.html.contains("<link"));
+        assert!(!prepared.html.contains("onclick"));
+        assert!(!prepared.html.contains("javascript:"));
+        assert_eq!(
+            prepared.report.removed_elements,
+            BTreeSet::from(["link".into(), "script".into()])
+        );
+        assert_eq!(
+            prepared.report.removed_attributes,
+            BTreeSet::from(["href (unsafe URL)".into(), "onclick".into()])
+        );
+    }
+
+    #[test]
+    fn safe_html_and_templates_are_kept() {
+        let html = r#"<p>Hi {{ name }}, <a href="{{ unsubscribe_url }}">unsubscribe</a></p>"#;
+
+        let prepared = prepare_for_email(html).unwrap();
+
+        assert!(prepared.html.contains("Hi {{ name }}"));
+        assert!(prepared.html.contains(r#"href="{{ unsubscribe_url }}""#));
+        assert_eq!(prepared.report, PreparationReport::default());
+    }
+
+    #[test]
+    fn footers_go_inside_the_body_of_full_documents() {
+        let html = "<html><head></head><BODY><p>Hi</p></BODY></html>";
+
+        assert_eq!(
+            append_to_body(html, "<p>Bye</p>"),
+            "<html><head></head><BODY><p>Hi</p><p>Bye</p></BODY></html>"
+        );
+    }
+
+    #[test]
+    fn footers_are_appended_to_fragments() {
+        assert_eq!(
+            append_to_body("<p>Hi</p>", "<p>Bye</p>"),
+            "<p>Hi</p><p>Bye</p>"
+        );
+    }
+
+    #[test]
+    fn specificity_counts_ids_classes_and_elements() {
+        assert_eq!(specificity("p"), (0, 0, 1));
+        assert_eq!(specificity("div > p.lead"), (0, 1, 2));
+        assert_eq!(specificity("#intro a[href]"), (1, 1, 1));
+        assert_eq!(specificity("*"), (0, 0, 0));
+    }
+}
@@ -0,0 +1,97 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::Context;
+use reqwest::Url;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IssueStatus {
+    /// Still being edited; nothing is enqueued
+    Draft,
+    /// Waiting for its send time; it can still be rescheduled or cancelled
+    Scheduled,
+    Published,
+    /// Delivery is on hold until resumed
+    Paused,
+    Cancelled,
+}
+
+impl IssueStatus {
+    /// Statuses whose queued deliveries the worker may send.
+    pub const SENDING: &'static [Self] = &[Self::Scheduled, Self::Published];
+
+    pub fn to_strings(statuses: &[Self]) -> Vec<String> {
+        statuses.iter().map(ToString::to_string).collect()
+    }
+}
+
+impl fmt::Display for IssueStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Draft => write!(f, "draft"),
+            Self::Scheduled => write!(f, "scheduled"),
+            Self::Published => write!(f, "published"),
+            Self::Paused => write!(f, "paused"),
+            Self::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+impl FromStr for IssueStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "draft" => Ok(Self::Draft),
+            "scheduled" => Ok(Self::Scheduled),
+            "published" => Ok(Self::Published),
+            "paused" => Ok(Self::Paused),
+            "cancelled" => Ok(Self::Cancelled),
+            other => Err(format!("{other} is not a valid issue status")),
+        }
+    }
+}
+
+/// The public "view in browser" page of an issue. Emails link to it, so
+/// its shape must not change.
+pub fn web_version_url(
+    base_url: &str,
+    issue_id: Uuid,
+) -> Result<Url, anyhow::Error> {
+    let mut url = Url::parse(base_url).context("Invalid base url")?;
+    url.set_path(&format!("/newsletters/{issue_id}"));
+
+    Ok(url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_round_trips_through_its_string_form() {
+        for status in [
+            IssueStatus::Draft,
+            IssueStatus::Scheduled,
+            IssueStatus::Published,
+            IssueStatus::Paused,
+            IssueStatus::Cancelled,
+        ] {
+            assert_eq!(status.to_string().parse(), Ok(status));
+        }
+    }
+
+    #[test]
+    fn web_version_url_is_rooted_at_the_base_url() {
+        let issue_id = Uuid::new_v4();
+
+        let url =
+            web_version_url("https://example.com/ignored", issue_id).unwrap();
+
+        assert_eq!(
+            url.as_str(),
+            format!("https://example.com/newsletters/{issue_id}")
+        );
+    }
+}
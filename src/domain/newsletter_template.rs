@@ -0,0 +1,201 @@
+use minijinja::{Environment, UndefinedBehavior, Value, context};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+
+use crate::domain::newsletter_html;
+use crate::routers::error_chain_fmt;
+
+/// Strict, so that a misspelt variable is an error rather than a blank.
+/// The HTML body is auto-escaped, the text body is not.
+static ENVIRONMENT: Lazy<Environment<'static>> = Lazy::new(|| {
+    let mut env = Environment::new();
+    env.set_undefined_behavior(UndefinedBehavior::Strict);
+    env
+});
+
+/// The public page of an issue: the title is escaped like any variable,
+/// the body was rendered and sanitized already.
+const WEB_VERSION_PAGE: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta http-equiv="content-type" content="text/html; charset=utf-8">
+<title>{{ title }}</title>
+</head>
+<body>
+<h1>{{ title }}</h1>
+{{ body }}
+</body>
+</html>"#;
+
+/// The variables available to issue templates, e.g. `{{ name }}`.
+#[derive(Serialize, Debug)]
+pub struct TemplateContext<'a> {
+    pub name: &'a str,
+    pub unsubscribe_url: &'a str,
+    pub view_online_url: &'a str,
+}
+
+impl<'a> TemplateContext<'a> {
+    /// Stands in for a subscriber where there is none, e.g. on the web
+    /// version or in a test send.
+    pub fn generic(unsubscribe_url: &'a str, view_online_url: &'a str) -> Self {
+        Self {
+            name: "Subscriber",
+            unsubscribe_url,
+            view_online_url,
+        }
+    }
+}
+
+#[derive(thiserror::Error)]
+#[error("The {body} body is not a valid template")]
+pub struct TemplateError {
+    body: &'static str,
+    #[source]
+    source: minijinja::Error,
+}
+
+impl std::fmt::Debug for TemplateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+#[derive(Debug)]
+pub struct RenderedContent {
+    pub text: String,
+    pub html: String,
+}
+
+pub fn render(
+    text_template: &str,
+    html_template: &str,
+    context: &TemplateContext<'_>,
+) -> Result<RenderedContent, TemplateError> {
+    let text = ENVIRONMENT
+        .render_named_str("content.txt", text_template, context)
+        .map_err(|source| TemplateError {
+            body: "text",
+            source,
+        })?;
+    let html = render_html(html_template, context)?;
+
+    Ok(RenderedContent { text, html })
+}
+
+/// Renders the HTML body, sanitized again: the template itself can output
+/// markup that was not there when the body was prepared for email.
+pub fn render_html(
+    html_template: &str,
+    context: &TemplateContext<'_>,
+) -> Result<String, TemplateError> {
+    let html = ENVIRONMENT
+        .render_named_str("content.html", html_template, context)
+        .map_err(|source| TemplateError {
+            body: "HTML",
+            source,
+        })?;
+    Ok(newsletter_html::sanitize(&html))
+}
+
+/// Wraps the rendered HTML body of an issue in its web version page.
+pub fn render_web_version(
+    title: &str,
+    html_body: &str,
+) -> Result<String, minijinja::Error> {
+    ENVIRONMENT.render_named_str(
+        "web_version.html",
+        WEB_VERSION_PAGE,
+        context! {
+            title,
+            body => Value::from_safe_string(html_body.to_owned()),
+        },
+    )
+}
+
+/// Checks both bodies render, catching syntax errors and unknown variables
+/// before the issue reaches any subscriber.
+pub fn validate(
+    text_template: &str,
+    html_template: &str,
+) -> Result<(), TemplateError> {
+    let context = TemplateContext::generic(
+        "https://example.com/unsubscribe",
+        "https://example.com/newsletter",
+    );
+    render(text_template, html_template, &context).map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::{assert_err, assert_ok};
+
+    use super::*;
+
+    fn context() -> TemplateContext<'static> {
+        TemplateContext {
+            name: "Ursula <Le Guin>",
+            unsubscribe_url: "https://example.com/unsubscribe?token=a&b",
+            view_online_url: "https://example.com/newsletters/1",
+        }
+    }
+
+    #[test]
+    fn variables_are_substituted() {
+        let content = render(
+            "Hi {{ name }}, read online: {{ view_online_url }}",
+            "<p>Hi {{ name }}</p>",
+            &context(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            content.text,
+            "Hi Ursula <Le Guin>, read online: \
+            https://example.com/newsletters/1"
+        );
+        assert_eq!(content.html, "<p>Hi Ursula &lt;Le Guin&gt;</p>");
+    }
+
+    #[test]
+    fn content_without_variables_is_unchanged() {
+        let content =
+            render("Plain text", "<p>Plain HTML</p>", &context()).unwrap();
+
+        assert_eq!(content.text, "Plain text");
+        assert_eq!(content.html, "<p>Plain HTML</p>");
+    }
+
+    #[test]
+    fn markup_output_by_the_template_is_sanitized() {
+        let content = render(
+            "Hi",
+            r#"{{ "<script>alert(1)</script>"|safe }}<p>Hi</p>"#,
+            &context(),
+        )
+        .unwrap();
+
+        assert!(!content.html.contains("<script"));
+        assert!(content.html.contains("<p>Hi</p>"));
+    }
+
+    #[test]
+    fn unknown_variables_are_rejected() {
+        assert_err!(validate("Hi {{ nmae }}", "<p>Hi</p>"));
+        assert_err!(validate("Hi", "<p>{{ unsubscribe_link }}</p>"));
+    }
+
+    #[test]
+    fn syntax_errors_are_rejected() {
+        assert_err!(validate("Hi {{ name", "<p>Hi</p>"));
+        assert_err!(validate("Hi", "<p>{% if name %}</p>"));
+    }
+
+    #[test]
+    fn known_variables_are_accepted() {
+        assert_ok!(validate(
+            "{{ name }} {{ unsubscribe_url }} {{ view_online_url }}",
+            "{% if name %}<p>{{ name }}</p>{% endif %}",
+        ));
+    }
+}
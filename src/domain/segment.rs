@@ -0,0 +1,127 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use time::OffsetDateTime;
+
+use crate::domain::subscriber_tag::SubscriberTag;
+
+const MAX_DEPTH: usize = 8;
+const MAX_CONDITIONS: usize = 100;
+
+/// A filter narrowing the audience of an issue, written as JSON:
+///
+/// ```json
+/// {"all": [
+///     {"tag": "beta"},
+///     {"not": {"attribute": {"name": "plan", "equals": "free"}}},
+///     {"subscribed_after": "2025-01-01T00:00:00Z"}
+/// ]}
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Segment {
+    /// Every condition holds; true if there are none
+    All(Vec<Segment>),
+    /// At least one condition holds; false if there are none
+    Any(Vec<Segment>),
+    Not(Box<Segment>),
+    Tag(SubscriberTag),
+    /// The top-level attribute `name` is equal to the JSON value `equals`
+    Attribute {
+        name: String,
+        equals: Value,
+    },
+    SubscribedBefore(#[serde(with = "time::serde::rfc3339")] OffsetDateTime),
+    SubscribedAfter(#[serde(with = "time::serde::rfc3339")] OffsetDateTime),
+}
+
+impl Segment {
+    /// Bounds the size of the filter, as it is compiled into a single query.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.depth() > MAX_DEPTH {
+            return Err(format!(
+                "A segment nests at most {MAX_DEPTH} conditions deep"
+            ));
+        }
+        if self.n_conditions() > MAX_CONDITIONS {
+            return Err(format!(
+                "A segment has at most {MAX_CONDITIONS} conditions"
+            ));
+        }
+        Ok(())
+    }
+
+    fn children(&self) -> &[Segment] {
+        match self {
+            Self::All(segments) | Self::Any(segments) => segments,
+            Self::Not(segment) => std::slice::from_ref(segment.as_ref()),
+            _ => &[],
+        }
+    }
+
+    fn depth(&self) -> usize {
+        1 + self.children().iter().map(Self::depth).max().unwrap_or(0)
+    }
+
+    fn n_conditions(&self) -> usize {
+        1 + self
+            .children()
+            .iter()
+            .map(Self::n_conditions)
+            .sum::<usize>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Segment;
+    use claim::{assert_err, assert_ok};
+    use serde_json::json;
+
+    #[test]
+    fn segments_are_parsed_from_json() {
+        let segment: Segment = serde_json::from_value(json!({
+            "all": [
+                {"tag": "beta"},
+                {"not": {"attribute": {"name": "plan", "equals": "free"}}},
+                {"subscribed_after": "2025-01-01T00:00:00Z"},
+            ]
+        }))
+        .unwrap();
+
+        let Segment::All(conditions) = &segment else {
+            panic!("expected an `all` segment, got {segment:?}");
+        };
+        assert_eq!(conditions.len(), 3);
+        assert_ok!(segment.validate());
+    }
+
+    #[test]
+    fn invalid_tags_are_rejected() {
+        let parsed =
+            serde_json::from_value::<Segment>(json!({"tag": "two words"}));
+        assert_err!(parsed);
+    }
+
+    #[test]
+    fn unknown_conditions_are_rejected() {
+        let parsed =
+            serde_json::from_value::<Segment>(json!({"email_like": "%@x"}));
+        assert_err!(parsed);
+    }
+
+    #[test]
+    fn deeply_nested_segments_are_rejected() {
+        let mut segment = Segment::All(vec![]);
+        for _ in 0..8 {
+            segment = Segment::Not(Box::new(segment));
+        }
+        assert_err!(segment.validate());
+    }
+
+    #[test]
+    fn segments_with_too_many_conditions_are_rejected() {
+        let tag = serde_json::from_value::<Segment>(json!({"tag": "beta"}));
+        let segment = Segment::Any(vec![tag.unwrap(); 100]);
+        assert_err!(segment.validate());
+    }
+}
@@ -1,19 +1,43 @@
 use std::fmt;
+use std::str::FromStr;
 
 use serde::Deserialize;
 
+use crate::domain::mailing_list::ListSlug;
+use crate::domain::subscriber_attributes::SubscriberAttributes;
 use crate::domain::subscriber_email::SubscriberEmail;
 use crate::domain::subscriber_name::SubscriberName;
+use crate::domain::subscriber_tag::SubscriberTag;
 
 #[derive(Deserialize, Debug)]
 pub struct Subscriber {
     pub name: SubscriberName,
     pub email: SubscriberEmail,
+    /// The lists to join, each confirmed by the same email
+    pub lists: Vec<ListSlug>,
+    pub tags: Vec<SubscriberTag>,
+    pub attributes: SubscriberAttributes,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SubscriberStatus {
     Confirmed,
     PendingConfirmation,
+    Unsubscribed,
+    /// The provider reported a hard bounce for the address
+    Bounced,
+    /// The subscriber marked one of our emails as spam
+    Complained,
+}
+
+impl SubscriberStatus {
+    /// Statuses whose subscribers are allowed to receive newsletters,
+    /// i.e. the default audience of a published issue.
+    pub const DELIVERABLE: &'static [Self] = &[Self::Confirmed];
+
+    pub fn to_strings(statuses: &[Self]) -> Vec<String> {
+        statuses.iter().map(ToString::to_string).collect()
+    }
 }
 
 impl fmt::Display for SubscriberStatus {
@@ -21,6 +45,47 @@ impl fmt::Display for SubscriberStatus {
         match self {
             Self::Confirmed => write!(f, "confirmed"),
             Self::PendingConfirmation => write!(f, "pending_confirmation"),
+            Self::Unsubscribed => write!(f, "unsubscribed"),
+            Self::Bounced => write!(f, "bounced"),
+            Self::Complained => write!(f, "complained"),
+        }
+    }
+}
+
+impl FromStr for SubscriberStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "confirmed" => Ok(Self::Confirmed),
+            "pending_confirmation" => Ok(Self::PendingConfirmation),
+            "unsubscribed" => Ok(Self::Unsubscribed),
+            "bounced" => Ok(Self::Bounced),
+            "complained" => Ok(Self::Complained),
+            other => Err(format!("{other} is not a valid subscriber status")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SubscriberStatus;
+
+    #[test]
+    fn status_round_trips_through_its_string_form() {
+        for status in [
+            SubscriberStatus::Confirmed,
+            SubscriberStatus::PendingConfirmation,
+            SubscriberStatus::Unsubscribed,
+            SubscriberStatus::Bounced,
+            SubscriberStatus::Complained,
+        ] {
+            assert_eq!(status.to_string().parse(), Ok(status));
         }
     }
+
+    #[test]
+    fn unknown_status_is_rejected() {
+        assert!("deleted".parse::<SubscriberStatus>().is_err());
+    }
 }
@@ -0,0 +1,97 @@
+use serde_json::{Map, Value};
+
+const MAX_ATTRIBUTES: usize = 50;
+const MAX_NAME_LENGTH: usize = 64;
+/// Size of the serialized object, in bytes
+const MAX_SIZE: usize = 4096;
+
+/// Free-form JSON values attached to a subscriber, e.g. `{"plan": "pro"}`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SubscriberAttributes(Map<String, Value>);
+
+impl SubscriberAttributes {
+    pub fn to_json(&self) -> String {
+        Value::Object(self.0.clone()).to_string()
+    }
+}
+
+impl TryFrom<Map<String, Value>> for SubscriberAttributes {
+    type Error = String;
+
+    fn try_from(value: Map<String, Value>) -> Result<Self, Self::Error> {
+        if value.len() > MAX_ATTRIBUTES {
+            return Err(format!(
+                "A subscriber has at most {MAX_ATTRIBUTES} attributes"
+            ));
+        }
+        if let Some(name) = value
+            .keys()
+            .find(|name| name.is_empty() || name.len() > MAX_NAME_LENGTH)
+        {
+            return Err(format!("{name:?} is not a valid attribute name"));
+        }
+        let attributes = Self(value);
+        if attributes.to_json().len() > MAX_SIZE {
+            return Err(format!(
+                "Attributes must not exceed {MAX_SIZE} bytes once serialized"
+            ));
+        }
+
+        Ok(attributes)
+    }
+}
+
+impl AsRef<Map<String, Value>> for SubscriberAttributes {
+    fn as_ref(&self) -> &Map<String, Value> {
+        &self.0
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for SubscriberAttributes {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let map = Map::deserialize(deserializer)?;
+        map.try_into().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SubscriberAttributes;
+    use claim::{assert_err, assert_ok};
+    use serde_json::{Map, Value, json};
+
+    fn object(value: Value) -> Map<String, Value> {
+        value.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn nested_values_are_valid() {
+        let attributes = object(json!({
+            "plan": "pro",
+            "seats": 3,
+            "address": { "country": "FR" },
+        }));
+        assert_ok!(SubscriberAttributes::try_from(attributes));
+    }
+
+    #[test]
+    fn empty_attribute_names_are_rejected() {
+        assert_err!(SubscriberAttributes::try_from(object(json!({"": 1}))));
+    }
+
+    #[test]
+    fn too_many_attributes_are_rejected() {
+        let attributes: Map<String, Value> =
+            (0..51).map(|i| (i.to_string(), json!(i))).collect();
+        assert_err!(SubscriberAttributes::try_from(attributes));
+    }
+
+    #[test]
+    fn oversized_attributes_are_rejected() {
+        let attributes = object(json!({ "bio": "a".repeat(4096) }));
+        assert_err!(SubscriberAttributes::try_from(attributes));
+    }
+}
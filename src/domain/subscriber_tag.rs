@@ -0,0 +1,79 @@
+/// A label put on subscribers to target them, e.g. `early-adopter`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SubscriberTag(String);
+
+impl TryFrom<String> for SubscriberTag {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let is_too_long = value.chars().count() > 64;
+        let has_forbidden_characters = value
+            .chars()
+            .any(|c| c.is_whitespace() || c.is_control() || c == ',');
+
+        if value.is_empty() || is_too_long || has_forbidden_characters {
+            return Err(format!("{value:?} is not a valid tag"));
+        }
+
+        Ok(Self(value))
+    }
+}
+
+impl AsRef<str> for SubscriberTag {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for SubscriberTag {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for SubscriberTag {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.try_into().map_err(serde::de::Error::custom)
+    }
+}
+
+impl serde::Serialize for SubscriberTag {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SubscriberTag;
+    use claim::{assert_err, assert_ok};
+
+    #[test]
+    fn a_64_character_tag_is_valid() {
+        assert_ok!(SubscriberTag::try_from("é".repeat(64)));
+    }
+
+    #[test]
+    fn a_tag_longer_than_64_characters_is_rejected() {
+        assert_err!(SubscriberTag::try_from("a".repeat(65)));
+    }
+
+    #[test]
+    fn empty_tag_is_rejected() {
+        assert_err!(SubscriberTag::try_from(String::new()));
+    }
+
+    #[test]
+    fn tags_with_whitespace_or_commas_are_rejected() {
+        for tag in ["early adopter", "vip\n", "a,b"] {
+            assert_err!(SubscriberTag::try_from(tag.to_string()));
+        }
+    }
+}
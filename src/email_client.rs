@@ -1,47 +1,111 @@
+mod file_sink;
+mod postmark;
+mod smtp;
+
+pub use file_sink::FileSinkTransport;
+pub use postmark::PostmarkTransport;
+pub use smtp::SmtpTransport;
+
+use std::sync::Arc;
+
+use anyhow::Context;
+use lettre::Message;
+use lettre::message::header::{HeaderName, HeaderValue};
+use lettre::message::{Mailbox, MultiPart};
+
+use crate::configuration::BackoffPolicy;
 use crate::domain::subscriber_email::SubscriberEmail;
-use reqwest::{Client, Url};
-use secrecy::{ExposeSecret, SecretString};
-use serde::Serialize;
+use crate::routers::error_chain_fmt;
+
+/// Why an email could not be sent, as far as retrying is concerned.
+#[derive(thiserror::Error)]
+pub enum SendEmailError {
+    /// The provider may accept the same email later, e.g. on timeouts,
+    /// 5xx responses or rate limiting.
+    #[error("failed to send the email, it can be retried")]
+    Transient(#[source] anyhow::Error),
+    /// The provider will never accept this email, e.g. because the
+    /// recipient is inactive or the address is invalid.
+    #[error("the email was rejected permanently")]
+    Permanent(#[source] anyhow::Error),
+}
 
-pub struct EmailClient {
-    http_client: Client,
-    base_url: String,
-    sender: SubscriberEmail,
-    server_token: SecretString,
-    pub retries_limit: u16,
-    pub retry_wait_seconds: u16,
+impl SendEmailError {
+    pub fn is_permanent(&self) -> bool {
+        matches!(self, Self::Permanent(_))
+    }
 }
 
-#[derive(Serialize)]
-#[serde(rename_all = "PascalCase")]
-struct SendEmailRequest<'a> {
-    from: &'a str,
-    to: &'a str,
-    subject: &'a str,
-    text_body: &'a str,
-    html_body: &'a str,
+impl std::fmt::Debug for SendEmailError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+/// An outgoing email, independent of the transport that delivers it.
+pub struct Email<'a> {
+    pub recipient: &'a SubscriberEmail,
+    pub subject: &'a str,
+    pub text_body: &'a str,
+    pub html_body: &'a str,
+    /// When set, the email carries RFC 8058 one-click unsubscribe headers.
+    pub list_unsubscribe_url: Option<&'a str>,
+}
+
+impl Email<'_> {
+    fn list_unsubscribe_headers(&self) -> Vec<(&'static str, String)> {
+        match self.list_unsubscribe_url {
+            Some(unsubscribe_url) => vec![
+                ("List-Unsubscribe", format!("<{unsubscribe_url}>")),
+                ("List-Unsubscribe-Post", "List-Unsubscribe=One-Click".into()),
+            ],
+            None => vec![],
+        }
+    }
+}
+
+#[async_trait::async_trait]
+pub trait EmailTransport: Send + Sync {
+    async fn send_email(&self, email: &Email<'_>)
+    -> Result<(), SendEmailError>;
+
+    /// Sends several emails at once. The outer error means nothing is known
+    /// about the batch; otherwise there is one result per email, in order.
+    ///
+    /// The default sends them one by one, transports with a batch API
+    /// should override it.
+    async fn send_email_batch(
+        &self,
+        emails: &[Email<'_>],
+    ) -> Result<Vec<Result<(), SendEmailError>>, SendEmailError> {
+        let mut results = Vec::with_capacity(emails.len());
+        for email in emails {
+            results.push(self.send_email(email).await);
+        }
+
+        Ok(results)
+    }
+}
+
+/// What the rest of the application sends emails through: a transport
+/// picked by configuration, plus the retry settings the workers honour.
+#[derive(Clone)]
+pub struct EmailClient {
+    transport: Arc<dyn EmailTransport>,
+    pub retry_policy: BackoffPolicy,
+    pub batch_size: u16,
 }
 
 impl EmailClient {
     pub fn new(
-        base_url: String,
-        sender: SubscriberEmail,
-        server_token: SecretString,
-        timeout_milliseconds: u64,
-        retries_limit: u16,
-        retry_wait_seconds: u16,
+        transport: Arc<dyn EmailTransport>,
+        retry_policy: BackoffPolicy,
+        batch_size: u16,
     ) -> Self {
-        let http_client = Client::builder()
-            .timeout(std::time::Duration::from_millis(timeout_milliseconds))
-            .build()
-            .unwrap();
         Self {
-            http_client,
-            base_url,
-            sender,
-            server_token,
-            retries_limit,
-            retry_wait_seconds,
+            transport,
+            retry_policy,
+            batch_size,
         }
     }
 
@@ -51,136 +115,68 @@ impl EmailClient {
         subject: &str,
         raw_content: &str,
         http_content: &str,
-    ) -> Result<(), reqwest::Error> {
-        let base_url = Url::parse(self.base_url.as_str()).unwrap();
-        let url = base_url.join("email").unwrap();
-
-        let request = SendEmailRequest {
-            from: self.sender.as_ref(),
-            to: recipient.as_ref(),
+        list_unsubscribe_url: Option<&str>,
+    ) -> Result<(), SendEmailError> {
+        let email = Email {
+            recipient,
             subject,
             text_body: raw_content,
             html_body: http_content,
+            list_unsubscribe_url,
         };
 
-        let builder = self
-            .http_client
-            .post(url)
-            .header("X-Server-Token", self.server_token.expose_secret())
-            .json(&request);
-        let response = builder.send().await?;
-        response.error_for_status()?;
-
-        Ok(())
+        self.transport.send_email(&email).await
     }
-}
 
-#[cfg(test)]
-mod tests {
-
-    use super::*;
-
-    use claim::assert_err;
-    use fake::faker::internet::en::SafeEmail;
-    use fake::faker::lorem::en::{Paragraph, Sentence};
-    use fake::{Fake, Faker};
-    use wiremock::matchers::{any, header, header_exists, method, path};
-    use wiremock::{Match, Mock, MockServer, ResponseTemplate};
-
-    struct SendEmailRequestMatch;
-    impl Match for SendEmailRequestMatch {
-        fn matches(&self, request: &wiremock::Request) -> bool {
-            let result: Result<serde_json::Value, _> =
-                serde_json::from_slice(&request.body);
-            if let Ok(body) = result {
-                body.get("From").is_some()
-                    && body.get("To").is_some()
-                    && body.get("Subject").is_some()
-                    && body.get("TextBody").is_some()
-                    && body.get("HtmlBody").is_some()
-            } else {
-                false
-            }
+    pub async fn send_email_batch(
+        &self,
+        emails: &[Email<'_>],
+    ) -> Result<Vec<Result<(), SendEmailError>>, SendEmailError> {
+        let results = self.transport.send_email_batch(emails).await?;
+        if results.len() != emails.len() {
+            return Err(SendEmailError::Transient(anyhow::anyhow!(
+                "Got {} results for a batch of {} emails",
+                results.len(),
+                emails.len()
+            )));
         }
-    }
-
-    fn email() -> SubscriberEmail {
-        SubscriberEmail::try_from(SafeEmail().fake::<String>()).unwrap()
-    }
-
-    fn subject() -> String {
-        Sentence(4..8).fake()
-    }
-
-    fn content() -> String {
-        Paragraph(1..3).fake()
-    }
-
-    fn email_client(uri: String) -> EmailClient {
-        EmailClient::new(
-            uri,
-            email(),
-            SecretString::from(Faker.fake::<String>()),
-            10_000,
-            5,
-            60,
-        )
-    }
 
-    #[tokio::test]
-    async fn test_send_valid_email_request() {
-        let mock_server = MockServer::start().await;
-
-        let email_client = email_client(mock_server.uri());
-
-        Mock::given(header_exists("X-Server-Token"))
-            .and(header("Content-Type", "application/json"))
-            .and(path("/email"))
-            .and(method("POST"))
-            .and(SendEmailRequestMatch)
-            .respond_with(ResponseTemplate::new(200))
-            .expect(1)
-            .mount(&mock_server)
-            .await;
-
-        let _ = email_client
-            .send_email(&email(), &subject(), &content(), &content())
-            .await;
+        Ok(results)
     }
+}
 
-    #[tokio::test]
-    async fn test_failed_if_return_500_response() {
-        let mock_server = MockServer::start().await;
-        let email_client = email_client(mock_server.uri());
-
-        Mock::given(any())
-            .respond_with(ResponseTemplate::new(500))
-            .mount(&mock_server)
-            .await;
-
-        let response = email_client
-            .send_email(&email(), &subject(), &content(), &content())
-            .await;
-
-        assert_err!(response);
+/// Builds the MIME message shared by the transports that speak raw email
+/// rather than a provider's HTTP API.
+fn mime_message(
+    sender: &SubscriberEmail,
+    email: &Email<'_>,
+) -> Result<Message, SendEmailError> {
+    let from: Mailbox = sender
+        .as_ref()
+        .parse()
+        .context("Failed to parse the sender address")
+        .map_err(SendEmailError::Transient)?;
+    let to: Mailbox = email
+        .recipient
+        .as_ref()
+        .parse()
+        .context("Failed to parse the recipient address")
+        .map_err(SendEmailError::Permanent)?;
+
+    let mut builder =
+        Message::builder().from(from).to(to).subject(email.subject);
+    for (name, value) in email.list_unsubscribe_headers() {
+        builder = builder.raw_header(HeaderValue::new(
+            HeaderName::new_from_ascii_str(name),
+            value,
+        ));
     }
 
-    #[tokio::test]
-    async fn send_email_times_out_if_the_server_takes_too_long() {
-        let mock_server = MockServer::start().await;
-        let email_client = email_client(mock_server.uri());
-
-        Mock::given(any())
-            .respond_with(
-                ResponseTemplate::new(200)
-                    .set_delay(std::time::Duration::from_secs(180)),
-            )
-            .mount(&mock_server)
-            .await;
-        let response = email_client
-            .send_email(&email(), &subject(), &content(), &content())
-            .await;
-
-        assert_err!(response);
-    }
+    builder
+        .multipart(MultiPart::alternative_plain_html(
+            email.text_body.to_owned(),
+            email.html_body.to_owned(),
+        ))
+        .context("Failed to build the email message")
+        .map_err(SendEmailError::Transient)
 }
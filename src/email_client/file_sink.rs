@@ -0,0 +1,88 @@
+use std::path::PathBuf;
+
+use anyhow::Context;
+use lettre::{AsyncFileTransport, AsyncTransport, Tokio1Executor};
+
+use crate::domain::subscriber_email::SubscriberEmail;
+use crate::email_client::{
+    Email, EmailTransport, SendEmailError, mime_message,
+};
+
+/// Writes every email as an `.eml` file into a directory instead of
+/// sending it. Meant for local development.
+pub struct FileSinkTransport {
+    sink: AsyncFileTransport<Tokio1Executor>,
+    sender: SubscriberEmail,
+}
+
+impl FileSinkTransport {
+    pub fn new(
+        directory: PathBuf,
+        sender: SubscriberEmail,
+    ) -> Result<Self, anyhow::Error> {
+        std::fs::create_dir_all(&directory).with_context(|| {
+            format!("Failed to create email sink directory {directory:?}")
+        })?;
+
+        Ok(Self {
+            sink: AsyncFileTransport::new(directory),
+            sender,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailTransport for FileSinkTransport {
+    async fn send_email(
+        &self,
+        email: &Email<'_>,
+    ) -> Result<(), SendEmailError> {
+        let message = mime_message(&self.sender, email)?;
+        self.sink
+            .send(message)
+            .await
+            .context("Failed to write the email to the sink directory")
+            .map_err(SendEmailError::Transient)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn send_email_writes_an_eml_file() {
+        let directory =
+            std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let transport = FileSinkTransport::new(
+            directory.clone(),
+            "sender@example.com".parse().unwrap(),
+        )
+        .unwrap();
+
+        let recipient = "recipient@example.com".parse().unwrap();
+        let email = Email {
+            recipient: &recipient,
+            subject: "Hello",
+            text_body: "plain text",
+            html_body: "<p>html</p>",
+            list_unsubscribe_url: None,
+        };
+        transport.send_email(&email).await.unwrap();
+
+        let files: Vec<_> = std::fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].extension().unwrap(), "eml");
+
+        let eml = std::fs::read_to_string(&files[0]).unwrap();
+        assert!(eml.contains("To: recipient@example.com"));
+        assert!(eml.contains("Subject: Hello"));
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
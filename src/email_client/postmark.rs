@@ -0,0 +1,581 @@
+use anyhow::Context;
+use reqwest::{Client, Response, StatusCode, Url};
+use secrecy::{ExposeSecret, SecretString};
+use serde::{Deserialize, Serialize};
+
+use crate::domain::subscriber_email::SubscriberEmail;
+use crate::email_client::{Email, EmailTransport, SendEmailError};
+
+/// Postmark accepts at most this many messages per `/email/batch` call.
+const MAX_BATCH_SIZE: usize = 500;
+
+/// Postmark error codes that will never succeed for the same message:
+/// 300 is an invalid email request (e.g. a malformed address) and 406 an
+/// inactive recipient (hard bounce, spam complaint or manual suppression).
+/// Any other code is an account or API problem worth retrying.
+///
+/// https://postmarkapp.com/developer/api/overview#error-codes
+const PERMANENT_ERROR_CODES: &[i64] = &[300, 406];
+
+/// Sends emails through Postmark's `/email` and `/email/batch` HTTP APIs.
+pub struct PostmarkTransport {
+    http_client: Client,
+    base_url: String,
+    sender: SubscriberEmail,
+    server_token: SecretString,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendEmailRequest<'a> {
+    from: &'a str,
+    to: &'a str,
+    subject: &'a str,
+    text_body: &'a str,
+    html_body: &'a str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    headers: Vec<EmailHeader>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct EmailHeader {
+    name: &'static str,
+    value: String,
+}
+
+/// The error body of `/email`, and one entry of the `/email/batch`
+/// response, in the order of the request.
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PostmarkResponse {
+    error_code: i64,
+    message: String,
+}
+
+impl PostmarkResponse {
+    fn into_result(self) -> Result<(), SendEmailError> {
+        if self.error_code == 0 {
+            return Ok(());
+        }
+
+        let e = anyhow::anyhow!(
+            "Postmark rejected the email ({}): {}",
+            self.error_code,
+            self.message
+        );
+        if PERMANENT_ERROR_CODES.contains(&self.error_code) {
+            Err(SendEmailError::Permanent(e))
+        } else {
+            Err(SendEmailError::Transient(e))
+        }
+    }
+}
+
+/// Timeouts and connection failures are worth retrying.
+fn request_error(e: reqwest::Error) -> SendEmailError {
+    SendEmailError::Transient(
+        anyhow::Error::new(e).context("Failed to reach Postmark"),
+    )
+}
+
+/// Rate limiting and server errors are transient; otherwise Postmark's
+/// error code decides.
+async fn response_error(response: Response) -> SendEmailError {
+    let status = response.status();
+    let body = response.json::<PostmarkResponse>().await.ok();
+
+    match body {
+        Some(body)
+            if status != StatusCode::TOO_MANY_REQUESTS
+                && !status.is_server_error() =>
+        {
+            body.into_result().err().unwrap_or_else(|| {
+                SendEmailError::Transient(anyhow::anyhow!(
+                    "Postmark answered {status} without an error code"
+                ))
+            })
+        }
+        _ => SendEmailError::Transient(anyhow::anyhow!(
+            "Postmark answered {status}"
+        )),
+    }
+}
+
+impl PostmarkTransport {
+    pub fn new(
+        base_url: String,
+        sender: SubscriberEmail,
+        server_token: SecretString,
+        timeout_milliseconds: u64,
+    ) -> Self {
+        let http_client = Client::builder()
+            .timeout(std::time::Duration::from_millis(timeout_milliseconds))
+            .build()
+            .unwrap();
+        Self {
+            http_client,
+            base_url,
+            sender,
+            server_token,
+        }
+    }
+
+    fn url(&self, path: &str) -> Result<Url, SendEmailError> {
+        Url::parse(self.base_url.as_str())
+            .and_then(|base_url| base_url.join(path))
+            .context("Invalid Postmark base url")
+            .map_err(SendEmailError::Transient)
+    }
+
+    fn request<'a>(&'a self, email: &'a Email<'_>) -> SendEmailRequest<'a> {
+        let headers = email
+            .list_unsubscribe_headers()
+            .into_iter()
+            .map(|(name, value)| EmailHeader { name, value })
+            .collect();
+
+        SendEmailRequest {
+            from: self.sender.as_ref(),
+            to: email.recipient.as_ref(),
+            subject: email.subject,
+            text_body: email.text_body,
+            html_body: email.html_body,
+            headers,
+        }
+    }
+
+    async fn send_chunk(
+        &self,
+        emails: &[Email<'_>],
+    ) -> Result<Vec<Result<(), SendEmailError>>, SendEmailError> {
+        let requests: Vec<_> =
+            emails.iter().map(|email| self.request(email)).collect();
+
+        let response = self
+            .http_client
+            .post(self.url("email/batch")?)
+            .header("X-Server-Token", self.server_token.expose_secret())
+            .json(&requests)
+            .send()
+            .await
+            .map_err(request_error)?;
+        if !response.status().is_success() {
+            return Err(response_error(response).await);
+        }
+
+        let entries: Vec<PostmarkResponse> = response
+            .json()
+            .await
+            .context("Failed to parse the batch response")
+            .map_err(SendEmailError::Transient)?;
+        if entries.len() != emails.len() {
+            return Err(SendEmailError::Transient(anyhow::anyhow!(
+                "Postmark answered {} entries for a batch of {} emails",
+                entries.len(),
+                emails.len()
+            )));
+        }
+
+        Ok(entries
+            .into_iter()
+            .map(PostmarkResponse::into_result)
+            .collect())
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailTransport for PostmarkTransport {
+    async fn send_email(
+        &self,
+        email: &Email<'_>,
+    ) -> Result<(), SendEmailError> {
+        let builder = self
+            .http_client
+            .post(self.url("email")?)
+            .header("X-Server-Token", self.server_token.expose_secret())
+            .json(&self.request(email));
+        let response = builder.send().await.map_err(request_error)?;
+        if !response.status().is_success() {
+            return Err(response_error(response).await);
+        }
+
+        Ok(())
+    }
+
+    /// Once a chunk has gone out, a later failing chunk must not fail the
+    /// whole batch, or the emails already sent would be sent again: its
+    /// messages, and those of the chunks not tried, fail on their own.
+    async fn send_email_batch(
+        &self,
+        emails: &[Email<'_>],
+    ) -> Result<Vec<Result<(), SendEmailError>>, SendEmailError> {
+        let mut results = Vec::with_capacity(emails.len());
+        for chunk in emails.chunks(MAX_BATCH_SIZE) {
+            match self.send_chunk(chunk).await {
+                Ok(chunk_results) => results.extend(chunk_results),
+                Err(e) if results.is_empty() => return Err(e),
+                Err(e) => {
+                    let permanent = e.is_permanent();
+                    let reason = format!("{:#}", anyhow::Error::from(e));
+                    results.extend(chunk.iter().map(|_| {
+                        let e = anyhow::anyhow!("The chunk failed: {reason}");
+                        if permanent {
+                            Err(SendEmailError::Permanent(e))
+                        } else {
+                            Err(SendEmailError::Transient(e))
+                        }
+                    }));
+                    results.extend(emails[results.len()..].iter().map(|_| {
+                        Err(SendEmailError::Transient(anyhow::anyhow!(
+                            "Not sent after an earlier chunk failed: {reason}"
+                        )))
+                    }));
+                    break;
+                }
+            }
+        }
+
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::configuration::BackoffPolicy;
+    use crate::email_client::EmailClient;
+    use std::sync::Arc;
+
+    use claim::{assert_err, assert_ok};
+    use fake::faker::internet::en::SafeEmail;
+    use fake::faker::lorem::en::{Paragraph, Sentence};
+    use fake::{Fake, Faker};
+    use wiremock::matchers::{any, header, header_exists, method, path};
+    use wiremock::{Match, Mock, MockServer, ResponseTemplate};
+
+    struct SendEmailRequestMatch;
+    impl Match for SendEmailRequestMatch {
+        fn matches(&self, request: &wiremock::Request) -> bool {
+            let result: Result<serde_json::Value, _> =
+                serde_json::from_slice(&request.body);
+            if let Ok(body) = result {
+                body.get("From").is_some()
+                    && body.get("To").is_some()
+                    && body.get("Subject").is_some()
+                    && body.get("TextBody").is_some()
+                    && body.get("HtmlBody").is_some()
+            } else {
+                false
+            }
+        }
+    }
+
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::try_from(SafeEmail().fake::<String>()).unwrap()
+    }
+
+    fn subject() -> String {
+        Sentence(4..8).fake()
+    }
+
+    fn content() -> String {
+        Paragraph(1..3).fake()
+    }
+
+    fn email_client(uri: String) -> EmailClient {
+        let transport = PostmarkTransport::new(
+            uri,
+            email(),
+            SecretString::from(Faker.fake::<String>()),
+            10_000,
+        );
+        EmailClient::new(Arc::new(transport), BackoffPolicy::default(), 100)
+    }
+
+    #[tokio::test]
+    async fn test_send_valid_email_request() {
+        let mock_server = MockServer::start().await;
+
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(header_exists("X-Server-Token"))
+            .and(header("Content-Type", "application/json"))
+            .and(path("/email"))
+            .and(method("POST"))
+            .and(SendEmailRequestMatch)
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let _ = email_client
+            .send_email(&email(), &subject(), &content(), &content(), None)
+            .await;
+    }
+
+    struct ListUnsubscribeHeadersMatch;
+    impl Match for ListUnsubscribeHeadersMatch {
+        fn matches(&self, request: &wiremock::Request) -> bool {
+            let body: serde_json::Value =
+                serde_json::from_slice(&request.body).unwrap();
+            body["Headers"]
+                == serde_json::json!([
+                    {
+                        "Name": "List-Unsubscribe",
+                        "Value": "<https://example.com/unsubscribe>"
+                    },
+                    {
+                        "Name": "List-Unsubscribe-Post",
+                        "Value": "List-Unsubscribe=One-Click"
+                    }
+                ])
+        }
+    }
+
+    #[tokio::test]
+    async fn send_email_emits_list_unsubscribe_headers() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(path("/email"))
+            .and(method("POST"))
+            .and(ListUnsubscribeHeadersMatch)
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let _ = email_client
+            .send_email(
+                &email(),
+                &subject(),
+                &content(),
+                &content(),
+                Some("https://example.com/unsubscribe"),
+            )
+            .await;
+    }
+
+    #[tokio::test]
+    async fn test_failed_if_return_500_response() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&mock_server)
+            .await;
+
+        let response = email_client
+            .send_email(&email(), &subject(), &content(), &content(), None)
+            .await;
+
+        assert!(!response.unwrap_err().is_permanent());
+    }
+
+    #[tokio::test]
+    async fn send_email_times_out_if_the_server_takes_too_long() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_delay(std::time::Duration::from_secs(180)),
+            )
+            .mount(&mock_server)
+            .await;
+        let response = email_client
+            .send_email(&email(), &subject(), &content(), &content(), None)
+            .await;
+
+        assert!(!response.unwrap_err().is_permanent());
+    }
+
+    fn batch(recipients: &[SubscriberEmail]) -> Vec<Email<'_>> {
+        recipients
+            .iter()
+            .map(|recipient| Email {
+                recipient,
+                subject: "Hello",
+                text_body: "plain text",
+                html_body: "<p>html</p>",
+                list_unsubscribe_url: None,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn send_email_batch_reports_the_outcome_of_each_message() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let recipients = vec![email(), email()];
+
+        Mock::given(header_exists("X-Server-Token"))
+            .and(path("/email/batch"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(
+                serde_json::json!([
+                    { "ErrorCode": 0, "Message": "OK" },
+                    { "ErrorCode": 406, "Message": "Inactive recipient" },
+                ]),
+            ))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let results = email_client
+            .send_email_batch(&batch(&recipients))
+            .await
+            .unwrap();
+
+        let request = &mock_server.received_requests().await.unwrap()[0];
+        let body: serde_json::Value =
+            serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(body[0]["To"], recipients[0].as_ref());
+        assert_eq!(body[1]["To"], recipients[1].as_ref());
+
+        assert_eq!(results.len(), 2);
+        assert_ok!(&results[0]);
+        assert!(results[1].as_ref().unwrap_err().is_permanent());
+    }
+
+    #[tokio::test]
+    async fn send_email_batch_fails_if_the_response_does_not_match() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let recipients = vec![email(), email()];
+
+        Mock::given(path("/email/batch"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(
+                serde_json::json!([{ "ErrorCode": 0, "Message": "OK" }]),
+            ))
+            .mount(&mock_server)
+            .await;
+
+        let results = email_client.send_email_batch(&batch(&recipients)).await;
+
+        assert_err!(results);
+    }
+
+    #[tokio::test]
+    async fn send_email_batch_fails_if_return_500_response() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let recipients = vec![email()];
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&mock_server)
+            .await;
+
+        let results = email_client.send_email_batch(&batch(&recipients)).await;
+
+        assert_err!(results);
+    }
+
+    #[tokio::test]
+    async fn a_failing_chunk_keeps_the_results_of_the_chunks_sent() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let recipients: Vec<_> =
+            (0..MAX_BATCH_SIZE * 2 + 1).map(|_| email()).collect();
+        let accepted = vec![
+            serde_json::json!({ "ErrorCode": 0, "Message": "OK" });
+            MAX_BATCH_SIZE
+        ];
+
+        Mock::given(path("/email/batch"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(accepted))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(path("/email/batch"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let results = email_client
+            .send_email_batch(&batch(&recipients))
+            .await
+            .unwrap();
+
+        assert_eq!(results.len(), recipients.len());
+        assert!(results[..MAX_BATCH_SIZE].iter().all(Result::is_ok));
+        assert!(
+            results[MAX_BATCH_SIZE..]
+                .iter()
+                .all(|r| { r.as_ref().is_err_and(|e| !e.is_permanent()) })
+        );
+    }
+
+    #[tokio::test]
+    async fn inactive_recipients_are_rejected_permanently() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(422).set_body_json(
+                serde_json::json!({
+                    "ErrorCode": 406,
+                    "Message": "You tried to send to an inactive recipient"
+                }),
+            ))
+            .mount(&mock_server)
+            .await;
+
+        let response = email_client
+            .send_email(&email(), &subject(), &content(), &content(), None)
+            .await;
+
+        assert!(response.unwrap_err().is_permanent());
+    }
+
+    #[tokio::test]
+    async fn account_errors_are_transient() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(422).set_body_json(
+                serde_json::json!({
+                    "ErrorCode": 405,
+                    "Message": "Not allowed to send"
+                }),
+            ))
+            .mount(&mock_server)
+            .await;
+
+        let response = email_client
+            .send_email(&email(), &subject(), &content(), &content(), None)
+            .await;
+
+        assert!(!response.unwrap_err().is_permanent());
+    }
+
+    #[tokio::test]
+    async fn rate_limiting_is_transient() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(429).set_body_json(
+                serde_json::json!({
+                    "ErrorCode": 406,
+                    "Message": "Rate limit exceeded"
+                }),
+            ))
+            .mount(&mock_server)
+            .await;
+
+        let response = email_client
+            .send_email(&email(), &subject(), &content(), &content(), None)
+            .await;
+
+        assert!(!response.unwrap_err().is_permanent());
+    }
+}
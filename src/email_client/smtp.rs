@@ -0,0 +1,249 @@
+use std::time::Duration;
+
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+use secrecy::ExposeSecret;
+
+use crate::configuration::{SmtpSettings, SmtpTls};
+use crate::domain::subscriber_email::SubscriberEmail;
+use crate::email_client::{
+    Email, EmailTransport, SendEmailError, mime_message,
+};
+
+/// Sends emails to an SMTP relay.
+pub struct SmtpTransport {
+    mailer: AsyncSmtpTransport<Tokio1Executor>,
+    sender: SubscriberEmail,
+}
+
+impl SmtpTransport {
+    pub fn new(
+        settings: SmtpSettings,
+        sender: SubscriberEmail,
+        timeout_milliseconds: u64,
+    ) -> Result<Self, anyhow::Error> {
+        let builder = match settings.tls {
+            SmtpTls::Starttls => {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(
+                    &settings.host,
+                )?
+            }
+            SmtpTls::Tls => {
+                AsyncSmtpTransport::<Tokio1Executor>::relay(&settings.host)?
+            }
+            SmtpTls::None => {
+                AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(
+                    &settings.host,
+                )
+            }
+        };
+        let mut builder = builder
+            .port(settings.port)
+            .timeout(Some(Duration::from_millis(timeout_milliseconds)));
+
+        match (settings.username, settings.password) {
+            (Some(username), Some(password)) => {
+                builder = builder.credentials(Credentials::new(
+                    username,
+                    password.expose_secret().to_owned(),
+                ));
+            }
+            (None, None) => {}
+            _ => anyhow::bail!(
+                "SMTP username and password must be provided together"
+            ),
+        }
+
+        Ok(Self {
+            mailer: builder.build(),
+            sender,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailTransport for SmtpTransport {
+    async fn send_email(
+        &self,
+        email: &Email<'_>,
+    ) -> Result<(), SendEmailError> {
+        let message = mime_message(&self.sender, email)?;
+        self.mailer.send(message).await.map_err(|e| {
+            // 5xx replies are final, everything else (4xx replies, timeouts,
+            // connection errors) may succeed later
+            let permanent = e.is_permanent();
+            let e = anyhow::Error::new(e)
+                .context("The SMTP server did not accept the email");
+            if permanent {
+                SendEmailError::Permanent(e)
+            } else {
+                SendEmailError::Transient(e)
+            }
+        })?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use claim::{assert_err, assert_ok};
+    use secrecy::SecretString;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+    use tokio::task::JoinHandle;
+
+    /// A minimal SMTP server that accepts a single session and returns
+    /// every line the client sent. It never offers STARTTLS and answers
+    /// `RCPT TO` with `rcpt_reply`.
+    async fn smtp_stand_in(
+        rcpt_reply: &'static [u8],
+    ) -> (u16, JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let handle = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            let mut transcript = vec![];
+            let mut in_data = false;
+
+            writer.write_all(b"220 stand-in ESMTP\r\n").await.unwrap();
+            while let Ok(Some(line)) = lines.next_line().await {
+                transcript.push(line.clone());
+                let reply: &[u8] = if in_data {
+                    if line != "." {
+                        continue;
+                    }
+                    in_data = false;
+                    b"250 queued\r\n"
+                } else if line.starts_with("EHLO") {
+                    b"250-stand-in\r\n250 AUTH PLAIN LOGIN\r\n"
+                } else if line.starts_with("AUTH") {
+                    b"235 authenticated\r\n"
+                } else if line.starts_with("RCPT") {
+                    rcpt_reply
+                } else if line == "DATA" {
+                    in_data = true;
+                    b"354 go ahead\r\n"
+                } else if line == "QUIT" {
+                    writer.write_all(b"221 bye\r\n").await.unwrap();
+                    break;
+                } else {
+                    b"250 ok\r\n"
+                };
+                writer.write_all(reply).await.unwrap();
+            }
+
+            transcript
+        });
+
+        (port, handle)
+    }
+
+    fn settings(port: u16, tls: SmtpTls) -> SmtpSettings {
+        SmtpSettings {
+            host: "127.0.0.1".into(),
+            port,
+            tls,
+            username: Some("craft".into()),
+            password: Some(SecretString::from("secret")),
+        }
+    }
+
+    fn address(s: &str) -> SubscriberEmail {
+        s.parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn send_email_authenticates_and_delivers_the_message() {
+        let (port, stand_in) = smtp_stand_in(b"250 ok\r\n").await;
+        let transport = SmtpTransport::new(
+            settings(port, SmtpTls::None),
+            address("sender@example.com"),
+            10_000,
+        )
+        .unwrap();
+
+        let recipient = address("recipient@example.com");
+        let email = Email {
+            recipient: &recipient,
+            subject: "Hello",
+            text_body: "plain text",
+            html_body: "<p>html</p>",
+            list_unsubscribe_url: Some("https://example.com/unsubscribe"),
+        };
+        assert_ok!(transport.send_email(&email).await);
+        drop(transport);
+
+        let transcript = stand_in.await.unwrap();
+        // base64("\0craft\0secret")
+        assert!(transcript.contains(&"AUTH PLAIN AGNyYWZ0AHNlY3JldA==".into()));
+        assert!(transcript.contains(&"MAIL FROM:<sender@example.com>".into()));
+        assert!(transcript.contains(&"RCPT TO:<recipient@example.com>".into()));
+        assert!(transcript.contains(&"Subject: Hello".into()));
+        assert!(transcript.contains(
+            &"List-Unsubscribe: <https://example.com/unsubscribe>".into()
+        ));
+        assert!(transcript.contains(
+            &"List-Unsubscribe-Post: List-Unsubscribe=One-Click".into()
+        ));
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_if_starttls_is_required_but_not_offered() {
+        let (port, _stand_in) = smtp_stand_in(b"250 ok\r\n").await;
+        let transport = SmtpTransport::new(
+            settings(port, SmtpTls::Starttls),
+            address("sender@example.com"),
+            10_000,
+        )
+        .unwrap();
+
+        let recipient = address("recipient@example.com");
+        let email = Email {
+            recipient: &recipient,
+            subject: "Hello",
+            text_body: "plain text",
+            html_body: "<p>html</p>",
+            list_unsubscribe_url: None,
+        };
+        assert_err!(transport.send_email(&email).await);
+    }
+
+    #[test]
+    fn username_without_password_is_rejected() {
+        let mut settings = settings(25, SmtpTls::None);
+        settings.password = None;
+
+        let transport =
+            SmtpTransport::new(settings, address("sender@example.com"), 10_000);
+        assert!(transport.is_err());
+    }
+
+    #[tokio::test]
+    async fn rejected_recipients_are_permanent_failures() {
+        let (port, _stand_in) = smtp_stand_in(b"550 no such user\r\n").await;
+        let transport = SmtpTransport::new(
+            settings(port, SmtpTls::None),
+            address("sender@example.com"),
+            10_000,
+        )
+        .unwrap();
+
+        let recipient = address("recipient@example.com");
+        let email = Email {
+            recipient: &recipient,
+            subject: "Hello",
+            text_body: "plain text",
+            html_body: "<p>html</p>",
+            list_unsubscribe_url: None,
+        };
+        let response = transport.send_email(&email).await;
+
+        assert!(response.unwrap_err().is_permanent());
+    }
+}
@@ -1,6 +1,8 @@
 use craft::background_workers::{
+    email_outbox_worker::run_outbox_worker_until_stop,
     idempotency_expire_wroker::run_expire_clean_worker_until_stop,
     issue_delivery_worker::run_worker_until_stop as run_delivery_work_until_stop,
+    subscription_token_expire_worker::run_subscription_token_clean_worker_until_stop,
 };
 use std::fmt::{Debug, Display};
 use tokio::task::JoinError;
@@ -26,13 +28,19 @@ async fn main() -> anyhow::Result<()> {
 
     let worker_task =
         tokio::spawn(run_delivery_work_until_stop(settings.clone()));
+    let outbox_worker_task =
+        tokio::spawn(run_outbox_worker_until_stop(settings.clone()));
     let expire_worker_task =
-        tokio::spawn(run_expire_clean_worker_until_stop(settings));
+        tokio::spawn(run_expire_clean_worker_until_stop(settings.clone()));
+    let token_expire_worker_task =
+        tokio::spawn(run_subscription_token_clean_worker_until_stop(settings));
 
     tokio::select! {
         o = app_task => report_exit("API", o),
         o = worker_task => report_exit("Background worker: email delivery", o),
-        o = expire_worker_task=> report_exit("Background worker: clean expired idempotency", o)
+        o = outbox_worker_task => report_exit("Background worker: email outbox", o),
+        o = expire_worker_task=> report_exit("Background worker: clean expired idempotency", o),
+        o = token_expire_worker_task => report_exit("Background worker: clean expired subscription tokens", o)
     };
 
     Ok(())
@@ -2,10 +2,13 @@ mod admin;
 mod health_check;
 mod home;
 mod login;
+mod newsletters;
 pub mod session_state;
 mod subscriptions;
+mod webhooks;
 
 use std::sync::Arc;
+use std::time::Duration;
 
 use axum::extract::Request;
 use axum::middleware::{Next, from_fn};
@@ -17,6 +20,7 @@ use sqlx::{Pool, Postgres};
 use tower_http::trace::TraceLayer;
 
 use crate::authentication::reject_anonymous_users;
+use crate::configuration::WebhookSettings;
 use crate::{app_state::AppState, email_client::EmailClient};
 
 pub fn error_chain_fmt(
@@ -36,6 +40,9 @@ pub fn get_router(
     pool: Pool<Postgres>,
     email_client: EmailClient,
     base_url: String,
+    subscription_token_ttl: Duration,
+    data_request_token_ttl: Duration,
+    webhook_credentials: Option<WebhookSettings>,
     session_store: SessionStore<SessionRedisPool>,
 ) -> axum::Router {
     // we can pass EmailClient directly through wit_state
@@ -46,6 +53,9 @@ pub fn get_router(
         pool,
         email_client,
         base_url,
+        subscription_token_ttl,
+        data_request_token_ttl,
+        webhook_credentials,
     });
 
     let admin_router = admin::router().layer(from_fn(reject_anonymous_users));
@@ -53,8 +63,10 @@ pub fn get_router(
     axum::Router::new()
         .route("/health", get(health_check::health_check))
         .route("/", get(home::home))
+        .route("/newsletters/{issue_id}", get(newsletters::web_version))
         .merge(subscriptions::router())
         .merge(login::router())
+        .nest("/webhooks", webhooks::router())
         .nest("/admin", admin_router)
         .layer(SessionLayer::new(session_store))
         .layer(TraceLayer::new_for_http())
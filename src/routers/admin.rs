@@ -10,8 +10,40 @@ use logout::*;
 mod newsletters;
 use newsletters::*;
 
+mod delivery_failures;
+use delivery_failures::*;
+
+mod newsletter_issue;
+use newsletter_issue::*;
+
+mod newsletter_schedule;
+use newsletter_schedule::*;
+
+mod newsletter_drafts;
+use newsletter_drafts::*;
+
+mod delivery_control;
+use delivery_control::*;
+
+mod subscribers;
+pub(crate) use subscribers::delete_subscriber_rows;
+use subscribers::*;
+
+mod subscriber_import;
+use subscriber_import::*;
+
+mod subscriber_export;
+use subscriber_export::*;
+
+mod subscriber_profile;
+use subscriber_profile::*;
+
+mod lists;
+use lists::*;
+pub(crate) use lists::{ListLookupError, find_list_ids};
+
 use crate::app_state::AppState;
-use axum::routing::{get, post};
+use axum::routing::{delete, get, patch, post, put};
 use std::sync::Arc;
 
 pub fn router() -> axum::routing::Router<Arc<AppState>> {
@@ -20,5 +52,53 @@ pub fn router() -> axum::routing::Router<Arc<AppState>> {
         .route("/password", get(change_password_form))
         .route("/password", post(change_password))
         .route("/logout", post(logout))
+        .route("/lists", get(get_lists))
+        .route("/lists", post(create_list))
+        .route("/subscribers", get(list_subscribers))
+        .route("/subscribers/import", post(import_subscribers))
+        .route("/subscribers/export", get(export_subscribers))
+        .route("/subscribers/{subscriber_id}", get(get_subscriber))
+        .route("/subscribers/{subscriber_id}", delete(delete_subscriber))
+        .route(
+            "/subscribers/{subscriber_id}/confirm",
+            post(confirm_subscriber),
+        )
+        .route(
+            "/subscribers/{subscriber_id}/reactivate",
+            post(reactivate_subscriber),
+        )
+        .route(
+            "/subscribers/{subscriber_id}/unsubscribe",
+            post(unsubscribe_subscriber),
+        )
+        .route(
+            "/subscribers/{subscriber_id}/tags",
+            put(put_subscriber_tags),
+        )
+        .route(
+            "/subscribers/{subscriber_id}/attributes",
+            patch(patch_subscriber_attributes),
+        )
+        .route("/newsletters", get(list_newsletter_issues))
         .route("/newsletters", post(publish_newsletter))
+        .route("/newsletters/drafts", post(create_draft))
+        .route("/newsletters/{issue_id}", get(get_newsletter_issue))
+        .route("/newsletters/{issue_id}", put(update_draft))
+        .route("/newsletters/{issue_id}/test", post(send_test))
+        .route("/newsletters/{issue_id}/publish", post(publish_draft))
+        .route(
+            "/newsletters/{issue_id}/reschedule",
+            post(reschedule_newsletter_issue),
+        )
+        .route("/newsletters/{issue_id}/pause", post(pause_delivery))
+        .route("/newsletters/{issue_id}/resume", post(resume_delivery))
+        .route("/newsletters/{issue_id}/cancel", post(cancel_delivery))
+        .route(
+            "/newsletters/{issue_id}/failures",
+            get(list_delivery_failures),
+        )
+        .route(
+            "/newsletters/{issue_id}/failures/retry",
+            post(retry_delivery_failures),
+        )
 }
@@ -0,0 +1,176 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+};
+use sqlx::{Postgres, Transaction};
+use tracing::instrument;
+use uuid::Uuid;
+
+use super::newsletter_issue::missing_or_conflict;
+use crate::{
+    app_state::AppState, domain::newsletter_issue::IssueStatus, utils::AppError,
+};
+
+/// Issues that still have deliveries ahead of them.
+const IN_FLIGHT: &[IssueStatus] = &[
+    IssueStatus::Scheduled,
+    IssueStatus::Published,
+    IssueStatus::Paused,
+];
+
+/// Holds the remaining deliveries of an issue. A batch the worker already
+/// picked up still goes out.
+#[instrument(name = "Pause the delivery of an issue", skip(app_state))]
+pub(crate) async fn pause_delivery(
+    State(app_state): State<Arc<AppState>>,
+    Path(issue_id): Path<Uuid>,
+) -> Result<StatusCode, AppError> {
+    change_status(
+        &app_state,
+        issue_id,
+        &[IssueStatus::Scheduled, IssueStatus::Published],
+        IssueStatus::Paused,
+        "being delivered",
+    )
+    .await
+}
+
+#[instrument(name = "Resume the delivery of an issue", skip(app_state))]
+pub(crate) async fn resume_delivery(
+    State(app_state): State<Arc<AppState>>,
+    Path(issue_id): Path<Uuid>,
+) -> Result<StatusCode, AppError> {
+    change_status(
+        &app_state,
+        issue_id,
+        &[IssueStatus::Paused],
+        IssueStatus::Published,
+        "paused",
+    )
+    .await
+}
+
+/// Stops an issue for good: its remaining deliveries are dropped from the
+/// queue and counted as cancelled.
+#[instrument(name = "Cancel the delivery of an issue", skip(app_state))]
+pub(crate) async fn cancel_delivery(
+    State(app_state): State<Arc<AppState>>,
+    Path(issue_id): Path<Uuid>,
+) -> Result<StatusCode, AppError> {
+    change_status(
+        &app_state,
+        issue_id,
+        IN_FLIGHT,
+        IssueStatus::Cancelled,
+        "being delivered",
+    )
+    .await
+}
+
+async fn change_status(
+    app_state: &AppState,
+    issue_id: Uuid,
+    from: &[IssueStatus],
+    to: IssueStatus,
+    expected_state: &str,
+) -> Result<StatusCode, AppError> {
+    let mut tx = app_state
+        .pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    // The queue rows are locked before the issue row, in the order the
+    // worker locks them: a batch it holds is then counted as sent or failed
+    // before the purge goes on, rather than the two deadlocking.
+    let n_cancelled = if to == IssueStatus::Cancelled {
+        purge_deliveries(&mut tx, issue_id)
+            .await
+            .context("Failed to drop the remaining deliveries")?
+    } else {
+        0
+    };
+    let changed = update_status(&mut tx, issue_id, from, to, n_cancelled)
+        .await
+        .context("Failed to update the issue status")?;
+    if !changed {
+        return Err(missing_or_conflict(
+            &app_state.pool,
+            issue_id,
+            expected_state,
+        )
+        .await);
+    }
+    tx.commit()
+        .await
+        .context("Failed to commit the issue status")?;
+
+    Ok(StatusCode::OK)
+}
+
+/// A published issue only counts while some of its deliveries are queued,
+/// or were just purged by `n_cancelled`; once they are all out there is
+/// nothing left to pause or cancel.
+///
+/// Resuming puts an issue whose send time has not come yet back to
+/// scheduled, where it can still be rescheduled.
+#[instrument(skip(tx))]
+async fn update_status(
+    tx: &mut Transaction<'_, Postgres>,
+    issue_id: Uuid,
+    from: &[IssueStatus],
+    to: IssueStatus,
+    n_cancelled: i32,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET
+            status = CASE
+                WHEN $2 = $4 AND published_at > NOW() THEN $6
+                ELSE $2
+            END,
+            n_cancelled = n_cancelled + $5
+        WHERE
+            newsletter_issue_id = $1 AND
+            status = ANY($3) AND
+            (
+                status <> $4 OR
+                $5 > 0 OR
+                EXISTS (
+                    SELECT 1 FROM issue_delivery_queue
+                    WHERE newsletter_issue_id = $1
+                )
+            )
+        "#,
+        issue_id,
+        to.to_string(),
+        &IssueStatus::to_strings(from),
+        IssueStatus::Published.to_string(),
+        n_cancelled,
+        IssueStatus::Scheduled.to_string(),
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Drops the deliveries left in the queue, returning how many. Waits for
+/// the batch the worker may hold locked, which it then removes itself.
+#[instrument(skip(tx))]
+async fn purge_deliveries(
+    tx: &mut Transaction<'_, Postgres>,
+    issue_id: Uuid,
+) -> Result<i32, sqlx::Error> {
+    let result = sqlx::query!(
+        "DELETE FROM issue_delivery_queue WHERE newsletter_issue_id = $1",
+        issue_id,
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(i32::try_from(result.rows_affected()).unwrap_or(i32::MAX))
+}
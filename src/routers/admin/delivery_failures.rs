@@ -0,0 +1,150 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use time::OffsetDateTime;
+use tracing::instrument;
+use uuid::Uuid;
+
+use super::newsletter_issue::missing_or_conflict;
+use crate::{
+    app_state::AppState, domain::newsletter_issue::IssueStatus, utils::AppError,
+};
+
+#[derive(Serialize)]
+pub struct DeliveryFailure {
+    subscriber_email: String,
+    last_error: String,
+    n_attempts: i16,
+    #[serde(with = "time::serde::rfc3339")]
+    enqueued_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    failed_at: OffsetDateTime,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RetryBody {
+    subscriber_emails: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct RetryResponse {
+    requeued: u64,
+}
+
+#[instrument(name = "List failed deliveries of an issue", skip(app_state))]
+pub(crate) async fn list_delivery_failures(
+    State(app_state): State<Arc<AppState>>,
+    Path(issue_id): Path<Uuid>,
+) -> Result<Json<Vec<DeliveryFailure>>, AppError> {
+    let failures = get_delivery_failures(&app_state.pool, issue_id)
+        .await
+        .context("Failed to fetch delivery failures")?;
+
+    Ok(Json(failures))
+}
+
+/// Moves the selected failures back into the delivery queue with a fresh
+/// retry budget. Recipients that are no longer deliverable are still
+/// skipped by the worker.
+#[instrument(name = "Re-enqueue failed deliveries", skip(app_state))]
+pub(crate) async fn retry_delivery_failures(
+    State(app_state): State<Arc<AppState>>,
+    Path(issue_id): Path<Uuid>,
+    Json(body): Json<RetryBody>,
+) -> Result<Json<RetryResponse>, AppError> {
+    if body.subscriber_emails.is_empty() {
+        return Err(AppError::E400(anyhow::anyhow!(
+            "Select at least one failed delivery to retry"
+        )));
+    }
+
+    let requeued =
+        requeue_failures(&app_state.pool, issue_id, &body.subscriber_emails)
+            .await
+            .context("Failed to re-enqueue delivery failures")?;
+    let Some(requeued) = requeued else {
+        return Err(missing_or_conflict(
+            &app_state.pool,
+            issue_id,
+            "an existing issue",
+        )
+        .await);
+    };
+
+    Ok(Json(RetryResponse { requeued }))
+}
+
+#[instrument(skip(pool))]
+async fn get_delivery_failures(
+    pool: &PgPool,
+    issue_id: Uuid,
+) -> Result<Vec<DeliveryFailure>, sqlx::Error> {
+    sqlx::query_as!(
+        DeliveryFailure,
+        r#"
+        SELECT
+            subscriber_email,
+            last_error,
+            n_attempts,
+            enqueued_at,
+            failed_at
+        FROM issue_delivery_failures
+        WHERE newsletter_issue_id = $1
+        ORDER BY failed_at, subscriber_email
+        "#,
+        issue_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// The failures leave the issue's failed count as they go back to the
+/// queue, so they are counted again once delivered or failed anew. Nothing
+/// is requeued for a cancelled issue. `None` if there is no such issue.
+#[instrument(skip(pool))]
+async fn requeue_failures(
+    pool: &PgPool,
+    issue_id: Uuid,
+    subscriber_emails: &[String],
+) -> Result<Option<u64>, sqlx::Error> {
+    let record = sqlx::query!(
+        r#"
+        WITH requeued AS (
+            DELETE FROM issue_delivery_failures
+            WHERE
+                newsletter_issue_id = $1 AND
+                subscriber_email = ANY($2) AND
+                NOT EXISTS (
+                    SELECT 1 FROM newsletter_issues
+                    WHERE newsletter_issue_id = $1 AND status = $3
+                )
+            RETURNING newsletter_issue_id, subscriber_email
+        ),
+        enqueued AS (
+            INSERT INTO issue_delivery_queue (
+                newsletter_issue_id, subscriber_email
+            )
+            SELECT newsletter_issue_id, subscriber_email
+            FROM requeued
+            ON CONFLICT DO NOTHING
+        )
+        UPDATE newsletter_issues
+        SET n_failed = n_failed - (SELECT COUNT(*) FROM requeued)
+        WHERE newsletter_issue_id = $1
+        RETURNING (SELECT COUNT(*) FROM requeued) AS "requeued!"
+        "#,
+        issue_id,
+        subscriber_emails,
+        IssueStatus::Cancelled.to_string(),
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(record.map(|r| r.requeued.try_into().unwrap_or(0)))
+}
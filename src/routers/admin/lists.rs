@@ -0,0 +1,172 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::{Json, extract::State};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tracing::instrument;
+use uuid::Uuid;
+
+use crate::{
+    app_state::AppState,
+    domain::{mailing_list::ListSlug, subscriber::SubscriberStatus},
+    routers::error_chain_fmt,
+    utils::AppError,
+};
+
+#[derive(Deserialize, Debug)]
+pub struct NewList {
+    slug: ListSlug,
+    name: String,
+}
+
+#[derive(Serialize)]
+pub struct ListCreated {
+    list_id: Uuid,
+}
+
+#[derive(Serialize)]
+pub struct MailingLists {
+    lists: Vec<MailingList>,
+}
+
+#[derive(Serialize)]
+pub struct MailingList {
+    list_id: Uuid,
+    slug: String,
+    name: String,
+    /// Members who confirmed this list
+    confirmed: i64,
+    /// Members who still have to click the confirmation link
+    pending: i64,
+}
+
+#[derive(thiserror::Error)]
+pub enum ListLookupError {
+    #[error("There is no list named {0}")]
+    UnknownList(ListSlug),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ListLookupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl From<ListLookupError> for AppError {
+    fn from(e: ListLookupError) -> Self {
+        match e {
+            ListLookupError::UnknownList(_) => Self::E400(e.into()),
+            ListLookupError::UnexpectedError(e) => Self::E500(e),
+        }
+    }
+}
+
+#[instrument(name = "Create a mailing list", skip(app_state))]
+pub(crate) async fn create_list(
+    State(app_state): State<Arc<AppState>>,
+    Json(body): Json<NewList>,
+) -> Result<Json<ListCreated>, AppError> {
+    let name = body.name.trim();
+    if name.is_empty() {
+        return Err(AppError::E400(anyhow::anyhow!(
+            "The list name must not be empty"
+        )));
+    }
+
+    let list_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO lists (list_id, slug, name)
+        VALUES ($1, $2, $3)
+        "#,
+        list_id,
+        body.slug.as_ref(),
+        name,
+    )
+    .execute(&app_state.pool)
+    .await
+    .map_err(|e| {
+        if e.as_database_error()
+            .is_some_and(|e| e.is_unique_violation())
+        {
+            AppError::E409(anyhow::anyhow!(
+                "A list named {} already exists",
+                body.slug
+            ))
+        } else {
+            AppError::E500(
+                anyhow::Error::new(e).context("Failed to create the list"),
+            )
+        }
+    })?;
+
+    Ok(Json(ListCreated { list_id }))
+}
+
+#[instrument(name = "List mailing lists", skip(app_state))]
+pub(crate) async fn get_lists(
+    State(app_state): State<Arc<AppState>>,
+) -> Result<Json<MailingLists>, AppError> {
+    let records = sqlx::query!(
+        r#"
+        SELECT
+            l.list_id,
+            l.slug,
+            l.name,
+            COUNT(*) FILTER (WHERE m.status = $1) AS "confirmed!",
+            COUNT(*) FILTER (WHERE m.status = $2) AS "pending!"
+        FROM lists l
+        LEFT JOIN list_memberships m ON m.list_id = l.list_id
+        GROUP BY l.list_id
+        ORDER BY l.created_at, l.slug
+        "#,
+        SubscriberStatus::Confirmed.to_string(),
+        SubscriberStatus::PendingConfirmation.to_string(),
+    )
+    .fetch_all(&app_state.pool)
+    .await
+    .context("Failed to list the mailing lists")?;
+
+    let lists = records
+        .into_iter()
+        .map(|r| MailingList {
+            list_id: r.list_id,
+            slug: r.slug,
+            name: r.name,
+            confirmed: r.confirmed,
+            pending: r.pending,
+        })
+        .collect();
+    Ok(Json(MailingLists { lists }))
+}
+
+/// The ids of the given lists, in no particular order.
+#[instrument(skip(pool))]
+pub(crate) async fn find_list_ids(
+    pool: &PgPool,
+    slugs: &[ListSlug],
+) -> Result<Vec<Uuid>, ListLookupError> {
+    let names: Vec<String> = slugs.iter().map(ToString::to_string).collect();
+    let records = sqlx::query!(
+        r#"
+        SELECT list_id, slug
+        FROM lists
+        WHERE slug = ANY($1)
+        "#,
+        &names,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to look up the mailing lists")?;
+
+    if let Some(unknown) = slugs
+        .iter()
+        .find(|slug| !records.iter().any(|r| r.slug == slug.as_ref()))
+    {
+        return Err(ListLookupError::UnknownList(unknown.clone()));
+    }
+    Ok(records.into_iter().map(|r| r.list_id).collect())
+}
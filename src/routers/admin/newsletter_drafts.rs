@@ -0,0 +1,353 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::{
+    Extension, Json,
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Postgres, Transaction};
+use time::OffsetDateTime;
+use tracing::instrument;
+use uuid::Uuid;
+
+use super::{
+    find_list_ids,
+    newsletter_issue::missing_or_conflict,
+    newsletters::{
+        Content, IssueBodies, PublishResponse, enqueue_delivery_tasks,
+        ensure_in_future, or_default_list, target_audience,
+    },
+};
+use crate::{
+    app_state::AppState,
+    authentication::UserId,
+    domain::{
+        mailing_list::ListSlug,
+        newsletter_html,
+        newsletter_issue::{IssueStatus, web_version_url},
+        newsletter_template::{self, TemplateContext},
+        segment::Segment,
+        subscriber::SubscriberStatus,
+        subscriber_email::SubscriberEmail,
+    },
+    idempotency::{
+        key::IdempotencyKey,
+        persistence::{NextAction, save_response, try_process},
+    },
+    utils::AppError,
+};
+
+/// Keeps a test send from turning into a mailing.
+const MAX_TEST_RECIPIENTS: usize = 10;
+
+#[derive(Deserialize, Debug)]
+pub struct DraftBody {
+    title: String,
+    content: Content,
+}
+
+#[derive(Serialize)]
+pub struct DraftCreated {
+    newsletter_issue_id: Uuid,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TestSendBody {
+    recipients: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PublishBody {
+    idempotency_key: String,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    send_at: Option<OffsetDateTime>,
+    /// The lists to send the issue to, the default list if empty
+    #[serde(default)]
+    lists: Vec<ListSlug>,
+    /// Narrows the audience to the subscribers of the lists matching it
+    segment: Option<Segment>,
+}
+
+#[instrument(name = "Create a newsletter draft", skip(app_state, body))]
+pub(crate) async fn create_draft(
+    State(app_state): State<Arc<AppState>>,
+    Json(body): Json<DraftBody>,
+) -> Result<Json<DraftCreated>, AppError> {
+    let newsletter_issue_id = Uuid::new_v4();
+    let bodies = IssueBodies::from(body.content);
+
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id,
+            title,
+            text_content,
+            html_content,
+            markdown_content,
+            status
+        )
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+        newsletter_issue_id,
+        body.title,
+        bodies.text,
+        bodies.html,
+        bodies.markdown,
+        IssueStatus::Draft.to_string(),
+    )
+    .execute(&app_state.pool)
+    .await
+    .context("Failed to store the newsletter draft")?;
+
+    Ok(Json(DraftCreated {
+        newsletter_issue_id,
+    }))
+}
+
+/// Only drafts can be edited; what was published stays as it was sent.
+/// Markdown drafts are rendered again from the new source.
+#[instrument(name = "Edit a newsletter draft", skip(app_state, body))]
+pub(crate) async fn update_draft(
+    State(app_state): State<Arc<AppState>>,
+    Path(issue_id): Path<Uuid>,
+    Json(body): Json<DraftBody>,
+) -> Result<StatusCode, AppError> {
+    let bodies = IssueBodies::from(body.content);
+    let result = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET
+            title = $2,
+            text_content = $3,
+            html_content = $4,
+            markdown_content = $5
+        WHERE newsletter_issue_id = $1 AND status = $6
+        "#,
+        issue_id,
+        body.title,
+        bodies.text,
+        bodies.html,
+        bodies.markdown,
+        IssueStatus::Draft.to_string(),
+    )
+    .execute(&app_state.pool)
+    .await
+    .context("Failed to update the newsletter draft")?;
+    if result.rows_affected() == 0 {
+        return Err(
+            missing_or_conflict(&app_state.pool, issue_id, "a draft").await
+        );
+    }
+
+    Ok(StatusCode::OK)
+}
+
+/// Sends the issue as it stands to a handful of addresses, bypassing the
+/// delivery queue. Subscribers are not involved.
+#[instrument(name = "Send a test of a newsletter issue", skip(app_state))]
+pub(crate) async fn send_test(
+    State(app_state): State<Arc<AppState>>,
+    Path(issue_id): Path<Uuid>,
+    Json(body): Json<TestSendBody>,
+) -> Result<StatusCode, AppError> {
+    if body.recipients.is_empty() || body.recipients.len() > MAX_TEST_RECIPIENTS
+    {
+        return Err(AppError::E400(anyhow::anyhow!(
+            "Send a test to between 1 and {MAX_TEST_RECIPIENTS} addresses"
+        )));
+    }
+    let recipients = body
+        .recipients
+        .into_iter()
+        .map(SubscriberEmail::try_from)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| AppError::E400(anyhow::anyhow!(e)))?;
+
+    let (title, text_content, html_content) =
+        get_content(&app_state.pool, issue_id).await?;
+    let html_content = newsletter_html::prepare_for_email(&html_content)
+        .map_err(AppError::E400)?
+        .html;
+    let view_online_url = web_version_url(&app_state.base_url, issue_id)?;
+    let mut unsubscribe_url = view_online_url.clone();
+    unsubscribe_url.set_path("/subscriptions/unsubscribe");
+    let content = newsletter_template::render(
+        &text_content,
+        &html_content,
+        &TemplateContext::generic(
+            unsubscribe_url.as_str(),
+            view_online_url.as_str(),
+        ),
+    )
+    .map_err(|e| AppError::E400(e.into()))?;
+
+    let subject = format!("[Test] {title}");
+    for recipient in &recipients {
+        app_state
+            .email_client
+            .send_email(recipient, &subject, &content.text, &content.html, None)
+            .await
+            .with_context(|| {
+                format!("Failed to send a test email to {}", recipient.as_ref())
+            })?;
+    }
+
+    Ok(StatusCode::OK)
+}
+
+/// Hands a draft over to the same idempotent enqueue path as publishing
+/// straight away, optionally scheduled with `send_at`.
+#[instrument(
+    name = "Publish a newsletter draft",
+    skip(app_state, body),
+    fields(user_id = %&*user_id)
+)]
+pub(crate) async fn publish_draft(
+    State(app_state): State<Arc<AppState>>,
+    Extension(user_id): Extension<UserId>,
+    Path(issue_id): Path<Uuid>,
+    Json(body): Json<PublishBody>,
+) -> Result<Response, AppError> {
+    let idempotency_key: IdempotencyKey =
+        body.idempotency_key.try_into().map_err(AppError::E400)?;
+    if let Some(send_at) = body.send_at {
+        ensure_in_future(send_at).map_err(AppError::E400)?;
+    }
+    if let Some(segment) = &body.segment {
+        segment
+            .validate()
+            .map_err(|e| AppError::E400(anyhow::anyhow!(e)))?;
+    }
+    let list_ids =
+        find_list_ids(&app_state.pool, &or_default_list(body.lists)).await?;
+
+    let mut tx =
+        match try_process(&app_state.pool, &idempotency_key, user_id).await? {
+            NextAction::StartProcessing(t) => t,
+            NextAction::ReturnSavedResponse(saved_response) => {
+                return Ok(saved_response);
+            }
+        };
+
+    let Some((text_content, html_content)) = lock_draft(&mut tx, issue_id)
+        .await
+        .context("Failed to fetch the newsletter draft")?
+    else {
+        return Err(
+            missing_or_conflict(&app_state.pool, issue_id, "a draft").await
+        );
+    };
+    let prepared = newsletter_html::prepare_for_email(&html_content)
+        .map_err(AppError::E400)?;
+    newsletter_template::validate(&text_content, &prepared.html)
+        .map_err(|e| AppError::E400(e.into()))?;
+    mark_published(&mut tx, issue_id, &prepared.html, body.send_at)
+        .await
+        .context("Failed to publish the newsletter draft")?;
+    target_audience(&mut tx, issue_id, &list_ids, body.segment.as_ref())
+        .await
+        .context("Failed to store the audience of the newsletter issue")?;
+    enqueue_delivery_tasks(
+        &mut tx,
+        issue_id,
+        SubscriberStatus::DELIVERABLE,
+        body.segment.as_ref(),
+        body.send_at,
+    )
+    .await
+    .context("Failed to enqueue delivery tasks")?;
+
+    let response = Json(PublishResponse {
+        newsletter_issue_id: issue_id,
+        html_report: prepared.report,
+    })
+    .into_response();
+    let response = save_response(tx, idempotency_key, user_id, response)
+        .await
+        .map_err(AppError::E500)?;
+    Ok(response)
+}
+
+/// The title and both bodies of the issue.
+#[instrument(skip(pool))]
+async fn get_content(
+    pool: &PgPool,
+    issue_id: Uuid,
+) -> Result<(String, String, String), AppError> {
+    let record = sqlx::query!(
+        r#"
+        SELECT title, text_content, html_content
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch the newsletter issue")?
+    .ok_or_else(|| {
+        AppError::E404(anyhow::anyhow!(
+            "No newsletter issue with id {issue_id}"
+        ))
+    })?;
+
+    Ok((record.title, record.text_content, record.html_content))
+}
+
+/// The bodies of the issue if it is a draft, locked against concurrent
+/// edits until it is published.
+#[instrument(skip(tx))]
+async fn lock_draft(
+    tx: &mut Transaction<'static, Postgres>,
+    issue_id: Uuid,
+) -> Result<Option<(String, String)>, sqlx::Error> {
+    let record = sqlx::query!(
+        r#"
+        SELECT text_content, html_content
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1 AND status = $2
+        FOR UPDATE
+        "#,
+        issue_id,
+        IssueStatus::Draft.to_string(),
+    )
+    .fetch_optional(&mut **tx)
+    .await?;
+
+    Ok(record.map(|r| (r.text_content, r.html_content)))
+}
+
+/// Stores the HTML body as prepared for email along with the new status.
+#[instrument(skip(tx, html_content))]
+async fn mark_published(
+    tx: &mut Transaction<'static, Postgres>,
+    issue_id: Uuid,
+    html_content: &str,
+    send_at: Option<OffsetDateTime>,
+) -> Result<(), sqlx::Error> {
+    let status = match send_at {
+        Some(_) => IssueStatus::Scheduled,
+        None => IssueStatus::Published,
+    };
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET
+            status = $2,
+            published_at = COALESCE($3, NOW()),
+            html_content = $4
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id,
+        status.to_string(),
+        send_at,
+        html_content,
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
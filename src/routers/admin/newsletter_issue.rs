@@ -0,0 +1,323 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use time::OffsetDateTime;
+use tracing::instrument;
+use uuid::Uuid;
+
+use crate::{
+    app_state::AppState,
+    domain::newsletter_issue::{IssueStatus, web_version_url},
+    utils::AppError,
+};
+
+const MAX_PER_PAGE: i64 = 100;
+
+#[derive(Deserialize, Debug)]
+pub struct ListParams {
+    #[serde(default = "default_page")]
+    page: i64,
+    #[serde(default = "default_per_page")]
+    per_page: i64,
+}
+
+pub(crate) fn default_page() -> i64 {
+    1
+}
+
+pub(crate) fn default_per_page() -> i64 {
+    20
+}
+
+/// Pages start at 1 and hold at most `MAX_PER_PAGE` items.
+pub(crate) fn check_page(page: i64, per_page: i64) -> Result<(), AppError> {
+    if page < 1 {
+        return Err(AppError::E400(anyhow::anyhow!(
+            "The page number starts at 1"
+        )));
+    }
+    if !(1..=MAX_PER_PAGE).contains(&per_page) {
+        return Err(AppError::E400(anyhow::anyhow!(
+            "per_page must be between 1 and {MAX_PER_PAGE}"
+        )));
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+pub struct NewsletterIssueList {
+    issues: Vec<NewsletterIssueSummary>,
+    page: i64,
+    per_page: i64,
+    total: i64,
+}
+
+#[derive(Serialize)]
+pub struct NewsletterIssueSummary {
+    newsletter_issue_id: Uuid,
+    title: String,
+    #[serde(with = "time::serde::rfc3339::option")]
+    published_at: Option<OffsetDateTime>,
+    status: String,
+    web_url: String,
+}
+
+#[derive(Serialize)]
+pub struct NewsletterIssue {
+    newsletter_issue_id: Uuid,
+    title: String,
+    /// When the issue went, or is scheduled to go, out; unset for drafts
+    #[serde(with = "time::serde::rfc3339::option")]
+    published_at: Option<OffsetDateTime>,
+    status: String,
+    content: IssueContent,
+    audience: IssueAudience,
+    delivery: DeliveryProgress,
+}
+
+#[derive(Serialize)]
+pub struct IssueAudience {
+    /// Slugs of the lists the issue goes to; empty for drafts
+    lists: Vec<String>,
+    segment: Option<serde_json::Value>,
+}
+
+#[derive(Serialize)]
+pub struct IssueContent {
+    text: String,
+    html: String,
+    /// The source of both bodies, for issues authored in Markdown
+    markdown: Option<String>,
+}
+
+/// Every recipient of the issue is counted exactly once: still in the queue
+/// (possibly retrying), sent, failed, skipped or cancelled.
+#[derive(Serialize)]
+pub struct DeliveryProgress {
+    recipients: i64,
+    queued: i64,
+    retrying: i64,
+    sent: i64,
+    failed: i64,
+    skipped: i64,
+    cancelled: i64,
+    #[serde(with = "time::serde::rfc3339::option")]
+    first_sent_at: Option<OffsetDateTime>,
+    #[serde(with = "time::serde::rfc3339::option")]
+    last_sent_at: Option<OffsetDateTime>,
+    /// The issue went out, or was cancelled, and nothing is left in the
+    /// queue for it
+    complete: bool,
+}
+
+/// Newest issues first, paginated with `page` (from 1) and `per_page`.
+#[instrument(name = "List newsletter issues", skip(app_state))]
+pub(crate) async fn list_newsletter_issues(
+    State(app_state): State<Arc<AppState>>,
+    Query(params): Query<ListParams>,
+) -> Result<Json<NewsletterIssueList>, AppError> {
+    check_page(params.page, params.per_page)?;
+
+    let (records, total) =
+        fetch_newsletter_issues(&app_state.pool, params.page, params.per_page)
+            .await
+            .context("Failed to list newsletter issues")?;
+    let issues = records
+        .into_iter()
+        .map(|(newsletter_issue_id, title, published_at, status)| {
+            let web_url =
+                web_version_url(&app_state.base_url, newsletter_issue_id)?;
+            Ok(NewsletterIssueSummary {
+                newsletter_issue_id,
+                title,
+                published_at,
+                status,
+                web_url: web_url.into(),
+            })
+        })
+        .collect::<Result<_, anyhow::Error>>()?;
+
+    Ok(Json(NewsletterIssueList {
+        issues,
+        page: params.page,
+        per_page: params.per_page,
+        total,
+    }))
+}
+
+#[instrument(name = "Get newsletter issue delivery status", skip(app_state))]
+pub(crate) async fn get_newsletter_issue(
+    State(app_state): State<Arc<AppState>>,
+    Path(issue_id): Path<Uuid>,
+) -> Result<Json<NewsletterIssue>, AppError> {
+    let issue = fetch_newsletter_issue(&app_state.pool, issue_id)
+        .await
+        .context("Failed to fetch the newsletter issue")?
+        .ok_or_else(|| {
+            AppError::E404(anyhow::anyhow!(
+                "No newsletter issue with id {issue_id}"
+            ))
+        })?;
+
+    Ok(Json(issue))
+}
+
+type IssueRow = (Uuid, String, Option<OffsetDateTime>, String);
+
+#[instrument(skip(pool))]
+async fn fetch_newsletter_issues(
+    pool: &PgPool,
+    page: i64,
+    per_page: i64,
+) -> Result<(Vec<IssueRow>, i64), sqlx::Error> {
+    let records = sqlx::query!(
+        r#"
+        SELECT newsletter_issue_id, title, published_at, status
+        FROM newsletter_issues
+        ORDER BY published_at DESC NULLS FIRST, newsletter_issue_id
+        LIMIT $1 OFFSET $2
+        "#,
+        per_page,
+        (page - 1) * per_page,
+    )
+    .fetch_all(pool)
+    .await?;
+    let total = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "total!" FROM newsletter_issues"#
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let issues = records
+        .into_iter()
+        .map(|r| (r.newsletter_issue_id, r.title, r.published_at, r.status))
+        .collect();
+    Ok((issues, total))
+}
+
+#[instrument(skip(pool))]
+async fn fetch_newsletter_issue(
+    pool: &PgPool,
+    issue_id: Uuid,
+) -> Result<Option<NewsletterIssue>, anyhow::Error> {
+    let record = sqlx::query!(
+        r#"
+        SELECT
+            i.newsletter_issue_id,
+            i.title,
+            i.published_at,
+            i.status,
+            i.text_content,
+            i.html_content,
+            i.markdown_content,
+            ARRAY(
+                SELECT l.slug
+                FROM newsletter_issue_lists il
+                JOIN lists l ON l.list_id = il.list_id
+                WHERE il.newsletter_issue_id = i.newsletter_issue_id
+                ORDER BY l.slug
+            ) AS "lists!",
+            i.segment::text AS segment,
+            i.n_sent,
+            i.n_failed,
+            i.n_skipped,
+            i.n_cancelled,
+            i.first_sent_at,
+            i.last_sent_at,
+            COUNT(q.subscriber_email) AS "queued!",
+            COUNT(q.subscriber_email) FILTER (
+                WHERE q.n_retries > 0
+            ) AS "retrying!"
+        FROM newsletter_issues i
+        LEFT JOIN issue_delivery_queue q
+            ON q.newsletter_issue_id = i.newsletter_issue_id
+        WHERE i.newsletter_issue_id = $1
+        GROUP BY i.newsletter_issue_id
+        "#,
+        issue_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    let Some(r) = record else {
+        return Ok(None);
+    };
+    let segment = r
+        .segment
+        .as_deref()
+        .map(serde_json::from_str)
+        .transpose()
+        .context("The stored segment is not valid JSON")?;
+    // A draft or a scheduled issue may have nothing queued yet
+    let complete = matches!(
+        r.status.parse().map_err(anyhow::Error::msg)?,
+        IssueStatus::Published | IssueStatus::Cancelled
+    ) && r.queued == 0;
+    let (sent, failed, skipped, cancelled) = (
+        r.n_sent.into(),
+        r.n_failed.into(),
+        r.n_skipped.into(),
+        r.n_cancelled.into(),
+    );
+
+    Ok(Some(NewsletterIssue {
+        newsletter_issue_id: r.newsletter_issue_id,
+        title: r.title,
+        published_at: r.published_at,
+        status: r.status,
+        content: IssueContent {
+            text: r.text_content,
+            html: r.html_content,
+            markdown: r.markdown_content,
+        },
+        audience: IssueAudience {
+            lists: r.lists,
+            segment,
+        },
+        delivery: DeliveryProgress {
+            recipients: r.queued + sent + failed + skipped + cancelled,
+            queued: r.queued,
+            retrying: r.retrying,
+            sent,
+            failed,
+            skipped,
+            cancelled,
+            first_sent_at: r.first_sent_at,
+            last_sent_at: r.last_sent_at,
+            complete,
+        },
+    }))
+}
+
+/// Tells an unknown issue apart from one that is no longer in the
+/// `expected_state` an update was guarded on, e.g. "a draft".
+pub(crate) async fn missing_or_conflict(
+    pool: &PgPool,
+    issue_id: Uuid,
+    expected_state: &str,
+) -> AppError {
+    let exists = sqlx::query!(
+        "SELECT 1 AS one FROM newsletter_issues WHERE newsletter_issue_id = $1",
+        issue_id,
+    )
+    .fetch_optional(pool)
+    .await;
+
+    match exists {
+        Ok(Some(_)) => AppError::E409(anyhow::anyhow!(
+            "The newsletter issue {issue_id} is not {expected_state}"
+        )),
+        Ok(None) => AppError::E404(anyhow::anyhow!(
+            "No newsletter issue with id {issue_id}"
+        )),
+        Err(e) => AppError::E500(
+            anyhow::Error::new(e).context("Failed to fetch the issue"),
+        ),
+    }
+}
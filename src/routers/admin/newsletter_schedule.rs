@@ -0,0 +1,97 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use serde::Deserialize;
+use sqlx::{Postgres, Transaction};
+use time::OffsetDateTime;
+use tracing::instrument;
+use uuid::Uuid;
+
+use super::{
+    newsletter_issue::missing_or_conflict, newsletters::ensure_in_future,
+};
+use crate::{
+    app_state::AppState, domain::newsletter_issue::IssueStatus, utils::AppError,
+};
+
+#[derive(Deserialize, Debug)]
+pub struct RescheduleBody {
+    #[serde(with = "time::serde::rfc3339")]
+    send_at: OffsetDateTime,
+}
+
+/// Moves a scheduled issue, and its pending deliveries, to a new send time.
+#[instrument(name = "Reschedule a newsletter issue", skip(app_state))]
+pub(crate) async fn reschedule_newsletter_issue(
+    State(app_state): State<Arc<AppState>>,
+    Path(issue_id): Path<Uuid>,
+    Json(body): Json<RescheduleBody>,
+) -> Result<StatusCode, AppError> {
+    ensure_in_future(body.send_at).map_err(AppError::E400)?;
+
+    let mut tx = app_state
+        .pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let found = update_send_time(&mut tx, issue_id, body.send_at)
+        .await
+        .context("Failed to reschedule the newsletter issue")?;
+    if !found {
+        return Err(missing_or_conflict(
+            &app_state.pool,
+            issue_id,
+            "scheduled",
+        )
+        .await);
+    }
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET execute_after = $2
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id,
+        body.send_at,
+    )
+    .execute(&mut *tx)
+    .await
+    .context("Failed to reschedule the delivery tasks")?;
+    tx.commit()
+        .await
+        .context("Failed to commit the new schedule")?;
+
+    Ok(StatusCode::OK)
+}
+
+/// Only touches issues that are still scheduled and whose send time has
+/// not come yet, i.e. before the worker may pick up their deliveries.
+#[instrument(skip(tx))]
+async fn update_send_time(
+    tx: &mut Transaction<'_, Postgres>,
+    issue_id: Uuid,
+    send_at: OffsetDateTime,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET published_at = $2
+        WHERE
+            newsletter_issue_id = $1 AND
+            status = $3 AND
+            published_at > NOW()
+        "#,
+        issue_id,
+        send_at,
+        IssueStatus::Scheduled.to_string(),
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
@@ -4,19 +4,26 @@ use anyhow::Context;
 use axum::{
     Extension, Json,
     extract::State,
-    http::{HeaderMap, StatusCode},
     response::{IntoResponse, Response},
 };
-use base64::prelude::*;
-use secrecy::SecretString;
-use serde::Deserialize;
-use sqlx::{PgPool, Postgres, Transaction};
+use serde::{Deserialize, Serialize};
+use sqlx::{Postgres, QueryBuilder, Transaction};
+use time::OffsetDateTime;
 use tracing::instrument;
 
+use super::find_list_ids;
 use crate::{
     app_state::AppState,
-    authentication::{Credentials, UserId},
-    domain::{subscriber::SubscriberStatus, subscriber_email::SubscriberEmail},
+    authentication::UserId,
+    domain::{
+        mailing_list::ListSlug,
+        markdown,
+        newsletter_html::{self, PreparationReport},
+        newsletter_issue::IssueStatus,
+        newsletter_template,
+        segment::Segment,
+        subscriber::SubscriberStatus,
+    },
     idempotency::{
         key::IdempotencyKey,
         persistence::{NextAction, save_response, try_process},
@@ -29,12 +36,54 @@ pub struct Body {
     title: String,
     content: Content,
     idempotency_key: String,
+    /// Schedules the issue instead of sending it right away
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    send_at: Option<OffsetDateTime>,
+    /// The lists to send the issue to, the default list if empty
+    #[serde(default)]
+    lists: Vec<ListSlug>,
+    /// Narrows the audience to the subscribers of the lists matching it
+    segment: Option<Segment>,
 }
 
+/// Either both bodies written by hand, or a Markdown source they are
+/// rendered from.
 #[derive(Deserialize, Debug)]
-pub struct Content {
-    text: String,
-    html: String,
+#[serde(untagged)]
+pub enum Content {
+    Markdown { markdown: String },
+    Bodies { text: String, html: String },
+}
+
+#[derive(Serialize)]
+pub struct PublishResponse {
+    pub(crate) newsletter_issue_id: uuid::Uuid,
+    /// What was changed in the HTML body to make it fit for email
+    pub(crate) html_report: PreparationReport,
+}
+
+/// The bodies of an issue as stored, with their Markdown source if any.
+pub(crate) struct IssueBodies {
+    pub(crate) text: String,
+    pub(crate) html: String,
+    pub(crate) markdown: Option<String>,
+}
+
+impl From<Content> for IssueBodies {
+    fn from(content: Content) -> Self {
+        match content {
+            Content::Markdown { markdown } => Self {
+                text: markdown::to_text(&markdown),
+                html: markdown::to_html(&markdown),
+                markdown: Some(markdown),
+            },
+            Content::Bodies { text, html } => Self {
+                text,
+                html,
+                markdown: None,
+            },
+        }
+    }
 }
 
 #[instrument(
@@ -52,6 +101,22 @@ pub(crate) async fn publish_newsletter(
 ) -> Result<Response, AppError> {
     let idempotency_key: IdempotencyKey =
         body.idempotency_key.try_into().map_err(AppError::E400)?;
+    if let Some(send_at) = body.send_at {
+        ensure_in_future(send_at).map_err(AppError::E400)?;
+    }
+    let mut bodies = IssueBodies::from(body.content);
+    let prepared = newsletter_html::prepare_for_email(&bodies.html)
+        .map_err(AppError::E400)?;
+    bodies.html = prepared.html;
+    newsletter_template::validate(&bodies.text, &bodies.html)
+        .map_err(|e| AppError::E400(e.into()))?;
+    if let Some(segment) = &body.segment {
+        segment
+            .validate()
+            .map_err(|e| AppError::E400(anyhow::anyhow!(e)))?;
+    }
+    let list_ids =
+        find_list_ids(&app_state.pool, &or_default_list(body.lists)).await?;
 
     let mut tx =
         match try_process(&app_state.pool, &idempotency_key, user_id).await? {
@@ -65,97 +130,72 @@ pub(crate) async fn publish_newsletter(
             }
         };
 
-    let newsletter_issue_id = save_newsletter_issue(
+    let newsletter_issue_id =
+        save_newsletter_issue(&mut tx, &body.title, &bodies, body.send_at)
+            .await
+            .context("Failed to store newsletter issue detailes")
+            .map_err(AppError::E500)?;
+    target_audience(
         &mut tx,
-        &body.title,
-        &body.content.text,
-        &body.content.html,
+        newsletter_issue_id,
+        &list_ids,
+        body.segment.as_ref(),
     )
     .await
-    .context("Failed to store newsletter issue detailes")
+    .context("Failed to store the audience of the newsletter issue")
+    .map_err(AppError::E500)?;
+    enqueue_delivery_tasks(
+        &mut tx,
+        newsletter_issue_id,
+        SubscriberStatus::DELIVERABLE,
+        body.segment.as_ref(),
+        body.send_at,
+    )
+    .await
+    .context("Failed to enqueue delivery tasks")
     .map_err(AppError::E500)?;
-    enqueue_delivery_tasks(&mut tx, newsletter_issue_id)
-        .await
-        .context("Failed to enqueue delivery tasks")
-        .map_err(AppError::E500)?;
 
-    let response = StatusCode::OK.into_response();
+    let response = Json(PublishResponse {
+        newsletter_issue_id,
+        html_report: prepared.report,
+    })
+    .into_response();
     let response = save_response(tx, idempotency_key, user_id, response)
         .await
         .map_err(AppError::E500)?;
     Ok(response)
 }
 
-#[deprecated(note = "Use session to manage authentication")]
-#[allow(dead_code)]
-fn basic_authentication(
-    headers: &HeaderMap,
-) -> Result<Credentials, anyhow::Error> {
-    let header_value = headers
-        .get("Authorization")
-        .context("The 'Authorization' header was missing")?
-        .to_str()
-        .context("The 'Authorization' header was not a valid UTF8 string.")?;
-    let base64encoded_segment = header_value
-        .strip_prefix("Basic ")
-        .context("The authorization scheme was not 'Basic'.")?;
-    let decoded_bytes = BASE64_STANDARD
-        .decode(base64encoded_segment)
-        .context("Failed to base64-decode 'Basic' credentials")?;
-    let decoded_credentials = String::from_utf8(decoded_bytes)
-        .context("The decoded credential string is not valid UTF8.")?;
-
-    let mut credentials = decoded_credentials.splitn(2, ':');
-    let username = credentials
-        .next()
-        .context("A username must be provided in 'Basic' auth.")?
-        .to_string();
-    let password = credentials
-        .next()
-        .context("A password must be provided in 'Basic' auth.")?
-        .to_string();
-
-    Ok(Credentials {
-        username,
-        password: SecretString::from(password),
-    })
+pub(crate) fn or_default_list(lists: Vec<ListSlug>) -> Vec<ListSlug> {
+    if lists.is_empty() {
+        vec![ListSlug::default_list()]
+    } else {
+        lists
+    }
 }
 
-#[allow(dead_code)]
-#[instrument(name = "get confirmed subscribers", skip(pool))]
-async fn get_confirmed_subscribers(
-    pool: &PgPool,
-) -> Result<Vec<Result<SubscriberEmail, anyhow::Error>>, anyhow::Error> {
-    let records = sqlx::query!(
-        r#"
-         SELECT email
-         FROM subscriptions
-         WHERE status = $1
-        "#,
-        SubscriberStatus::Confirmed.to_string()
-    )
-    .fetch_all(pool)
-    .await?;
-
-    let emails = records
-        .iter()
-        .map(|r| match r.email.parse::<SubscriberEmail>() {
-            Ok(email) => Ok(email),
-            Err(error) => Err(anyhow::anyhow!(error)),
-        })
-        .collect();
-
-    Ok(emails)
+pub(crate) fn ensure_in_future(
+    send_at: OffsetDateTime,
+) -> Result<(), anyhow::Error> {
+    anyhow::ensure!(
+        send_at > OffsetDateTime::now_utc(),
+        "The send time {send_at} is in the past"
+    );
+    Ok(())
 }
 
 #[instrument(skip_all)]
 async fn save_newsletter_issue(
     tx: &mut Transaction<'static, Postgres>,
     title: &str,
-    text_content: &str,
-    html_content: &str,
+    bodies: &IssueBodies,
+    send_at: Option<OffsetDateTime>,
 ) -> Result<uuid::Uuid, anyhow::Error> {
     let newsletter_issue_id = uuid::Uuid::new_v4();
+    let status = match send_at {
+        Some(_) => IssueStatus::Scheduled,
+        None => IssueStatus::Published,
+    };
 
     sqlx::query!(
         r#"
@@ -164,14 +204,19 @@ async fn save_newsletter_issue(
             title,
             text_content,
             html_content,
-            published_at
+            markdown_content,
+            published_at,
+            status
         )
-        VALUES ($1, $2, $3, $4, NOW())
+        VALUES ($1, $2, $3, $4, $5, COALESCE($6, NOW()), $7)
         "#,
         newsletter_issue_id,
         title,
-        text_content,
-        html_content
+        bodies.text,
+        bodies.html,
+        bodies.markdown,
+        send_at,
+        status.to_string(),
     )
     .execute(&mut **tx)
     .await?;
@@ -179,23 +224,131 @@ async fn save_newsletter_issue(
     Ok(newsletter_issue_id)
 }
 
-#[instrument(skip_all)]
-async fn enqueue_delivery_tasks(
+/// Stores who the issue is for, so that the audience can be shown later.
+#[instrument(skip(tx))]
+pub(crate) async fn target_audience(
     tx: &mut Transaction<'static, Postgres>,
     newsletter_issue_id: uuid::Uuid,
+    list_ids: &[uuid::Uuid],
+    segment: Option<&Segment>,
 ) -> Result<(), anyhow::Error> {
     sqlx::query!(
         r#"
-        INSERT INTO issue_delivery_queue (
-            newsletter_issue_id, subscriber_email
-        )
-        SELECT $1, email
-        FROM subscriptions
+        INSERT INTO newsletter_issue_lists (newsletter_issue_id, list_id)
+        SELECT $1, UNNEST($2::uuid[])
         "#,
-        newsletter_issue_id
+        newsletter_issue_id,
+        list_ids,
     )
     .execute(&mut **tx)
     .await?;
+    let segment = segment.map(serde_json::to_string).transpose()?;
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET segment = $2::text::jsonb
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id,
+        segment,
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+/// The audience of a scheduled issue is fixed when it is published; the
+/// worker still skips recipients that are no longer deliverable.
+///
+/// Subscribers are queued once, however many of the issue's lists they
+/// confirmed, if they match the segment.
+#[instrument(skip_all)]
+pub(crate) async fn enqueue_delivery_tasks(
+    tx: &mut Transaction<'static, Postgres>,
+    newsletter_issue_id: uuid::Uuid,
+    audience: &[SubscriberStatus],
+    segment: Option<&Segment>,
+    send_at: Option<OffsetDateTime>,
+) -> Result<(), anyhow::Error> {
+    let mut query = QueryBuilder::<Postgres>::new(
+        r#"
+        INSERT INTO issue_delivery_queue (
+            newsletter_issue_id, subscriber_email, execute_after
+        )
+        SELECT DISTINCT il.newsletter_issue_id, s.email, COALESCE("#,
+    );
+    query.push_bind(send_at).push(
+        r#", NOW())
+        FROM subscriptions s
+        JOIN list_memberships m ON m.subscription_id = s.id
+        JOIN newsletter_issue_lists il ON il.list_id = m.list_id
+        WHERE il.newsletter_issue_id = "#,
+    );
+    query
+        .push_bind(newsletter_issue_id)
+        .push(" AND m.status = ")
+        .push_bind(SubscriberStatus::Confirmed.to_string())
+        .push(" AND s.status = ANY(")
+        .push_bind(SubscriberStatus::to_strings(audience))
+        .push(")");
+    if let Some(segment) = segment {
+        query.push(" AND ");
+        push_segment(&mut query, segment);
+    }
+
+    query.build().execute(&mut **tx).await?;
 
     Ok(())
 }
+
+/// Compiles the segment into a condition on `s`, the subscriptions row.
+/// Every value is bound, and each condition is either true or false, never
+/// NULL, so that `not` selects exactly the other subscribers.
+fn push_segment(query: &mut QueryBuilder<'_, Postgres>, segment: &Segment) {
+    match segment {
+        Segment::All(segments) => push_many(query, segments, " AND ", "TRUE"),
+        Segment::Any(segments) => push_many(query, segments, " OR ", "FALSE"),
+        Segment::Not(segment) => {
+            query.push("NOT ");
+            push_segment(query, segment);
+        }
+        Segment::Tag(tag) => {
+            query.push_bind(tag.to_string()).push(" = ANY(s.tags)");
+        }
+        Segment::Attribute { name, equals } => {
+            query
+                .push("COALESCE(s.attributes -> ")
+                .push_bind(name.clone())
+                .push(" = ")
+                .push_bind(equals.to_string())
+                .push("::jsonb, FALSE)");
+        }
+        Segment::SubscribedBefore(t) => {
+            query.push("s.subscribed_at < ").push_bind(*t);
+        }
+        Segment::SubscribedAfter(t) => {
+            query.push("s.subscribed_at > ").push_bind(*t);
+        }
+    }
+}
+
+fn push_many(
+    query: &mut QueryBuilder<'_, Postgres>,
+    segments: &[Segment],
+    separator: &str,
+    empty: &str,
+) {
+    if segments.is_empty() {
+        query.push(empty);
+        return;
+    }
+    query.push("(");
+    for (i, segment) in segments.iter().enumerate() {
+        if i > 0 {
+            query.push(separator);
+        }
+        push_segment(query, segment);
+    }
+    query.push(")");
+}
@@ -0,0 +1,180 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::{
+    body::Body,
+    extract::{Query, State},
+    http::header,
+    response::{IntoResponse, Response},
+};
+use futures_util::{StreamExt, TryStreamExt, stream};
+use serde::{Deserialize, Serialize};
+use sqlx::{Postgres, Transaction};
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+use tracing::instrument;
+use uuid::Uuid;
+
+use crate::{
+    app_state::AppState,
+    domain::{csv_writer::csv_record, subscriber::SubscriberStatus},
+    utils::AppError,
+};
+
+/// Rows fetched from the cursor at a time, i.e. held in memory at most.
+const BATCH_SIZE: usize = 500;
+
+#[derive(Deserialize, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    /// One JSON object per line
+    Ndjson,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ExportParams {
+    #[serde(default)]
+    format: ExportFormat,
+    status: Option<String>,
+}
+
+#[derive(Serialize, sqlx::FromRow)]
+struct ExportedSubscriber {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    #[serde(with = "time::serde::rfc3339")]
+    subscribed_at: OffsetDateTime,
+}
+
+impl ExportFormat {
+    fn content_type(self) -> &'static str {
+        match self {
+            Self::Csv => "text/csv; charset=utf-8",
+            Self::Ndjson => "application/x-ndjson",
+        }
+    }
+
+    fn file_name(self) -> &'static str {
+        match self {
+            Self::Csv => "subscribers.csv",
+            Self::Ndjson => "subscribers.ndjson",
+        }
+    }
+
+    fn header(self) -> Option<String> {
+        match self {
+            Self::Csv => Some(csv_record(&[
+                "id",
+                "email",
+                "name",
+                "status",
+                "subscribed_at",
+            ])),
+            Self::Ndjson => None,
+        }
+    }
+
+    fn line(
+        self,
+        subscriber: &ExportedSubscriber,
+    ) -> Result<String, anyhow::Error> {
+        match self {
+            Self::Csv => {
+                let subscribed_at =
+                    subscriber.subscribed_at.format(&Rfc3339)?;
+                Ok(csv_record(&[
+                    &subscriber.id.to_string(),
+                    &subscriber.email,
+                    &subscriber.name,
+                    &subscriber.status,
+                    &subscribed_at,
+                ]))
+            }
+            Self::Ndjson => Ok(serde_json::to_string(subscriber)? + "\n"),
+        }
+    }
+}
+
+/// Streams every subscriber, oldest first, optionally only those with the
+/// given `status`.
+///
+/// Rows are read from a server-side cursor a batch at a time and written
+/// out as they come, so the table never has to fit in memory.
+#[instrument(name = "Export subscribers", skip(app_state))]
+pub(crate) async fn export_subscribers(
+    State(app_state): State<Arc<AppState>>,
+    Query(params): Query<ExportParams>,
+) -> Result<Response, AppError> {
+    let status = params
+        .status
+        .map(|s| s.parse::<SubscriberStatus>())
+        .transpose()
+        .map_err(|e| AppError::E400(anyhow::anyhow!(e)))?
+        .map(|s| s.to_string());
+    let format = params.format;
+
+    let mut tx = app_state
+        .pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    sqlx::query(
+        r#"
+        DECLARE subscriber_export NO SCROLL CURSOR FOR
+        SELECT id, email, name, status, subscribed_at
+        FROM subscriptions
+        WHERE $1::text IS NULL OR status = $1
+        ORDER BY subscribed_at, id
+        "#,
+    )
+    .bind(status)
+    .execute(&mut *tx)
+    .await
+    .context("Failed to open a cursor over the subscribers")?;
+
+    let lines = stream::try_unfold(Some(tx), move |tx| async move {
+        let Some(mut tx) = tx else { return Ok(None) };
+        let batch = fetch_batch(&mut tx)
+            .await
+            .context("Failed to fetch subscribers to export")?;
+        if batch.is_empty() {
+            tx.commit()
+                .await
+                .context("Failed to close the subscriber export")?;
+            return Ok(None);
+        }
+        let lines = batch
+            .iter()
+            .map(|s| format.line(s))
+            .collect::<Result<String, _>>()?;
+        Ok(Some((lines, Some(tx))))
+    })
+    .inspect_err(|e: &anyhow::Error| {
+        tracing::error!(error.cause_chain = ?e, "Subscriber export failed");
+    });
+    let body = stream::iter(format.header().map(Ok)).chain(lines);
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, format.content_type().to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", format.file_name()),
+            ),
+        ],
+        Body::from_stream(body),
+    )
+        .into_response())
+}
+
+#[instrument(skip(tx))]
+async fn fetch_batch(
+    tx: &mut Transaction<'static, Postgres>,
+) -> Result<Vec<ExportedSubscriber>, sqlx::Error> {
+    sqlx::query_as(&format!("FETCH {BATCH_SIZE} FROM subscriber_export"))
+        .fetch_all(&mut **tx)
+        .await
+}
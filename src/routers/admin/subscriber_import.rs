@@ -0,0 +1,266 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::{
+    Json,
+    body::Body,
+    extract::{Query, State},
+};
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+use uuid::Uuid;
+
+use super::find_list_ids;
+use crate::{
+    app_state::AppState,
+    domain::{
+        csv_reader::{CsvReader, CsvRecord},
+        mailing_list::ListSlug,
+        subscriber::Subscriber,
+        subscriber_attributes::SubscriberAttributes,
+        subscriber_email::SubscriberEmail,
+        subscriber_name::SubscriberName,
+    },
+    routers::subscriptions::{insert_subscriber, is_unique_violation},
+    utils::AppError,
+};
+
+#[derive(Deserialize, Debug)]
+pub struct ImportParams {
+    /// The subscribers already opted in elsewhere: no confirmation email
+    #[serde(default)]
+    confirmed: bool,
+}
+
+#[derive(Serialize, Default)]
+pub struct ImportReport {
+    accepted: usize,
+    duplicates: usize,
+    rejected: usize,
+    rows: Vec<RowReport>,
+}
+
+#[derive(Serialize)]
+pub struct RowReport {
+    line: usize,
+    /// As found in the file, if the line could be read
+    email: Option<String>,
+    outcome: RowOutcome,
+    reason: Option<String>,
+}
+
+#[derive(Serialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum RowOutcome {
+    Accepted,
+    /// The email is already subscribed, or appeared earlier in the file
+    Duplicate,
+    Rejected,
+}
+
+/// Where the columns are, read from the header line.
+#[derive(Clone, Copy)]
+struct Columns {
+    email: usize,
+    name: usize,
+}
+
+impl Columns {
+    fn from_header(header: &[String]) -> Result<Self, anyhow::Error> {
+        let position = |column: &str| {
+            header
+                .iter()
+                .position(|h| h.trim().eq_ignore_ascii_case(column))
+                .with_context(|| format!("The header has no {column} column"))
+        };
+        Ok(Self {
+            email: position("email")?,
+            name: position("name")?,
+        })
+    }
+}
+
+/// Imports the subscribers of a CSV file sent as the request body, with
+/// an `email` and a `name` column, into the default list.
+///
+/// The body is read as it arrives and every line is committed on its own,
+/// so one bad line does not fail the others.
+#[instrument(name = "Import subscribers", skip(app_state, body))]
+pub(crate) async fn import_subscribers(
+    State(app_state): State<Arc<AppState>>,
+    Query(params): Query<ImportParams>,
+    body: Body,
+) -> Result<Json<ImportReport>, AppError> {
+    let list_ids =
+        find_list_ids(&app_state.pool, &[ListSlug::default_list()]).await?;
+    let mut importer = Importer {
+        app_state: &app_state,
+        list_ids,
+        confirmed: params.confirmed,
+        columns: None,
+        seen: HashSet::new(),
+        report: ImportReport::default(),
+    };
+
+    let mut reader = CsvReader::new();
+    let mut stream = body.into_data_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk
+            .context("Failed to read the uploaded file")
+            .map_err(AppError::E400)?;
+        for record in reader.feed(&chunk) {
+            importer.import(record).await?;
+        }
+    }
+    if let Some(record) = reader.finish() {
+        importer.import(record).await?;
+    }
+    if importer.columns.is_none() {
+        return Err(AppError::E400(anyhow::anyhow!("The file is empty")));
+    }
+
+    Ok(Json(importer.report))
+}
+
+struct Importer<'a> {
+    app_state: &'a AppState,
+    list_ids: Vec<Uuid>,
+    confirmed: bool,
+    /// Unset until the header is read
+    columns: Option<Columns>,
+    /// Emails already imported from this file, lowercased: addresses are
+    /// matched regardless of case
+    seen: HashSet<String>,
+    report: ImportReport,
+}
+
+impl Importer<'_> {
+    async fn import(&mut self, record: CsvRecord) -> Result<(), AppError> {
+        let Some(columns) = self.columns else {
+            let header = record.fields.map_err(|e| {
+                AppError::E400(anyhow::anyhow!(
+                    "Failed to read the header: {e}"
+                ))
+            })?;
+            let columns =
+                Columns::from_header(&header).map_err(AppError::E400)?;
+            self.columns = Some(columns);
+            return Ok(());
+        };
+        let line = record.line;
+        let fields = match record.fields {
+            Ok(fields) => fields,
+            Err(e) => {
+                self.record(line, None, RowOutcome::Rejected, Some(e));
+                return Ok(());
+            }
+        };
+
+        let email = fields.get(columns.email).map(|e| e.trim().to_string());
+        let name = fields.get(columns.name).map(|n| n.trim().to_string());
+        let subscriber = match parse_subscriber(email.clone(), name) {
+            Ok(subscriber) => subscriber,
+            Err(e) => {
+                self.record(line, email, RowOutcome::Rejected, Some(e));
+                return Ok(());
+            }
+        };
+        if !self.seen.insert(subscriber.email.as_ref().to_lowercase()) {
+            self.record(line, email, RowOutcome::Duplicate, None);
+            return Ok(());
+        }
+
+        let outcome = self.insert(&subscriber).await?;
+        self.record(line, email, outcome, None);
+        Ok(())
+    }
+
+    async fn insert(
+        &self,
+        subscriber: &Subscriber,
+    ) -> Result<RowOutcome, AppError> {
+        let mut tx =
+            self.app_state.pool.begin().await.context(
+                "Failed to acquire a Postgres connection from the pool",
+            )?;
+        let exists = sqlx::query!(
+            r#"
+            SELECT 1 AS one
+            FROM subscriptions
+            WHERE lower(email) = lower($1)
+            "#,
+            subscriber.email.as_ref(),
+        )
+        .fetch_optional(&mut *tx)
+        .await
+        .context("Failed to look up an existing subscriber")?;
+        if exists.is_some() {
+            return Ok(RowOutcome::Duplicate);
+        }
+
+        match insert_subscriber(
+            &mut tx,
+            &self.app_state.base_url,
+            subscriber,
+            &self.list_ids,
+            self.confirmed,
+        )
+        .await
+        {
+            Ok(_) => {}
+            // Signed up while the file was being imported
+            Err(e) if is_unique_violation(&e) => {
+                return Ok(RowOutcome::Duplicate);
+            }
+            Err(e) => {
+                return Err(AppError::E500(
+                    anyhow::Error::new(e)
+                        .context("Failed to import a subscriber"),
+                ));
+            }
+        }
+        tx.commit()
+            .await
+            .context("Failed to commit an imported subscriber")?;
+
+        Ok(RowOutcome::Accepted)
+    }
+
+    fn record(
+        &mut self,
+        line: usize,
+        email: Option<String>,
+        outcome: RowOutcome,
+        reason: Option<String>,
+    ) {
+        match outcome {
+            RowOutcome::Accepted => self.report.accepted += 1,
+            RowOutcome::Duplicate => self.report.duplicates += 1,
+            RowOutcome::Rejected => self.report.rejected += 1,
+        }
+        self.report.rows.push(RowReport {
+            line,
+            email,
+            outcome,
+            reason,
+        });
+    }
+}
+
+fn parse_subscriber(
+    email: Option<String>,
+    name: Option<String>,
+) -> Result<Subscriber, String> {
+    let email = email.ok_or("The email is missing")?;
+    let name = name.ok_or("The name is missing")?;
+
+    Ok(Subscriber {
+        name: SubscriberName::try_from(name)?,
+        email: SubscriberEmail::try_from(email)?,
+        lists: vec![ListSlug::default_list()],
+        tags: Vec::new(),
+        attributes: SubscriberAttributes::default(),
+    })
+}
@@ -0,0 +1,147 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sqlx::{Postgres, Transaction};
+use tracing::instrument;
+use uuid::Uuid;
+
+use crate::{
+    app_state::AppState,
+    domain::{
+        subscriber_attributes::SubscriberAttributes,
+        subscriber_tag::SubscriberTag,
+    },
+    utils::AppError,
+};
+
+#[derive(Deserialize, Debug)]
+pub struct TagsBody {
+    tags: Vec<SubscriberTag>,
+}
+
+/// What segments can select a subscriber on.
+#[derive(Serialize)]
+pub struct SubscriberProfile {
+    subscriber_id: Uuid,
+    tags: Vec<String>,
+    attributes: Map<String, Value>,
+}
+
+/// Replaces the tags of a subscriber.
+#[instrument(name = "Set subscriber tags", skip(app_state))]
+pub(crate) async fn put_subscriber_tags(
+    State(app_state): State<Arc<AppState>>,
+    Path(subscriber_id): Path<Uuid>,
+    Json(body): Json<TagsBody>,
+) -> Result<Json<SubscriberProfile>, AppError> {
+    let mut tags: Vec<String> =
+        body.tags.iter().map(ToString::to_string).collect();
+    tags.sort();
+    tags.dedup();
+
+    let mut tx = app_state
+        .pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let profile = lock_profile(&mut tx, subscriber_id).await?;
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET tags = $2
+        WHERE id = $1
+        "#,
+        subscriber_id,
+        &tags,
+    )
+    .execute(&mut *tx)
+    .await
+    .context("Failed to store the subscriber tags")?;
+    tx.commit()
+        .await
+        .context("Failed to commit the subscriber tags")?;
+
+    Ok(Json(SubscriberProfile { tags, ..profile }))
+}
+
+/// Merges the body into the attributes of a subscriber; `null` removes an
+/// attribute.
+#[instrument(name = "Update subscriber attributes", skip(app_state))]
+pub(crate) async fn patch_subscriber_attributes(
+    State(app_state): State<Arc<AppState>>,
+    Path(subscriber_id): Path<Uuid>,
+    Json(patch): Json<Map<String, Value>>,
+) -> Result<Json<SubscriberProfile>, AppError> {
+    let mut tx = app_state
+        .pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let mut profile = lock_profile(&mut tx, subscriber_id).await?;
+    for (name, value) in patch {
+        if value.is_null() {
+            profile.attributes.remove(&name);
+        } else {
+            profile.attributes.insert(name, value);
+        }
+    }
+    let attributes = SubscriberAttributes::try_from(profile.attributes)
+        .map_err(|e| AppError::E400(anyhow::anyhow!(e)))?;
+
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET attributes = $2::text::jsonb
+        WHERE id = $1
+        "#,
+        subscriber_id,
+        attributes.to_json(),
+    )
+    .execute(&mut *tx)
+    .await
+    .context("Failed to store the subscriber attributes")?;
+    tx.commit()
+        .await
+        .context("Failed to commit the subscriber attributes")?;
+
+    Ok(Json(SubscriberProfile {
+        attributes: attributes.as_ref().clone(),
+        ..profile
+    }))
+}
+
+#[instrument(skip(tx))]
+async fn lock_profile(
+    tx: &mut Transaction<'static, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<SubscriberProfile, AppError> {
+    let record = sqlx::query!(
+        r#"
+        SELECT tags, attributes::text AS "attributes!"
+        FROM subscriptions
+        WHERE id = $1
+        FOR UPDATE
+        "#,
+        subscriber_id,
+    )
+    .fetch_optional(&mut **tx)
+    .await
+    .context("Failed to fetch the subscriber")?
+    .ok_or_else(|| {
+        AppError::E404(anyhow::anyhow!("No subscriber with id {subscriber_id}"))
+    })?;
+    let attributes = serde_json::from_str(&record.attributes)
+        .context("Stored subscriber attributes are not a JSON object")?;
+
+    Ok(SubscriberProfile {
+        subscriber_id,
+        tags: record.tags,
+        attributes,
+    })
+}
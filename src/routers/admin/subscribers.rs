@@ -0,0 +1,433 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sqlx::{PgPool, Postgres, Transaction};
+use time::OffsetDateTime;
+use tracing::instrument;
+use uuid::Uuid;
+
+use super::newsletter_issue::{check_page, default_page, default_per_page};
+use crate::{
+    app_state::AppState,
+    domain::subscriber::SubscriberStatus,
+    routers::subscriptions::{
+        confirm_subscription, mark_as_unsubscribed, retire_tokens,
+    },
+    utils::AppError,
+};
+
+#[derive(Deserialize, Debug)]
+pub struct SubscriberListParams {
+    #[serde(default = "default_page")]
+    page: i64,
+    #[serde(default = "default_per_page")]
+    per_page: i64,
+    /// Part of the email or name, case-insensitive
+    search: Option<String>,
+    status: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct SubscriberList {
+    subscribers: Vec<SubscriberSummary>,
+    page: i64,
+    per_page: i64,
+    total: i64,
+}
+
+#[derive(Serialize)]
+pub struct SubscriberSummary {
+    subscriber_id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    #[serde(with = "time::serde::rfc3339")]
+    subscribed_at: OffsetDateTime,
+}
+
+#[derive(Serialize)]
+pub struct SubscriberDetails {
+    subscriber_id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    #[serde(with = "time::serde::rfc3339")]
+    subscribed_at: OffsetDateTime,
+    tags: Vec<String>,
+    attributes: Map<String, Value>,
+    lists: Vec<ListMembership>,
+    /// Issues still waiting to be sent to the subscriber
+    queued_deliveries: i64,
+}
+
+#[derive(Serialize)]
+pub struct ListMembership {
+    slug: String,
+    status: String,
+}
+
+/// Newest subscribers first, paginated like the newsletter issues and
+/// optionally narrowed with `search` and `status`.
+#[instrument(name = "List subscribers", skip(app_state))]
+pub(crate) async fn list_subscribers(
+    State(app_state): State<Arc<AppState>>,
+    Query(params): Query<SubscriberListParams>,
+) -> Result<Json<SubscriberList>, AppError> {
+    check_page(params.page, params.per_page)?;
+    let status = params
+        .status
+        .map(|s| s.parse::<SubscriberStatus>())
+        .transpose()
+        .map_err(|e| AppError::E400(anyhow::anyhow!(e)))?
+        .map(|s| s.to_string());
+    let pattern = params
+        .search
+        .filter(|s| !s.trim().is_empty())
+        .map(|s| format!("%{}%", escape_like(s.trim())));
+
+    let records = sqlx::query!(
+        r#"
+        SELECT
+            id,
+            email,
+            name,
+            status,
+            subscribed_at,
+            COUNT(*) OVER () AS "total!"
+        FROM subscriptions
+        WHERE
+            ($1::text IS NULL OR email ILIKE $1 OR name ILIKE $1) AND
+            ($2::text IS NULL OR status = $2)
+        ORDER BY subscribed_at DESC, id
+        LIMIT $3 OFFSET $4
+        "#,
+        pattern,
+        status,
+        params.per_page,
+        (params.page - 1) * params.per_page,
+    )
+    .fetch_all(&app_state.pool)
+    .await
+    .context("Failed to list subscribers")?;
+
+    let total = match records.first() {
+        Some(r) => r.total,
+        None => count_subscribers(&app_state.pool, &pattern, &status)
+            .await
+            .context("Failed to count subscribers")?,
+    };
+    let subscribers = records
+        .into_iter()
+        .map(|r| SubscriberSummary {
+            subscriber_id: r.id,
+            email: r.email,
+            name: r.name,
+            status: r.status,
+            subscribed_at: r.subscribed_at,
+        })
+        .collect();
+
+    Ok(Json(SubscriberList {
+        subscribers,
+        page: params.page,
+        per_page: params.per_page,
+        total,
+    }))
+}
+
+/// A page past the end has no rows to read the total from.
+#[instrument(skip(pool))]
+async fn count_subscribers(
+    pool: &PgPool,
+    pattern: &Option<String>,
+    status: &Option<String>,
+) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "total!"
+        FROM subscriptions
+        WHERE
+            ($1::text IS NULL OR email ILIKE $1 OR name ILIKE $1) AND
+            ($2::text IS NULL OR status = $2)
+        "#,
+        pattern.as_deref(),
+        status.as_deref(),
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// `%`, `_` and `\` match literally in the search.
+fn escape_like(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+#[instrument(name = "Get a subscriber", skip(app_state))]
+pub(crate) async fn get_subscriber(
+    State(app_state): State<Arc<AppState>>,
+    Path(subscriber_id): Path<Uuid>,
+) -> Result<Json<SubscriberDetails>, AppError> {
+    let r = sqlx::query!(
+        r#"
+        SELECT
+            s.id,
+            s.email,
+            s.name,
+            s.status,
+            s.subscribed_at,
+            s.tags,
+            s.attributes::text AS "attributes!",
+            (
+                SELECT COUNT(*)
+                FROM issue_delivery_queue q
+                WHERE q.subscriber_email = s.email
+            ) AS "queued_deliveries!"
+        FROM subscriptions s
+        WHERE s.id = $1
+        "#,
+        subscriber_id,
+    )
+    .fetch_optional(&app_state.pool)
+    .await
+    .context("Failed to fetch the subscriber")?
+    .ok_or_else(|| not_found(subscriber_id))?;
+    let lists = sqlx::query_as!(
+        ListMembership,
+        r#"
+        SELECT l.slug, m.status
+        FROM list_memberships m
+        JOIN lists l ON l.list_id = m.list_id
+        WHERE m.subscription_id = $1
+        ORDER BY l.slug
+        "#,
+        subscriber_id,
+    )
+    .fetch_all(&app_state.pool)
+    .await
+    .context("Failed to fetch the lists of the subscriber")?;
+    let attributes = serde_json::from_str(&r.attributes)
+        .context("Stored subscriber attributes are not a JSON object")?;
+
+    Ok(Json(SubscriberDetails {
+        subscriber_id: r.id,
+        email: r.email,
+        name: r.name,
+        status: r.status,
+        subscribed_at: r.subscribed_at,
+        tags: r.tags,
+        attributes,
+        lists,
+        queued_deliveries: r.queued_deliveries,
+    }))
+}
+
+/// Confirms a pending subscriber on their behalf, e.g. when the
+/// confirmation email got lost. The link they were sent stops working.
+#[instrument(name = "Confirm a subscriber manually", skip(app_state))]
+pub(crate) async fn confirm_subscriber(
+    State(app_state): State<Arc<AppState>>,
+    Path(subscriber_id): Path<Uuid>,
+) -> Result<StatusCode, AppError> {
+    let mut tx = app_state
+        .pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    match lock_status(&mut tx, subscriber_id).await? {
+        SubscriberStatus::Confirmed => return Ok(StatusCode::OK),
+        SubscriberStatus::PendingConfirmation => {}
+        // They opted out, or their address does not work: confirming them
+        // would mail them against their will, or for nothing.
+        status => {
+            return Err(AppError::E409(anyhow::anyhow!(
+                "The subscriber {subscriber_id} is {status}"
+            )));
+        }
+    }
+
+    confirm_subscription(&mut tx, subscriber_id)
+        .await
+        .context("Failed to confirm the subscriber")?;
+    retire_tokens(&mut tx, subscriber_id)
+        .await
+        .context("Failed to retire the confirmation tokens")?;
+    tx.commit()
+        .await
+        .context("Failed to commit the confirmation")?;
+
+    Ok(StatusCode::OK)
+}
+
+/// Moves a bounced or complaining subscriber back to pending, e.g. once
+/// their mailbox works again. Signing up with the public form then sends
+/// them a confirmation email, which it refuses to do before.
+#[instrument(name = "Reactivate a subscriber", skip(app_state))]
+pub(crate) async fn reactivate_subscriber(
+    State(app_state): State<Arc<AppState>>,
+    Path(subscriber_id): Path<Uuid>,
+) -> Result<StatusCode, AppError> {
+    let mut tx = app_state
+        .pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    match lock_status(&mut tx, subscriber_id).await? {
+        SubscriberStatus::Bounced | SubscriberStatus::Complained => {}
+        status => {
+            return Err(AppError::E409(anyhow::anyhow!(
+                "The subscriber {subscriber_id} is {status}"
+            )));
+        }
+    }
+
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET status = $2
+        WHERE id = $1
+        "#,
+        subscriber_id,
+        SubscriberStatus::PendingConfirmation.to_string(),
+    )
+    .execute(&mut *tx)
+    .await
+    .context("Failed to reactivate the subscriber")?;
+    tx.commit()
+        .await
+        .context("Failed to commit the reactivation")?;
+
+    Ok(StatusCode::OK)
+}
+
+/// Unsubscribes from every list. Bounced subscribers and spam complainers
+/// are left as they are: they are not mailed either, and their status
+/// tells why.
+#[instrument(name = "Unsubscribe a subscriber manually", skip(app_state))]
+pub(crate) async fn unsubscribe_subscriber(
+    State(app_state): State<Arc<AppState>>,
+    Path(subscriber_id): Path<Uuid>,
+) -> Result<StatusCode, AppError> {
+    let mut tx = app_state
+        .pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    if let SubscriberStatus::Confirmed | SubscriberStatus::PendingConfirmation =
+        lock_status(&mut tx, subscriber_id).await?
+    {
+        mark_as_unsubscribed(&mut tx, subscriber_id).await?;
+        tx.commit()
+            .await
+            .context("Failed to commit the unsubscription")?;
+    }
+
+    Ok(StatusCode::OK)
+}
+
+/// Removes the subscriber for good, along with their confirmation and data
+/// request tokens, list memberships and the deliveries still queued for
+/// them, which are counted as skipped.
+#[instrument(name = "Delete a subscriber", skip(app_state))]
+pub(crate) async fn delete_subscriber(
+    State(app_state): State<Arc<AppState>>,
+    Path(subscriber_id): Path<Uuid>,
+) -> Result<StatusCode, AppError> {
+    let mut tx = app_state
+        .pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    lock_status(&mut tx, subscriber_id).await?;
+    delete_subscriber_rows(&mut tx, subscriber_id)
+        .await
+        .context("Failed to delete the subscriber")?;
+    tx.commit()
+        .await
+        .context("Failed to commit the deletion of the subscriber")?;
+
+    Ok(StatusCode::OK)
+}
+
+#[instrument(skip(tx))]
+pub(crate) async fn delete_subscriber_rows(
+    tx: &mut Transaction<'static, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        WITH removed AS (
+            DELETE FROM issue_delivery_queue q
+            USING subscriptions s
+            WHERE s.id = $1 AND q.subscriber_email = s.email
+            RETURNING q.newsletter_issue_id
+        )
+        UPDATE newsletter_issues i
+        SET n_skipped = n_skipped + 1
+        FROM removed r
+        WHERE i.newsletter_issue_id = r.newsletter_issue_id
+        "#,
+        subscriber_id,
+    )
+    .execute(&mut **tx)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM subscription_tokens WHERE subscription_id = $1",
+        subscriber_id,
+    )
+    .execute(&mut **tx)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM data_request_tokens WHERE subscription_id = $1",
+        subscriber_id,
+    )
+    .execute(&mut **tx)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM list_memberships WHERE subscription_id = $1",
+        subscriber_id,
+    )
+    .execute(&mut **tx)
+    .await?;
+    sqlx::query!("DELETE FROM subscriptions WHERE id = $1", subscriber_id)
+        .execute(&mut **tx)
+        .await?;
+
+    Ok(())
+}
+
+/// The status of the subscriber, locked until the end of the transaction.
+#[instrument(skip(tx))]
+async fn lock_status(
+    tx: &mut Transaction<'static, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<SubscriberStatus, AppError> {
+    let record = sqlx::query!(
+        r#"
+        SELECT status
+        FROM subscriptions
+        WHERE id = $1
+        FOR UPDATE
+        "#,
+        subscriber_id,
+    )
+    .fetch_optional(&mut **tx)
+    .await
+    .context("Failed to fetch the subscriber")?
+    .ok_or_else(|| not_found(subscriber_id))?;
+
+    let status = record.status.parse().map_err(anyhow::Error::msg)?;
+    Ok(status)
+}
+
+fn not_found(subscriber_id: Uuid) -> AppError {
+    AppError::E404(anyhow::anyhow!("No subscriber with id {subscriber_id}"))
+}
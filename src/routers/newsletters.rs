@@ -0,0 +1,70 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::extract::{Path, State};
+use axum::response::{Html, IntoResponse, Response};
+use sqlx::PgPool;
+use tracing::instrument;
+use uuid::Uuid;
+
+use crate::app_state::AppState;
+use crate::domain::newsletter_issue::{IssueStatus, web_version_url};
+use crate::domain::newsletter_template::{self, TemplateContext};
+use crate::utils::AppError;
+
+/// The "view in browser" page linked from every newsletter email.
+#[instrument(name = "Render the web version of an issue", skip(app_state))]
+pub(crate) async fn web_version(
+    State(app_state): State<Arc<AppState>>,
+    Path(issue_id): Path<Uuid>,
+) -> Result<Response, AppError> {
+    let (title, html_content) = get_issue(&app_state.pool, issue_id)
+        .await
+        .context("Failed to fetch the newsletter issue")?
+        .ok_or_else(|| {
+            AppError::E404(anyhow::anyhow!(
+                "No newsletter issue with id {issue_id}"
+            ))
+        })?;
+
+    let view_online_url = web_version_url(&app_state.base_url, issue_id)?;
+    let mut unsubscribe_url = view_online_url.clone();
+    unsubscribe_url.set_path("/subscriptions/unsubscribe");
+    let html_content = newsletter_template::render_html(
+        &html_content,
+        &TemplateContext::generic(
+            unsubscribe_url.as_str(),
+            view_online_url.as_str(),
+        ),
+    )
+    .context("Failed to render the newsletter issue")?;
+
+    let page = newsletter_template::render_web_version(&title, &html_content)
+        .context("Failed to render the web version page")?;
+    Ok(Html(page).into_response())
+}
+
+/// Scheduled issues stay private until their send time, cancelled ones for
+/// good.
+#[instrument(skip(pool))]
+async fn get_issue(
+    pool: &PgPool,
+    issue_id: Uuid,
+) -> Result<Option<(String, String)>, sqlx::Error> {
+    let record = sqlx::query!(
+        r#"
+        SELECT title, html_content
+        FROM newsletter_issues
+        WHERE
+            newsletter_issue_id = $1 AND
+            status <> $2 AND
+            published_at <= NOW()
+        "#,
+        issue_id,
+        IssueStatus::Cancelled.to_string(),
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(record.map(|r| (r.title, r.html_content)))
+}
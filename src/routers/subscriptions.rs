@@ -1,5 +1,13 @@
+mod data_access;
+mod data_request;
+mod erasure;
 mod post;
 mod subscriptions_confirm;
+mod unsubscribe;
+
+pub(crate) use post::{insert_subscriber, is_unique_violation};
+pub(crate) use subscriptions_confirm::{confirm_subscription, retire_tokens};
+pub(crate) use unsubscribe::mark_as_unsubscribed;
 
 use crate::app_state::AppState;
 use axum::routing::{get, post};
@@ -12,4 +20,19 @@ pub fn router() -> axum::routing::Router<Arc<AppState>> {
             "/subscriptions/confirm",
             get(subscriptions_confirm::confirm),
         )
+        .route(
+            "/subscriptions/unsubscribe",
+            get(unsubscribe::unsubscribe_form),
+        )
+        .route("/subscriptions/unsubscribe", post(unsubscribe::unsubscribe))
+        .route(
+            "/subscriptions/data-requests",
+            post(data_request::request_data),
+        )
+        .route(
+            "/subscriptions/data-requests",
+            get(data_request::data_request_page),
+        )
+        .route("/subscriptions/data", get(data_access::download_data))
+        .route("/subscriptions/erase", post(erasure::erase))
 }
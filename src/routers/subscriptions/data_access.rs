@@ -0,0 +1,273 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::Json;
+use axum::extract::{Query, State};
+use axum::http::header;
+use axum::response::{IntoResponse, Response};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use sqlx::{Postgres, Transaction};
+use time::OffsetDateTime;
+use tracing::instrument;
+use uuid::Uuid;
+
+use super::data_request::{Params, match_data_request_token};
+use crate::app_state::AppState;
+use crate::utils::AppError;
+
+/// Everything stored about a subscriber's address.
+#[derive(Serialize)]
+pub struct SubscriberData {
+    subscriber: SubscriberRecord,
+    lists: Vec<ListMembership>,
+    confirmation_tokens: Vec<ConfirmationToken>,
+    /// Issues still waiting to be sent to the address
+    queued_deliveries: Vec<QueuedDelivery>,
+    /// Issues that could not be sent to the address
+    failed_deliveries: Vec<FailedDelivery>,
+    /// What the email provider reported about the address
+    email_events: Vec<EmailEvent>,
+    /// Emails not sent yet, such as the one with this link
+    pending_emails: Vec<PendingEmail>,
+}
+
+#[derive(Serialize)]
+pub struct SubscriberRecord {
+    subscriber_id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    #[serde(with = "time::serde::rfc3339")]
+    subscribed_at: OffsetDateTime,
+    tags: Vec<String>,
+    attributes: Map<String, Value>,
+}
+
+#[derive(Serialize)]
+pub struct ListMembership {
+    slug: String,
+    status: String,
+    #[serde(with = "time::serde::rfc3339")]
+    joined_at: OffsetDateTime,
+}
+
+/// The token itself is left out: it is a credential, not personal data.
+#[derive(Serialize)]
+pub struct ConfirmationToken {
+    #[serde(with = "time::serde::rfc3339")]
+    created_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339::option")]
+    used_at: Option<OffsetDateTime>,
+}
+
+#[derive(Serialize)]
+pub struct QueuedDelivery {
+    newsletter_issue_id: Uuid,
+    title: String,
+    #[serde(with = "time::serde::rfc3339")]
+    enqueued_at: OffsetDateTime,
+}
+
+#[derive(Serialize)]
+pub struct FailedDelivery {
+    newsletter_issue_id: Uuid,
+    title: String,
+    last_error: String,
+    n_attempts: i16,
+    #[serde(with = "time::serde::rfc3339")]
+    failed_at: OffsetDateTime,
+}
+
+#[derive(Serialize)]
+pub struct EmailEvent {
+    record_type: String,
+    event_type: Option<String>,
+    payload: Value,
+    #[serde(with = "time::serde::rfc3339")]
+    received_at: OffsetDateTime,
+}
+
+#[derive(Serialize)]
+pub struct PendingEmail {
+    subject: String,
+    #[serde(with = "time::serde::rfc3339")]
+    created_at: OffsetDateTime,
+}
+
+/// Downloads, as a JSON file, everything tied to the address the data
+/// request link was sent to.
+#[instrument(name = "Download subscriber data", skip_all)]
+pub(crate) async fn download_data(
+    State(app_state): State<Arc<AppState>>,
+    Query(params): Query<Params>,
+) -> Result<Response, AppError> {
+    let mut tx = app_state
+        .pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let subscriber_id = match_data_request_token(
+        &mut tx,
+        &params.token,
+        app_state.data_request_token_ttl,
+    )
+    .await?;
+    let data = collect_data(&mut tx, subscriber_id)
+        .await
+        .context("Failed to collect the data of the subscriber")?;
+    tx.commit()
+        .await
+        .context("Failed to commit the data download")?;
+
+    Ok((
+        [(
+            header::CONTENT_DISPOSITION,
+            "attachment; filename=\"subscriber-data.json\"",
+        )],
+        Json(data),
+    )
+        .into_response())
+}
+
+#[instrument(skip(tx))]
+async fn collect_data(
+    tx: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<SubscriberData, anyhow::Error> {
+    let r = sqlx::query!(
+        r#"
+        SELECT
+            id,
+            email,
+            name,
+            status,
+            subscribed_at,
+            tags,
+            attributes::text AS "attributes!"
+        FROM subscriptions
+        WHERE id = $1
+        "#,
+        subscriber_id,
+    )
+    .fetch_one(&mut **tx)
+    .await?;
+    let subscriber = SubscriberRecord {
+        subscriber_id: r.id,
+        email: r.email,
+        name: r.name,
+        status: r.status,
+        subscribed_at: r.subscribed_at,
+        tags: r.tags,
+        attributes: serde_json::from_str(&r.attributes)
+            .context("Stored subscriber attributes are not a JSON object")?,
+    };
+    let email = subscriber.email.as_str();
+
+    let lists = sqlx::query_as!(
+        ListMembership,
+        r#"
+        SELECT l.slug, m.status, m.joined_at
+        FROM list_memberships m
+        JOIN lists l ON l.list_id = m.list_id
+        WHERE m.subscription_id = $1
+        ORDER BY l.slug
+        "#,
+        subscriber_id,
+    )
+    .fetch_all(&mut **tx)
+    .await?;
+    let confirmation_tokens = sqlx::query_as!(
+        ConfirmationToken,
+        r#"
+        SELECT created_at, used_at
+        FROM subscription_tokens
+        WHERE subscription_id = $1
+        ORDER BY created_at
+        "#,
+        subscriber_id,
+    )
+    .fetch_all(&mut **tx)
+    .await?;
+    let queued_deliveries = sqlx::query_as!(
+        QueuedDelivery,
+        r#"
+        SELECT q.newsletter_issue_id, i.title, q.enqueued_at
+        FROM issue_delivery_queue q
+        JOIN newsletter_issues i
+            ON i.newsletter_issue_id = q.newsletter_issue_id
+        WHERE lower(q.subscriber_email) = lower($1)
+        ORDER BY q.enqueued_at
+        "#,
+        email,
+    )
+    .fetch_all(&mut **tx)
+    .await?;
+    let failed_deliveries = sqlx::query_as!(
+        FailedDelivery,
+        r#"
+        SELECT
+            f.newsletter_issue_id,
+            i.title,
+            f.last_error,
+            f.n_attempts,
+            f.failed_at
+        FROM issue_delivery_failures f
+        JOIN newsletter_issues i
+            ON i.newsletter_issue_id = f.newsletter_issue_id
+        WHERE lower(f.subscriber_email) = lower($1)
+        ORDER BY f.failed_at
+        "#,
+        email,
+    )
+    .fetch_all(&mut **tx)
+    .await?;
+    let email_events = sqlx::query!(
+        r#"
+        SELECT
+            record_type,
+            event_type,
+            payload::text AS "payload!",
+            received_at
+        FROM email_events
+        WHERE lower(email) = lower($1)
+        ORDER BY received_at
+        "#,
+        email,
+    )
+    .fetch_all(&mut **tx)
+    .await?
+    .into_iter()
+    .map(|r| {
+        Ok(EmailEvent {
+            record_type: r.record_type,
+            event_type: r.event_type,
+            payload: serde_json::from_str(&r.payload)?,
+            received_at: r.received_at,
+        })
+    })
+    .collect::<Result<_, serde_json::Error>>()
+    .context("Stored email event payloads are not JSON")?;
+    let pending_emails = sqlx::query_as!(
+        PendingEmail,
+        r#"
+        SELECT subject, created_at
+        FROM email_outbox
+        WHERE lower(recipient) = lower($1)
+        ORDER BY created_at
+        "#,
+        email,
+    )
+    .fetch_all(&mut **tx)
+    .await?;
+
+    Ok(SubscriberData {
+        subscriber,
+        lists,
+        confirmation_tokens,
+        queued_deliveries,
+        failed_deliveries,
+        email_events,
+        pending_emails,
+    })
+}
@@ -0,0 +1,198 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use axum::Json;
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::response::{Html, IntoResponse, Response};
+use reqwest::Url;
+use serde::Deserialize;
+use sqlx::{Postgres, Transaction};
+use time::OffsetDateTime;
+use tracing::instrument;
+use uuid::Uuid;
+
+use super::post::generate_token;
+use crate::app_state::AppState;
+use crate::background_workers::email_outbox_worker::enqueue_email;
+use crate::domain::subscriber_email::SubscriberEmail;
+use crate::utils::AppError;
+
+#[derive(Deserialize)]
+pub struct DataRequestForm {
+    email: SubscriberEmail,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Params {
+    pub(super) token: String,
+}
+
+/// Emails the subscriber a link to download or erase everything we hold
+/// about their address.
+///
+/// The response is the same whether or not the address is subscribed, so
+/// that the endpoint cannot be used to find out who is.
+#[instrument(name = "Request subscriber data", skip_all)]
+pub(crate) async fn request_data(
+    State(app_state): State<Arc<AppState>>,
+    Json(form): Json<DataRequestForm>,
+) -> Result<StatusCode, AppError> {
+    let mut tx = app_state
+        .pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let subscriber = sqlx::query!(
+        r#"
+        SELECT id
+        FROM subscriptions
+        WHERE lower(email) = lower($1)
+        FOR UPDATE
+        "#,
+        form.email.as_ref(),
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .context("Failed to look up the subscriber")?;
+    let Some(subscriber) = subscriber else {
+        return Ok(StatusCode::OK);
+    };
+
+    let token = generate_token();
+    // Rotate: only the link from the most recent email stays valid.
+    sqlx::query!(
+        "DELETE FROM data_request_tokens WHERE subscription_id = $1",
+        subscriber.id,
+    )
+    .execute(&mut *tx)
+    .await
+    .context("Failed to remove previous data request tokens")?;
+    sqlx::query!(
+        r#"
+        INSERT INTO data_request_tokens (data_request_token, subscription_id)
+        VALUES ($1, $2)
+        "#,
+        token,
+        subscriber.id,
+    )
+    .execute(&mut *tx)
+    .await
+    .context("Failed to store the data request token")?;
+    enqueue_data_request_email(
+        &mut tx,
+        &app_state.base_url,
+        &form.email,
+        &token,
+    )
+    .await
+    .context("Failed to enqueue the data request email")?;
+    tx.commit()
+        .await
+        .context("Failed to commit the data request")?;
+
+    Ok(StatusCode::OK)
+}
+
+#[instrument(skip(tx, to, token))]
+async fn enqueue_data_request_email(
+    tx: &mut Transaction<'_, Postgres>,
+    base_url: &str,
+    to: &SubscriberEmail,
+    token: &str,
+) -> Result<(), sqlx::Error> {
+    let link = {
+        let mut l = Url::parse(base_url).expect("Invalid base url");
+        l.set_path("/subscriptions/data-requests");
+        l.query_pairs_mut().append_pair("token", token);
+
+        l
+    };
+
+    let html_body = format!(
+        "We received a request for the data we hold about this address.<br />\
+        Click <a href=\"{link}\">here</a> to download or erase it. \
+        If you did not ask for it, you can ignore this email."
+    );
+    let text_body = format!(
+        "We received a request for the data we hold about this address.\n\
+        Visit {link} to download or erase it. \
+        If you did not ask for it, you can ignore this email."
+    );
+
+    enqueue_email(tx, to, "Your data", &text_body, &html_body).await
+}
+
+/// Where the emailed link lands. Like the unsubscribe page, it only offers
+/// the actions: a GET must not erase anything.
+#[instrument(name = "Render data request page", skip_all)]
+pub(crate) async fn data_request_page(
+    State(app_state): State<Arc<AppState>>,
+    Query(params): Query<Params>,
+) -> Result<Response, AppError> {
+    let mut tx = app_state
+        .pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    match_data_request_token(
+        &mut tx,
+        &params.token,
+        app_state.data_request_token_ttl,
+    )
+    .await?;
+
+    let token = params.token;
+    let html_content = format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta http-equiv="content-type" content="text/html; charset=utf-8">
+<title>Your data</title>
+</head>
+<body>
+<p><a href="/subscriptions/data?token={token}">Download your data</a></p>
+<form action="/subscriptions/erase?token={token}" method="post">
+<p>Erasing your data unsubscribes you from every list, for good.</p>
+<button type="submit">Erase your data</button>
+</form>
+</body>
+</html>"#
+    );
+    Ok(Html(html_content).into_response())
+}
+
+/// The subscriber the link was sent to, locked until the end of the
+/// transaction.
+///
+/// The token is random and only stored here rather than signed: it can be
+/// revoked, and it never outlives its purpose. Asking again replaces it,
+/// erasing deletes it along with the subscriber so that the link cannot be
+/// replayed, and it expires after its own, short, TTL.
+#[instrument(name = "Match data request token", skip(tx, token))]
+pub(super) async fn match_data_request_token(
+    tx: &mut Transaction<'_, Postgres>,
+    token: &str,
+    ttl: Duration,
+) -> Result<Uuid, AppError> {
+    let expired_at = OffsetDateTime::now_utc() - ttl;
+    let record = sqlx::query!(
+        r#"
+        SELECT subscription_id
+        FROM data_request_tokens
+        WHERE data_request_token = $1 AND created_at > $2
+        FOR UPDATE
+        "#,
+        token,
+        expired_at,
+    )
+    .fetch_optional(&mut **tx)
+    .await
+    .context("Failed to look up the data request token")?
+    .ok_or_else(|| {
+        AppError::E401(anyhow::anyhow!("Unknown or expired data request token"))
+    })?;
+
+    Ok(record.subscription_id)
+}
@@ -0,0 +1,88 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::extract::{Query, State};
+use axum::response::{Html, IntoResponse, Response};
+use sqlx::{Postgres, Transaction};
+use tracing::instrument;
+use uuid::Uuid;
+
+use super::data_request::{Params, match_data_request_token};
+use crate::app_state::AppState;
+use crate::routers::admin::delete_subscriber_rows;
+use crate::utils::AppError;
+
+/// Erases the subscriber the data request link was sent to.
+#[instrument(name = "Erase subscriber data", skip_all)]
+pub(crate) async fn erase(
+    State(app_state): State<Arc<AppState>>,
+    Query(params): Query<Params>,
+) -> Result<Response, AppError> {
+    let mut tx = app_state
+        .pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let subscriber_id = match_data_request_token(
+        &mut tx,
+        &params.token,
+        app_state.data_request_token_ttl,
+    )
+    .await?;
+    // Spends the link too: its token goes with the subscriber
+    erase_subscriber(&mut tx, subscriber_id)
+        .await
+        .context("Failed to erase the subscriber")?;
+    tx.commit().await.context("Failed to commit the erasure")?;
+
+    Ok(Html("Your data has been erased.").into_response())
+}
+
+/// Deletes the subscriber like an admin would, along with the delivery
+/// failures, provider events and unsent emails of their address, whatever
+/// its case, and records that the erasure happened.
+#[instrument(skip(tx))]
+async fn erase_subscriber(
+    tx: &mut Transaction<'static, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_failures f
+        USING subscriptions s
+        WHERE s.id = $1 AND lower(f.subscriber_email) = lower(s.email)
+        "#,
+        subscriber_id,
+    )
+    .execute(&mut **tx)
+    .await?;
+    sqlx::query!(
+        r#"
+        DELETE FROM email_events e
+        USING subscriptions s
+        WHERE s.id = $1 AND lower(e.email) = lower(s.email)
+        "#,
+        subscriber_id,
+    )
+    .execute(&mut **tx)
+    .await?;
+    sqlx::query!(
+        r#"
+        DELETE FROM email_outbox o
+        USING subscriptions s
+        WHERE s.id = $1 AND lower(o.recipient) = lower(s.email)
+        "#,
+        subscriber_id,
+    )
+    .execute(&mut **tx)
+    .await?;
+    delete_subscriber_rows(tx, subscriber_id).await?;
+    sqlx::query!(
+        "INSERT INTO subscriber_erasures (subscription_id) VALUES ($1)",
+        subscriber_id,
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
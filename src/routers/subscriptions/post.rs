@@ -2,23 +2,40 @@ use std::sync::Arc;
 
 use crate::{
     app_state::AppState,
+    background_workers::email_outbox_worker::enqueue_email,
     domain::{
+        mailing_list::ListSlug,
         subscriber::{Subscriber, SubscriberStatus},
+        subscriber_attributes::SubscriberAttributes,
         subscriber_email::SubscriberEmail,
+        subscriber_name::SubscriberName,
+        subscriber_tag::SubscriberTag,
+    },
+    routers::{
+        admin::{ListLookupError, find_list_ids},
+        error_chain_fmt,
+        subscriptions::confirm_subscription,
     },
-    email_client::EmailClient,
-    routers::error_chain_fmt,
 };
 use anyhow::Context;
 use axum::{Json, extract::State, http::StatusCode, response::IntoResponse};
 use rand::distr::{Alphanumeric, SampleString};
 use reqwest::Url;
+use serde::Deserialize;
 use sqlx::{Postgres, Transaction};
 use tracing::instrument;
 use uuid::Uuid;
 
 #[derive(thiserror::Error)]
 pub enum SubscriptionError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("A subscription for this email is already being processed")]
+    Conflict(#[source] anyhow::Error),
+    /// The confirmation email is only queued here, in the same transaction
+    /// as the subscriber: nothing was stored, and trying again may work.
+    #[error("Failed to queue the confirmation email")]
+    ConfirmationEmailFailed(#[source] sqlx::Error),
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -26,6 +43,9 @@ pub enum SubscriptionError {
 impl SubscriptionError {
     pub fn status_code(&self) -> StatusCode {
         match self {
+            Self::ValidationError(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::Conflict(_) => StatusCode::CONFLICT,
+            Self::ConfirmationEmailFailed(_) => StatusCode::SERVICE_UNAVAILABLE,
             Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
@@ -59,61 +79,197 @@ impl IntoResponse for SubscriptionError {
     }
 }
 
+#[derive(Deserialize)]
+pub struct FormData {
+    name: String,
+    email: String,
+    /// Slugs of the lists to join, the default list if empty
+    #[serde(default)]
+    lists: Vec<String>,
+    /// Only stored for a new address: the form is public, so signing up
+    /// again must not change the profile of an existing subscriber.
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    attributes: serde_json::Map<String, serde_json::Value>,
+}
+
+impl TryFrom<FormData> for Subscriber {
+    type Error = String;
+
+    fn try_from(form: FormData) -> Result<Self, Self::Error> {
+        let name = SubscriberName::try_from(form.name)?;
+        let email = SubscriberEmail::try_from(form.email)?;
+        let mut lists = form
+            .lists
+            .into_iter()
+            .map(ListSlug::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
+        if lists.is_empty() {
+            lists.push(ListSlug::default_list());
+        }
+        let tags = form
+            .tags
+            .into_iter()
+            .map(SubscriberTag::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
+        let attributes = SubscriberAttributes::try_from(form.attributes)?;
+
+        Ok(Self {
+            name,
+            email,
+            lists,
+            tags,
+            attributes,
+        })
+    }
+}
+
 #[instrument(
     name = "Adding a new subscriber",
-    skip(app_state, user),
+    skip(app_state, form),
     fields(
         request_id = %uuid::Uuid::new_v4(),
-        subscriber_email = %user.email.as_ref(),
-        subscriber_name = %user.name.as_ref()
+        subscriber_email = %form.email,
+        subscriber_name = %form.name
     )
 )]
 pub(crate) async fn subscript(
     State(app_state): State<Arc<AppState>>,
-    Json(user): Json<Subscriber>,
+    Json(form): Json<FormData>,
 ) -> Result<StatusCode, SubscriptionError> {
+    let user: Subscriber = form
+        .try_into()
+        .map_err(SubscriptionError::ValidationError)?;
+    let list_ids = find_list_ids(&app_state.pool, &user.lists).await.map_err(
+        |e| match e {
+            ListLookupError::UnknownList(_) => {
+                SubscriptionError::ValidationError(e.to_string())
+            }
+            ListLookupError::UnexpectedError(e) => e.into(),
+        },
+    )?;
+
     let mut tx = app_state
         .pool
         .begin()
         .await
         .context("Failed to acquire a Postgres connection from the pool")?;
 
-    let subscriber_id = insert_user(&mut tx, &user)
+    let existing = get_existing_subscriber(&mut tx, &user.email)
         .await
-        .context("Failed to insert new subscriber in the database.")?;
+        .context("Failed to look up an existing subscriber.")?;
+    let (subscriber_id, is_confirmed) = match existing {
+        None => {
+            let subscriber_id =
+                insert_user(&mut tx, &user).await.map_err(|e| {
+                    if is_unique_violation(&e) {
+                        SubscriptionError::Conflict(e.into())
+                    } else {
+                        anyhow::Error::new(e)
+                            .context("Failed to insert new subscriber.")
+                            .into()
+                    }
+                })?;
+            (subscriber_id, false)
+        }
+        Some((_, SubscriberStatus::Bounced | SubscriberStatus::Complained)) => {
+            // The provider told us the address is dead or does not want our
+            // mail: anyone can post it, so only an admin can reactivate it.
+            // The response must not tell the caller, though.
+            return Ok(StatusCode::OK);
+        }
+        Some((subscriber_id, status)) => {
+            // Signing up again is an explicit opt-in, which the confirmation
+            // email double-checks.
+            if status == SubscriberStatus::Unsubscribed {
+                resubscribe(&mut tx, subscriber_id).await.context(
+                    "Failed to move an unsubscribed subscriber back to pending.",
+                )?;
+            }
+            (subscriber_id, status == SubscriberStatus::Confirmed)
+        }
+    };
+
+    let n_joined = join_lists(&mut tx, subscriber_id, &list_ids)
+        .await
+        .context("Failed to add the subscriber to the requested lists.")?;
+    if is_confirmed && n_joined == 0 {
+        // Nothing left to confirm, and nothing the caller needs to know
+        // about.
+        tx.commit().await.context(
+            "Failed to commit SQL transaction to update a subscriber.",
+        )?;
+        return Ok(StatusCode::OK);
+    }
 
     let token = generate_token();
 
+    // Rotate: only the link from the most recent email stays valid.
+    delete_tokens(&mut tx, subscriber_id)
+        .await
+        .context("Failed to remove previous confirmation tokens.")?;
     store_token(&mut tx, subscriber_id, &token).await.context(
         "Failed to store the confirmation token for a new subscriber.",
     )?;
 
-    tx.commit().await.context(
-        "Failed to commit SQL transaction to store a new subscriber.",
-    )?;
-
-    send_confirmation_email(
-        &app_state.email_client,
+    enqueue_confirmation_email(
+        &mut tx,
         &app_state.base_url,
-        user.email,
+        &user.email,
         &token,
     )
     .await
-    .context("Failed to send a confirmation email.")?;
+    .map_err(SubscriptionError::ConfirmationEmailFailed)?;
+
+    tx.commit().await.context(
+        "Failed to commit SQL transaction to store a new subscriber.",
+    )?;
 
     Ok(StatusCode::OK)
 }
 
+/// Stores a subscriber whose email is not taken yet, as a member of the
+/// lists. They are sent the confirmation email unless `confirmed`, e.g.
+/// when they opted in somewhere else already.
+#[instrument(name = "Insert a subscriber", skip(tx, base_url, user))]
+pub(crate) async fn insert_subscriber(
+    tx: &mut Transaction<'_, Postgres>,
+    base_url: &str,
+    user: &Subscriber,
+    list_ids: &[Uuid],
+    confirmed: bool,
+) -> Result<Uuid, sqlx::Error> {
+    let subscriber_id = insert_user(tx, user).await?;
+    join_lists(tx, subscriber_id, list_ids).await?;
+    if confirmed {
+        confirm_subscription(tx, subscriber_id).await?;
+    } else {
+        let token = generate_token();
+        store_token(tx, subscriber_id, &token)
+            .await
+            .map_err(|e| e.0)?;
+        enqueue_confirmation_email(tx, base_url, &user.email, &token).await?;
+    }
+
+    Ok(subscriber_id)
+}
+
+pub(crate) fn is_unique_violation(e: &sqlx::Error) -> bool {
+    e.as_database_error()
+        .is_some_and(|e| e.is_unique_violation())
+}
+
 #[instrument(
-    name = "Send confirmation email to subscriber",
-    skip(email_client, to)
+    name = "Enqueue confirmation email to subscriber",
+    skip(tx, to, token)
 )]
-async fn send_confirmation_email(
-    email_client: &EmailClient,
+async fn enqueue_confirmation_email(
+    tx: &mut Transaction<'_, Postgres>,
     base_url: &str,
-    to: SubscriberEmail,
+    to: &SubscriberEmail,
     token: &str,
-) -> Result<(), reqwest::Error> {
+) -> Result<(), sqlx::Error> {
     let confirmation_link = {
         let mut l = Url::parse(base_url).expect("Invalid base url");
         l.set_path("/subscriptions/confirm");
@@ -130,9 +286,84 @@ async fn send_confirmation_email(
         "Welcome to our newsletter!\nVisit {confirmation_link} to confirm your subscription."
     );
 
-    email_client
-        .send_email(&to, "Welcome", &pain_text_body, &html_body)
-        .await
+    enqueue_email(tx, to, "Welcome", &pain_text_body, &html_body).await
+}
+
+#[instrument(name = "Get existing subscriber", skip(tx, email))]
+async fn get_existing_subscriber(
+    tx: &mut Transaction<'_, Postgres>,
+    email: &SubscriberEmail,
+) -> Result<Option<(Uuid, SubscriberStatus)>, anyhow::Error> {
+    let record = sqlx::query!(
+        r#"
+        SELECT id, status
+        FROM subscriptions
+        WHERE lower(email) = lower($1)
+        FOR UPDATE
+        "#,
+        email.as_ref(),
+    )
+    .fetch_optional(&mut **tx)
+    .await?;
+
+    match record {
+        Some(r) => {
+            let status = r.status.parse().map_err(anyhow::Error::msg)?;
+            Ok(Some((r.id, status)))
+        }
+        None => Ok(None),
+    }
+}
+
+#[instrument(name = "Resubscribe an unsubscribed subscriber", skip(tx))]
+async fn resubscribe(
+    tx: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET status = $1
+        WHERE id = $2
+        "#,
+        SubscriberStatus::PendingConfirmation.to_string(),
+        subscriber_id,
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+fn tag_strings(tags: &[SubscriberTag]) -> Vec<String> {
+    tags.iter().map(ToString::to_string).collect()
+}
+
+/// Adds pending memberships for the lists the subscriber has not confirmed
+/// yet, returning how many there are.
+#[instrument(name = "Join mailing lists", skip(tx))]
+async fn join_lists(
+    tx: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    list_ids: &[Uuid],
+) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO list_memberships (list_id, subscription_id, status)
+        SELECT UNNEST($1::uuid[]), $2, $3
+        ON CONFLICT (list_id, subscription_id) DO UPDATE
+        SET status = EXCLUDED.status, joined_at = NOW()
+        WHERE list_memberships.status <> $4
+        "#,
+        list_ids,
+        subscriber_id,
+        SubscriberStatus::PendingConfirmation.to_string(),
+        SubscriberStatus::Confirmed.to_string(),
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(result.rows_affected())
 }
 
 #[instrument(name = "Inserting a new user in the database", skip(tx, user))]
@@ -144,13 +375,25 @@ async fn insert_user(
 
     sqlx::query!(
         r#"
-        INSERT INTO subscriptions (id, name, email, subscribed_at, status)
-        VALUES ($1, $2, $3, NOW(), $4)
+        INSERT INTO subscriptions (
+            id,
+            name,
+            email,
+            subscribed_at,
+            status,
+            unsubscribe_token,
+            tags,
+            attributes
+        )
+        VALUES ($1, $2, $3, NOW(), $4, $5, $6, $7::text::jsonb)
         "#,
         id,
         user.name.as_ref(),
         user.email.as_ref(),
         SubscriberStatus::PendingConfirmation.to_string(),
+        generate_token(),
+        &tag_strings(&user.tags),
+        user.attributes.to_json(),
     )
     .execute(&mut **tx) // WHY ????
     .await?;
@@ -182,6 +425,24 @@ impl std::fmt::Debug for StoreTokenError {
     }
 }
 
+#[instrument(name = "Delete subscription tokens", skip(tx))]
+async fn delete_tokens(
+    tx: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM subscription_tokens
+        WHERE subscription_id = $1
+        "#,
+        subscriber_id,
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
 #[instrument(name = "Store subscription token in the database", skip(tx))]
 async fn store_token(
     tx: &mut Transaction<'_, Postgres>,
@@ -190,8 +451,10 @@ async fn store_token(
 ) -> Result<(), StoreTokenError> {
     sqlx::query!(
         r#"
-        INSERT INTO subscription_tokens (subscription_token, subscription_id)
-        VALUES ($1, $2)
+        INSERT INTO subscription_tokens (
+            subscription_token, subscription_id, created_at
+        )
+        VALUES ($1, $2, NOW())
         "#,
         token,
         subscriber_id,
@@ -208,6 +471,6 @@ async fn store_token(
 }
 
 #[instrument(name = "Generate subscription token")]
-fn generate_token() -> String {
+pub(super) fn generate_token() -> String {
     Alphanumeric.sample_string(&mut rand::rng(), 25)
 }
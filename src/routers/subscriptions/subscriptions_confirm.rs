@@ -1,9 +1,11 @@
 use std::sync::Arc;
+use std::time::Duration;
 
 use axum::extract::{Query, State};
 use axum::http::StatusCode;
 use serde::Deserialize;
-use sqlx::PgPool;
+use sqlx::{Postgres, Transaction};
+use time::OffsetDateTime;
 use tracing::instrument;
 use uuid::Uuid;
 
@@ -15,6 +17,13 @@ pub struct Params {
     token: String,
 }
 
+enum TokenOutcome {
+    Consumed(Uuid),
+    Unknown,
+    Expired,
+    AlreadyUsed,
+}
+
 #[instrument(
     name = "Confirm a pending confirmed subscription",
     skip(app_state)
@@ -23,71 +32,168 @@ pub(crate) async fn confirm(
     State(app_state): State<Arc<AppState>>,
     Query(params): Query<Params>,
 ) -> StatusCode {
-    let subscriber_id =
-        match match_subscription(&app_state.pool, &params.token).await {
-            Ok(uuid) => match uuid {
-                Some(id) => id,
-                None => return StatusCode::UNAUTHORIZED,
-            },
-            Err(_) => return StatusCode::INTERNAL_SERVER_ERROR,
-        };
-
-    if confirm_subscription(&app_state.pool, subscriber_id)
-        .await
-        .is_err()
+    let Ok(mut tx) = app_state.pool.begin().await else {
+        return StatusCode::INTERNAL_SERVER_ERROR;
+    };
+
+    let subscriber_id = match consume_token(
+        &mut tx,
+        &params.token,
+        app_state.subscription_token_ttl,
+    )
+    .await
     {
+        Ok(TokenOutcome::Consumed(id)) => id,
+        Ok(TokenOutcome::Unknown) => return StatusCode::UNAUTHORIZED,
+        Ok(TokenOutcome::Expired) => return StatusCode::GONE,
+        Ok(TokenOutcome::AlreadyUsed) => return StatusCode::CONFLICT,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR,
+    };
+
+    match confirm_subscription(&mut tx, subscriber_id).await {
+        Ok(true) => {}
+        Ok(false) => return StatusCode::CONFLICT,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR,
+    }
+
+    if tx.commit().await.is_err() {
         return StatusCode::INTERNAL_SERVER_ERROR;
     }
 
     StatusCode::OK
 }
 
-#[instrument(name = "Match subscription token", skip(pool, token))]
-async fn match_subscription(
-    pool: &PgPool,
+/// Marks the token as used if it is still valid. The check and the update
+/// happen in a single statement, so concurrent replays of the same link
+/// cannot both succeed.
+#[instrument(name = "Consume subscription token", skip(tx, token))]
+async fn consume_token(
+    tx: &mut Transaction<'_, Postgres>,
     token: &str,
-) -> Result<Option<Uuid>, sqlx::Error> {
-    let result = sqlx::query!(
+    ttl: Duration,
+) -> Result<TokenOutcome, sqlx::Error> {
+    let expired_at = OffsetDateTime::now_utc() - ttl;
+
+    let consumed = sqlx::query!(
+        r#"
+        UPDATE subscription_tokens
+        SET used_at = NOW()
+        WHERE
+            subscription_token = $1 AND
+            used_at IS NULL AND
+            created_at > $2
+        RETURNING subscription_id
+        "#,
+        token,
+        expired_at,
+    )
+    .fetch_optional(&mut **tx)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {e:?}");
+        e
+    })?;
+
+    if let Some(r) = consumed {
+        return Ok(TokenOutcome::Consumed(r.subscription_id));
+    }
+
+    let stored = sqlx::query!(
         r#"
-         SELECT subscription_id
-         FROM subscription_tokens
-         WHERE subscription_token = $1
+        SELECT used_at
+        FROM subscription_tokens
+        WHERE subscription_token = $1
         "#,
         token
     )
-    .fetch_optional(pool)
+    .fetch_optional(&mut **tx)
     .await
     .map_err(|e| {
         tracing::error!("Failed to execute query: {e:?}");
         e
     })?;
 
-    Ok(result.map(|r| r.subscription_id))
+    let outcome = match stored {
+        None => TokenOutcome::Unknown,
+        Some(r) if r.used_at.is_some() => TokenOutcome::AlreadyUsed,
+        Some(_) => TokenOutcome::Expired,
+    };
+    Ok(outcome)
 }
 
+/// Confirms the subscriber and the lists they joined, unless they left in
+/// the meantime: an unsubscribed, bounced or complaining subscriber is
+/// left as is, and `false` returned. A confirmed subscriber still gets
+/// the lists they joined since confirmed.
 #[instrument(
     name = "Change subscription status to confirm in db",
-    skip(pool, subscriber_id)
+    skip(tx, subscriber_id)
 )]
-async fn confirm_subscription(
-    pool: &PgPool,
+pub(crate) async fn confirm_subscription(
+    tx: &mut Transaction<'_, Postgres>,
     subscriber_id: Uuid,
-) -> Result<(), sqlx::Error> {
-    sqlx::query!(
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
         r#"
         UPDATE subscriptions
         SET status = $1
-        WHERE id = $2
+        WHERE id = $2 AND status = ANY($3)
         "#,
         SubscriberStatus::Confirmed.to_string(),
-        subscriber_id
+        subscriber_id,
+        &[
+            SubscriberStatus::PendingConfirmation.to_string(),
+            SubscriberStatus::Confirmed.to_string(),
+        ],
     )
-    .execute(pool)
+    .execute(&mut **tx)
     .await
     .map_err(|e| {
         tracing::error!("Failed to execute query: {e:?}");
         e
     })?;
+    if result.rows_affected() == 0 {
+        return Ok(false);
+    }
+
+    // The link confirms every list joined since the previous one was sent
+    sqlx::query!(
+        r#"
+        UPDATE list_memberships
+        SET status = $1
+        WHERE subscription_id = $2 AND status = $3
+        "#,
+        SubscriberStatus::Confirmed.to_string(),
+        subscriber_id,
+        SubscriberStatus::PendingConfirmation.to_string(),
+    )
+    .execute(&mut **tx)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {e:?}");
+        e
+    })?;
+
+    Ok(true)
+}
+
+/// Spends the confirmation links still out there, e.g. when the subscriber
+/// leaves: an old link must not sign them back up.
+#[instrument(name = "Retire confirmation tokens", skip(tx))]
+pub(crate) async fn retire_tokens(
+    tx: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE subscription_tokens
+        SET used_at = NOW()
+        WHERE subscription_id = $1 AND used_at IS NULL
+        "#,
+        subscriber_id,
+    )
+    .execute(&mut **tx)
+    .await?;
 
     Ok(())
 }
@@ -0,0 +1,164 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::extract::{Query, State};
+use axum::response::{Html, IntoResponse, Response};
+use serde::Deserialize;
+use sqlx::{PgPool, Postgres, Transaction};
+use tracing::instrument;
+use uuid::Uuid;
+
+use super::retire_tokens;
+use crate::app_state::AppState;
+use crate::domain::subscriber::SubscriberStatus;
+use crate::utils::AppError;
+
+#[derive(Deserialize, Debug)]
+pub struct Params {
+    token: String,
+}
+
+/// The web version of an issue links here without a token: there is no
+/// subscriber to unsubscribe, only the way to do it to explain.
+#[derive(Deserialize, Debug)]
+pub struct FormParams {
+    token: Option<String>,
+}
+
+#[instrument(name = "Render unsubscribe page", skip_all)]
+pub(crate) async fn unsubscribe_form(
+    State(app_state): State<Arc<AppState>>,
+    Query(params): Query<FormParams>,
+) -> Result<Response, AppError> {
+    let Some(token) = params.token else {
+        return Ok(Html(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta http-equiv="content-type" content="text/html; charset=utf-8">
+<title>Unsubscribe</title>
+</head>
+<body>
+<p>To unsubscribe, follow the unsubscribe link at the bottom of any
+newsletter email you received: it is the only way we can tell who you
+are.</p>
+</body>
+</html>"#,
+        )
+        .into_response());
+    };
+    let subscriber_id = match_unsubscribe_token(&app_state.pool, &token)
+        .await
+        .map_err(AppError::E500)?;
+    if subscriber_id.is_none() {
+        return Err(AppError::E401(anyhow::anyhow!(
+            "Unknown unsubscribe token"
+        )));
+    }
+
+    // The link in the email only lands on this page: a GET must not change
+    // state, otherwise link scanners would unsubscribe people on their behalf.
+    let html_content = format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta http-equiv="content-type" content="text/html; charset=utf-8">
+<title>Unsubscribe</title>
+</head>
+<body>
+<form action="/subscriptions/unsubscribe?token={token}" method="post">
+<input type="hidden" name="List-Unsubscribe" value="One-Click">
+<button type="submit">Unsubscribe</button>
+</form>
+</body>
+</html>"#
+    );
+    Ok(Html(html_content).into_response())
+}
+
+/// One-click unsubscribe as described in RFC 8058, from every list.
+///
+/// Mail clients POST `List-Unsubscribe=One-Click` to the URL advertised in
+/// the `List-Unsubscribe` header; the token in the query string is all we
+/// need, so the body is not inspected.
+#[instrument(name = "Unsubscribe a subscriber", skip_all)]
+pub(crate) async fn unsubscribe(
+    State(app_state): State<Arc<AppState>>,
+    Query(params): Query<Params>,
+) -> Result<Response, AppError> {
+    let subscriber_id = match_unsubscribe_token(&app_state.pool, &params.token)
+        .await
+        .map_err(AppError::E500)?
+        .ok_or_else(|| {
+            AppError::E401(anyhow::anyhow!("Unknown unsubscribe token"))
+        })?;
+
+    let mut tx = app_state
+        .pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    mark_as_unsubscribed(&mut tx, subscriber_id).await?;
+    tx.commit()
+        .await
+        .context("Failed to commit the unsubscription")?;
+
+    Ok(Html("You have been unsubscribed.").into_response())
+}
+
+#[instrument(name = "Match unsubscribe token", skip(pool, token))]
+async fn match_unsubscribe_token(
+    pool: &PgPool,
+    token: &str,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"
+        SELECT id
+        FROM subscriptions
+        WHERE unsubscribe_token = $1
+        "#,
+        token
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up unsubscribe token")?;
+
+    Ok(result.map(|r| r.id))
+}
+
+/// Leaves every list as well, and spends the confirmation links sent.
+#[instrument(name = "Change subscription status to unsubscribed", skip(tx))]
+pub(crate) async fn mark_as_unsubscribed(
+    tx: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET status = $1
+        WHERE id = $2
+        "#,
+        SubscriberStatus::Unsubscribed.to_string(),
+        subscriber_id
+    )
+    .execute(&mut **tx)
+    .await
+    .context("Failed to unsubscribe subscriber")?;
+    sqlx::query!(
+        r#"
+        UPDATE list_memberships
+        SET status = $1
+        WHERE subscription_id = $2
+        "#,
+        SubscriberStatus::Unsubscribed.to_string(),
+        subscriber_id
+    )
+    .execute(&mut **tx)
+    .await
+    .context("Failed to leave the mailing lists")?;
+    retire_tokens(tx, subscriber_id)
+        .await
+        .context("Failed to retire the confirmation tokens")?;
+
+    Ok(())
+}
@@ -0,0 +1,9 @@
+mod postmark;
+
+use crate::app_state::AppState;
+use axum::routing::post;
+use std::sync::Arc;
+
+pub fn router() -> axum::routing::Router<Arc<AppState>> {
+    axum::Router::new().route("/postmark", post(postmark::postmark_webhook))
+}
@@ -0,0 +1,199 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+};
+use secrecy::ExposeSecret;
+use serde::Deserialize;
+use sqlx::{Postgres, Transaction};
+use tracing::instrument;
+use uuid::Uuid;
+
+use crate::{
+    app_state::AppState, authentication::basic_authentication,
+    configuration::WebhookSettings, domain::subscriber::SubscriberStatus,
+    routers::subscriptions::retire_tokens, utils::AppError,
+};
+
+/// The fields we use from Postmark's bounce and spam complaint webhooks.
+/// https://postmarkapp.com/developer/webhooks/bounce-webhook
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct PostmarkEvent {
+    record_type: String,
+    #[serde(rename = "ID")]
+    id: Option<i64>,
+    #[serde(rename = "Type")]
+    event_type: Option<String>,
+    email: Option<String>,
+    /// Whether Postmark stopped sending to the address because of it
+    #[serde(default)]
+    inactive: bool,
+}
+
+impl PostmarkEvent {
+    /// The status the matching subscriber moves to, if any. Soft bounces
+    /// and other transient events are only recorded.
+    fn subscriber_status(&self) -> Option<SubscriberStatus> {
+        match self.record_type.as_str() {
+            "SpamComplaint" => Some(SubscriberStatus::Complained),
+            "Bounce"
+                if self.inactive
+                    || self.event_type.as_deref() == Some("HardBounce") =>
+            {
+                Some(SubscriberStatus::Bounced)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[instrument(name = "Ingest a Postmark webhook", skip_all)]
+pub(crate) async fn postmark_webhook(
+    State(app_state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<StatusCode, AppError> {
+    authenticate(&headers, app_state.webhook_credentials.as_ref())
+        .map_err(AppError::E401)?;
+
+    let payload = std::str::from_utf8(&body)
+        .context("The payload is not UTF-8")
+        .map_err(AppError::E400)?;
+    let event: PostmarkEvent = serde_json::from_str(payload)
+        .context("Failed to parse the Postmark payload")
+        .map_err(AppError::E400)?;
+    tracing::info!(
+        record_type = %event.record_type,
+        event_type = ?event.event_type,
+        "Received a Postmark event"
+    );
+
+    // Deliveries, opens and clicks are not of interest for now
+    if !matches!(event.record_type.as_str(), "Bounce" | "SpamComplaint") {
+        return Ok(StatusCode::OK);
+    }
+    let email = event
+        .email
+        .as_deref()
+        .context("The event has no email address")
+        .map_err(AppError::E400)?;
+
+    let mut tx = app_state
+        .pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    let recorded = record_event(&mut tx, &event, email, payload)
+        .await
+        .context("Failed to record the email event")?;
+    if let (true, Some(status)) = (recorded, event.subscriber_status()) {
+        update_subscriber_status(&mut tx, email, status)
+            .await
+            .context("Failed to update the subscriber status")?;
+    }
+
+    tx.commit()
+        .await
+        .context("Failed to commit the email event")?;
+
+    Ok(StatusCode::OK)
+}
+
+fn authenticate(
+    headers: &HeaderMap,
+    expected: Option<&WebhookSettings>,
+) -> Result<(), anyhow::Error> {
+    let expected = expected.context("No webhook credentials are configured")?;
+    let credentials = basic_authentication(headers)?;
+
+    let username_matches =
+        constant_time_eq(&credentials.username, &expected.username);
+    let password_matches = constant_time_eq(
+        credentials.password.expose_secret(),
+        expected.password.expose_secret(),
+    );
+    anyhow::ensure!(
+        username_matches && password_matches,
+        "Invalid webhook credentials"
+    );
+
+    Ok(())
+}
+
+/// Compares without returning early, so the time taken does not reveal
+/// how much of a guess was right.
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
+/// Returns false if Postmark already delivered this event, as it retries
+/// webhooks that did not get a timely answer.
+#[instrument(skip(tx, payload))]
+async fn record_event(
+    tx: &mut Transaction<'_, Postgres>,
+    event: &PostmarkEvent,
+    email: &str,
+    payload: &str,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO email_events (
+            event_id,
+            provider_event_id,
+            record_type,
+            event_type,
+            email,
+            payload
+        )
+        VALUES ($1, $2, $3, $4, $5, $6::text::jsonb)
+        ON CONFLICT (record_type, provider_event_id) DO NOTHING
+        "#,
+        Uuid::new_v4(),
+        event.id,
+        event.record_type,
+        event.event_type,
+        email,
+        payload,
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// A bounce never overrides a spam complaint, the stronger signal. The
+/// confirmation links sent are spent, so that they cannot undo it.
+#[instrument(skip(tx))]
+async fn update_subscriber_status(
+    tx: &mut Transaction<'_, Postgres>,
+    email: &str,
+    status: SubscriberStatus,
+) -> Result<(), sqlx::Error> {
+    let updated = sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET status = $1
+        WHERE lower(email) = lower($2) AND status <> $3
+        RETURNING id
+        "#,
+        status.to_string(),
+        email,
+        SubscriberStatus::Complained.to_string(),
+    )
+    .fetch_all(&mut **tx)
+    .await?;
+    for r in updated {
+        retire_tokens(tx, r.id).await?;
+    }
+
+    Ok(())
+}
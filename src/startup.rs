@@ -29,6 +29,7 @@ impl Application {
         let pool = PgPool::connect_lazy(&db_url)
             .expect("Failed to connect to the database");
 
+        let webhook_credentials = settings.email_client.webhook.clone();
         let email_client = settings.email_client.client();
 
         let session_store = Self::get_redis_store(
@@ -40,6 +41,9 @@ impl Application {
             pool,
             email_client,
             settings.app_settings.base_url,
+            settings.app_settings.subscription_token_ttl,
+            settings.app_settings.data_request_token_ttl,
+            webhook_credentials,
             session_store,
         );
         let server = axum::serve(listener, app.into_make_service());
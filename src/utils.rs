@@ -10,6 +10,10 @@ pub enum AppError {
     E400(#[source] anyhow::Error),
     #[error("authorization failed")]
     E401(#[source] anyhow::Error),
+    #[error("resource not found")]
+    E404(#[source] anyhow::Error),
+    #[error("conflict with the current state")]
+    E409(#[source] anyhow::Error),
 }
 
 impl AppError {
@@ -18,6 +22,8 @@ impl AppError {
             Self::E500(_) => StatusCode::INTERNAL_SERVER_ERROR,
             Self::E400(_) => StatusCode::BAD_REQUEST,
             Self::E401(_) => StatusCode::UNAUTHORIZED,
+            Self::E404(_) => StatusCode::NOT_FOUND,
+            Self::E409(_) => StatusCode::CONFLICT,
         }
     }
 }
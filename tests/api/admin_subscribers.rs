@@ -0,0 +1,343 @@
+use reqwest::{StatusCode, Url};
+use serde_json::{Value, json};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helper::{TestApp, assert_is_redirect_to, spawn_app};
+
+/// Signs up `email` without confirming it, returning its subscriber id and
+/// confirmation link.
+async fn sign_up(app: &TestApp, name: &str, email: &str) -> (Uuid, Url) {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscriptions_json(&json!({ "name": name, "email": email }))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    app.dispatch_all_outbox_emails().await;
+    let requests = app.email_server.received_requests().await.unwrap();
+    let link = app.extract_links(requests.last().unwrap()).html;
+
+    let subscriber_id =
+        sqlx::query!("SELECT id FROM subscriptions WHERE email = $1", email)
+            .fetch_one(&app.pool)
+            .await
+            .unwrap()
+            .id;
+    (subscriber_id, link)
+}
+
+async fn confirmed(app: &TestApp, name: &str, email: &str) -> Uuid {
+    let (subscriber_id, link) = sign_up(app, name, email).await;
+    reqwest::get(link).await.unwrap();
+    subscriber_id
+}
+
+async fn emails(app: &TestApp, query: &str) -> Vec<String> {
+    let response = app.get_subscribers(query).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: Value = response.json().await.unwrap();
+    body["subscribers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|s| s["email"].as_str().unwrap().to_owned())
+        .collect()
+}
+
+async fn status(app: &TestApp, subscriber_id: Uuid) -> Value {
+    let body: Value = app
+        .get_subscriber(subscriber_id)
+        .await
+        .json()
+        .await
+        .unwrap();
+    body["status"].clone()
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_manage_subscribers() {
+    let app = spawn_app().await;
+    let subscriber_id = Uuid::new_v4();
+
+    for response in [
+        app.get_subscribers("").await,
+        app.get_subscriber(subscriber_id).await,
+        app.post_confirm_subscriber(subscriber_id).await,
+        app.post_reactivate_subscriber(subscriber_id).await,
+        app.post_unsubscribe_subscriber(subscriber_id).await,
+        app.delete_subscriber(subscriber_id).await,
+    ] {
+        assert_is_redirect_to(&response, "/login");
+    }
+}
+
+#[tokio::test]
+async fn subscribers_are_listed_newest_first_with_pagination() {
+    let app = spawn_app().await;
+    app.login().await;
+    for i in 0..3 {
+        sign_up(&app, "reader", &format!("reader{i}@example.com")).await;
+    }
+
+    let response = app.get_subscribers("page=1&per_page=2").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["total"], 3);
+    assert_eq!(body["subscribers"].as_array().unwrap().len(), 2);
+    assert_eq!(body["subscribers"][0]["email"], "reader2@example.com");
+    assert_eq!(body["subscribers"][0]["status"], "pending_confirmation");
+
+    let body: Value = app
+        .get_subscribers("page=5&per_page=2")
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body["total"], 3);
+    assert!(body["subscribers"].as_array().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn subscribers_can_be_searched_by_email_or_name() {
+    let app = spawn_app().await;
+    app.login().await;
+    sign_up(&app, "Ursula Le Guin", "ursula@example.com").await;
+    sign_up(&app, "Octavia Butler", "octavia@example.com").await;
+    sign_up(&app, "N. K. Jemisin", "nk_jemisin@example.com").await;
+
+    assert_eq!(
+        emails(&app, "search=OCTAVIA").await,
+        ["octavia@example.com"]
+    );
+    assert_eq!(
+        emails(&app, "search=le%20guin").await,
+        ["ursula@example.com"]
+    );
+    // `_` and `%` are not wildcards
+    assert_eq!(emails(&app, "search=_").await, ["nk_jemisin@example.com"]);
+    assert!(emails(&app, "search=%25").await.is_empty());
+}
+
+#[tokio::test]
+async fn subscribers_can_be_filtered_by_status() {
+    let app = spawn_app().await;
+    app.login().await;
+    confirmed(&app, "reader", "confirmed@example.com").await;
+    sign_up(&app, "reader", "pending@example.com").await;
+
+    assert_eq!(
+        emails(&app, "status=confirmed").await,
+        ["confirmed@example.com"]
+    );
+    assert_eq!(
+        emails(&app, "status=pending_confirmation").await,
+        ["pending@example.com"]
+    );
+    let response = app.get_subscribers("status=deleted").await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn a_subscriber_can_be_viewed_with_their_lists() {
+    let app = spawn_app().await;
+    app.login().await;
+    let subscriber_id = confirmed(&app, "Ursula", "ursula@example.com").await;
+
+    let response = app.get_subscriber(subscriber_id).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["email"], "ursula@example.com");
+    assert_eq!(body["name"], "Ursula");
+    assert_eq!(body["status"], "confirmed");
+    assert_eq!(
+        body["lists"],
+        json!([{"slug": "newsletter", "status": "confirmed"}])
+    );
+    assert_eq!(body["queued_deliveries"], 0);
+}
+
+#[tokio::test]
+async fn unknown_subscribers_return_404() {
+    let app = spawn_app().await;
+    app.login().await;
+    let subscriber_id = Uuid::new_v4();
+
+    for response in [
+        app.get_subscriber(subscriber_id).await,
+        app.post_confirm_subscriber(subscriber_id).await,
+        app.post_unsubscribe_subscriber(subscriber_id).await,
+        app.delete_subscriber(subscriber_id).await,
+    ] {
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
+
+#[tokio::test]
+async fn admins_can_confirm_a_pending_subscriber() {
+    let app = spawn_app().await;
+    app.login().await;
+    let (subscriber_id, link) =
+        sign_up(&app, "reader", "reader@example.com").await;
+
+    let response = app.post_confirm_subscriber(subscriber_id).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(status(&app, subscriber_id).await, "confirmed");
+    let body: Value = app
+        .get_subscriber(subscriber_id)
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body["lists"][0]["status"], "confirmed");
+    // The emailed link has been used up
+    let response = reqwest::get(link).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn unsubscribed_subscribers_cannot_be_confirmed_by_admins() {
+    let app = spawn_app().await;
+    app.login().await;
+    let subscriber_id = confirmed(&app, "reader", "reader@example.com").await;
+    app.post_unsubscribe_subscriber(subscriber_id).await;
+
+    let response = app.post_confirm_subscriber(subscriber_id).await;
+
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    assert_eq!(status(&app, subscriber_id).await, "unsubscribed");
+}
+
+#[tokio::test]
+async fn admins_can_unsubscribe_a_subscriber() {
+    let app = spawn_app().await;
+    app.login().await;
+    let subscriber_id = confirmed(&app, "reader", "reader@example.com").await;
+
+    let response = app.post_unsubscribe_subscriber(subscriber_id).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: Value = app
+        .get_subscriber(subscriber_id)
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body["status"], "unsubscribed");
+    assert_eq!(body["lists"][0]["status"], "unsubscribed");
+}
+
+#[tokio::test]
+async fn unsubscribing_keeps_the_status_of_bounced_subscribers() {
+    let app = spawn_app().await;
+    app.login().await;
+    let subscriber_id = confirmed(&app, "reader", "reader@example.com").await;
+    sqlx::query!(
+        "UPDATE subscriptions SET status = 'bounced' WHERE id = $1",
+        subscriber_id
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+
+    let response = app.post_unsubscribe_subscriber(subscriber_id).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(status(&app, subscriber_id).await, "bounced");
+}
+
+#[tokio::test]
+async fn admins_can_reactivate_a_bounced_subscriber() {
+    let app = spawn_app().await;
+    app.login().await;
+    let subscriber_id = confirmed(&app, "reader", "reader@example.com").await;
+    sqlx::query!(
+        "UPDATE subscriptions SET status = 'bounced' WHERE id = $1",
+        subscriber_id
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+
+    let response = app.post_reactivate_subscriber(subscriber_id).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(status(&app, subscriber_id).await, "pending_confirmation");
+    // Signing up again goes through the confirmation email once more
+    let (_, link) = sign_up(&app, "reader", "reader@example.com").await;
+    reqwest::get(link).await.unwrap();
+    assert_eq!(status(&app, subscriber_id).await, "confirmed");
+}
+
+#[tokio::test]
+async fn only_bounced_or_complaining_subscribers_can_be_reactivated() {
+    let app = spawn_app().await;
+    app.login().await;
+    let subscriber_id = confirmed(&app, "reader", "reader@example.com").await;
+
+    let response = app.post_reactivate_subscriber(subscriber_id).await;
+
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    assert_eq!(status(&app, subscriber_id).await, "confirmed");
+}
+
+#[tokio::test]
+async fn deleting_a_subscriber_removes_their_tokens_and_queued_deliveries() {
+    let app = spawn_app().await;
+    app.login().await;
+    let subscriber_id = confirmed(&app, "reader", "reader@example.com").await;
+    confirmed(&app, "other", "other@example.com").await;
+    let response = app
+        .post_newsletters(&json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            },
+            "idempotency_key": Uuid::new_v4().to_string(),
+        }))
+        .await;
+    let body: Value = response.json().await.unwrap();
+    let issue_id: Uuid = body["newsletter_issue_id"]
+        .as_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+
+    let response = app.delete_subscriber(subscriber_id).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = app.get_subscriber(subscriber_id).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let tokens = sqlx::query!(
+        "SELECT COUNT(*) AS n FROM subscription_tokens WHERE subscription_id = $1",
+        subscriber_id
+    )
+    .fetch_one(&app.pool)
+    .await
+    .unwrap();
+    assert_eq!(tokens.n, Some(0));
+    let queued =
+        sqlx::query!("SELECT subscriber_email FROM issue_delivery_queue")
+            .fetch_all(&app.pool)
+            .await
+            .unwrap();
+    assert_eq!(queued.len(), 1);
+    assert_eq!(queued[0].subscriber_email, "other@example.com");
+    let issue: Value = app
+        .get_newsletter_issue(issue_id)
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(issue["delivery"]["recipients"], 2);
+    assert_eq!(issue["delivery"]["skipped"], 1);
+}
@@ -0,0 +1,322 @@
+use reqwest::{StatusCode, Url};
+use serde_json::{Value, json};
+use uuid::Uuid;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helper::{TestApp, create_confirmed_subscriber, spawn_app};
+
+async fn subscriber(app: &TestApp) -> (Uuid, String) {
+    create_confirmed_subscriber(app).await;
+    let r = sqlx::query!("SELECT id, email FROM subscriptions")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    (r.id, r.email)
+}
+
+/// Requests the data of `email`, returning the link of the email sent.
+async fn request_link(app: &TestApp, email: &str) -> Url {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    let response = app.post_data_request(&json!({ "email": email })).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    app.dispatch_all_outbox_emails().await;
+    let requests = app.email_server.received_requests().await.unwrap();
+    app.extract_links(requests.last().unwrap()).html
+}
+
+/// The same token, behind another endpoint.
+fn with_path(link: &Url, path: &str) -> Url {
+    let mut link = link.clone();
+    link.set_path(path);
+    link
+}
+
+async fn publish_issue(app: &TestApp) {
+    app.login().await;
+    let response = app
+        .post_newsletters(&json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            },
+            "idempotency_key": Uuid::new_v4().to_string(),
+        }))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn unknown_addresses_are_acknowledged_without_sending_anything() {
+    let app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_data_request(&json!({ "email": "nobody@example.com" }))
+        .await;
+    app.dispatch_all_outbox_emails().await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn invalid_addresses_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_data_request(&json!({ "email": "not-an-email" }))
+        .await;
+
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[tokio::test]
+async fn the_emailed_link_offers_download_and_erasure() {
+    let app = spawn_app().await;
+    let (_, email) = subscriber(&app).await;
+    let link = request_link(&app, &email).await;
+
+    let response = reqwest::get(link.clone()).await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let token = link.query().unwrap();
+    let page = response.text().await.unwrap();
+    assert!(page.contains(&format!("/subscriptions/data?{token}")));
+    assert!(page.contains(&format!("/subscriptions/erase?{token}")));
+}
+
+#[tokio::test]
+async fn addresses_are_matched_regardless_of_case() {
+    let app = spawn_app().await;
+    let (_, email) = subscriber(&app).await;
+    let link = request_link(&app, &email.to_uppercase()).await;
+
+    let response = reqwest::get(link).await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn subscribers_can_download_everything_tied_to_their_address() {
+    let app = spawn_app().await;
+    let (subscriber_id, email) = subscriber(&app).await;
+    publish_issue(&app).await;
+    let link = request_link(&app, &email).await;
+
+    let response = reqwest::get(with_path(&link, "/subscriptions/data"))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(
+        response.headers()["content-disposition"]
+            .to_str()
+            .unwrap()
+            .starts_with("attachment")
+    );
+    let data: Value = response.json().await.unwrap();
+    assert_eq!(
+        data["subscriber"]["subscriber_id"],
+        subscriber_id.to_string()
+    );
+    assert_eq!(data["subscriber"]["email"], email);
+    assert_eq!(data["subscriber"]["status"], "confirmed");
+    assert_eq!(data["lists"][0]["slug"], "newsletter");
+    let tokens = data["confirmation_tokens"].as_array().unwrap();
+    assert_eq!(tokens.len(), 1);
+    assert!(tokens[0]["used_at"].is_string());
+    assert!(tokens[0].get("subscription_token").is_none());
+    assert_eq!(data["queued_deliveries"][0]["title"], "Newsletter title");
+    assert!(data["failed_deliveries"].as_array().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn subscribers_can_erase_their_data() {
+    let app = spawn_app().await;
+    let (subscriber_id, email) = subscriber(&app).await;
+    publish_issue(&app).await;
+    sqlx::query!(
+        r#"
+        INSERT INTO email_events (event_id, record_type, email, payload)
+        VALUES ($1, 'Delivery', $2, '{}')
+        "#,
+        Uuid::new_v4(),
+        email.to_uppercase(),
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+    let link = request_link(&app, &email).await;
+    // Whatever the case the address was used with
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_failures
+            (newsletter_issue_id, subscriber_email, last_error, n_attempts,
+             enqueued_at)
+        SELECT newsletter_issue_id, $1, 'Inactive recipient', 1, NOW()
+        FROM newsletter_issues
+        "#,
+        email.to_uppercase(),
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        r#"
+        INSERT INTO email_outbox
+            (email_id, recipient, subject, text_body, html_body)
+        VALUES ($1, $2, 'Welcome', 'Hi', '<p>Hi</p>')
+        "#,
+        Uuid::new_v4(),
+        email.to_uppercase(),
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+
+    let response = reqwest::Client::new()
+        .post(with_path(&link, "/subscriptions/erase"))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let remaining = sqlx::query!(
+        r#"
+        SELECT
+            (SELECT COUNT(*) FROM subscriptions) AS "subscriptions!",
+            (SELECT COUNT(*) FROM issue_delivery_failures) AS "failures!",
+            (SELECT COUNT(*) FROM email_outbox) AS "outbox!",
+            (SELECT COUNT(*) FROM subscription_tokens) AS "tokens!",
+            (SELECT COUNT(*) FROM data_request_tokens) AS "data_tokens!",
+            (SELECT COUNT(*) FROM list_memberships) AS "memberships!",
+            (SELECT COUNT(*) FROM issue_delivery_queue) AS "queued!",
+            (SELECT COUNT(*) FROM email_events) AS "events!"
+        "#
+    )
+    .fetch_one(&app.pool)
+    .await
+    .unwrap();
+    assert_eq!(remaining.subscriptions, 0);
+    assert_eq!(remaining.failures, 0);
+    assert_eq!(remaining.outbox, 0);
+    assert_eq!(remaining.tokens, 0);
+    assert_eq!(remaining.data_tokens, 0);
+    assert_eq!(remaining.memberships, 0);
+    assert_eq!(remaining.queued, 0);
+    assert_eq!(remaining.events, 0);
+    let erasure =
+        sqlx::query!("SELECT subscription_id FROM subscriber_erasures")
+            .fetch_one(&app.pool)
+            .await
+            .unwrap();
+    assert_eq!(erasure.subscription_id, subscriber_id);
+
+    // The link is spent along with the rest
+    let response = reqwest::get(with_path(&link, "/subscriptions/data"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = reqwest::Client::new()
+        .post(with_path(&link, "/subscriptions/erase"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn unknown_tokens_are_rejected() {
+    let app = spawn_app().await;
+    let client = reqwest::Client::new();
+    let url = |path: &str| format!("{}{path}?token=unknown", app.address);
+
+    for response in [
+        client
+            .get(url("/subscriptions/data-requests"))
+            .send()
+            .await
+            .unwrap(),
+        client.get(url("/subscriptions/data")).send().await.unwrap(),
+        client
+            .post(url("/subscriptions/erase"))
+            .send()
+            .await
+            .unwrap(),
+    ] {
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+}
+
+#[tokio::test]
+async fn only_the_latest_link_is_valid() {
+    let app = spawn_app().await;
+    let (_, email) = subscriber(&app).await;
+    let first = request_link(&app, &email).await;
+    let second = request_link(&app, &email).await;
+
+    let response = reqwest::get(first).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = reqwest::get(second).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn expired_links_are_rejected() {
+    let app = spawn_app().await;
+    let (_, email) = subscriber(&app).await;
+    let link = request_link(&app, &email).await;
+    sqlx::query!(
+        r#"
+        UPDATE data_request_tokens
+        SET created_at = created_at - INTERVAL '1000 days'
+        "#
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+
+    let response = reqwest::Client::new()
+        .post(with_path(&link, "/subscriptions/erase"))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let subscribers = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_all(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(subscribers.len(), 1);
+}
+
+#[tokio::test]
+async fn links_expire_sooner_than_confirmation_links() {
+    let app = spawn_app().await;
+    let (_, email) = subscriber(&app).await;
+    let link = request_link(&app, &email).await;
+    sqlx::query!(
+        r#"
+        UPDATE data_request_tokens
+        SET created_at = created_at - INTERVAL '2 hours'
+        "#
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+
+    let response = reqwest::get(link).await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
@@ -0,0 +1,260 @@
+use reqwest::StatusCode;
+use serde_json::{Value, json};
+use uuid::Uuid;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, Request, Respond, ResponseTemplate};
+
+use crate::helper::{
+    PostmarkBatchResponder, TestApp, assert_is_redirect_to,
+    create_confirmed_subscriber, spawn_app,
+};
+
+async fn publish_issue(app: &TestApp) -> Uuid {
+    let response = app
+        .post_newsletters(&json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            },
+            "idempotency_key": Uuid::new_v4().to_string()
+        }))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id
+}
+
+async fn get_issue(app: &TestApp, issue_id: Uuid) -> Value {
+    let response = app.get_newsletter_issue(issue_id).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    response.json().await.unwrap()
+}
+
+#[tokio::test]
+async fn paused_issues_are_delivered_once_resumed() {
+    let app = spawn_app().await;
+    app.login().await;
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = publish_issue(&app).await;
+
+    let response = app.post_pause(issue_id).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    {
+        let _guard = Mock::given(any())
+            .respond_with(PostmarkBatchResponder::default())
+            .expect(0)
+            .mount_as_scoped(&app.email_server)
+            .await;
+        app.dispatch_all_pending_emails().await;
+    }
+    let issue = get_issue(&app, issue_id).await;
+    assert_eq!(issue["status"], "paused");
+    assert_eq!(issue["delivery"]["queued"], 2);
+
+    let response = app.post_resume(issue_id).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    let issue = get_issue(&app, issue_id).await;
+    assert_eq!(issue["status"], "published");
+    assert_eq!(issue["delivery"]["sent"], 2);
+    assert_eq!(issue["delivery"]["complete"], true);
+}
+
+#[tokio::test]
+async fn cancelling_an_issue_in_flight_drops_its_remaining_deliveries() {
+    let app = spawn_app().await;
+    app.login().await;
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = publish_issue(&app).await;
+    Mock::given(any())
+        .respond_with(PostmarkBatchResponder::default())
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_cancel(issue_id).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    app.dispatch_all_pending_emails().await;
+
+    let issue = get_issue(&app, issue_id).await;
+    assert_eq!(issue["status"], "cancelled");
+    assert_eq!(issue["delivery"]["recipients"], 2);
+    assert_eq!(issue["delivery"]["queued"], 0);
+    assert_eq!(issue["delivery"]["cancelled"], 2);
+    assert_eq!(issue["delivery"]["complete"], true);
+    let response = app.get_web_version(issue_id).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+/// Answers like Postmark, but only after `0`.
+struct DelayedResponder(std::time::Duration);
+
+impl Respond for DelayedResponder {
+    fn respond(&self, request: &Request) -> ResponseTemplate {
+        PostmarkBatchResponder::default()
+            .respond(request)
+            .set_delay(self.0)
+    }
+}
+
+#[tokio::test]
+async fn cancelling_while_the_worker_holds_a_batch_waits_for_it() {
+    let app = spawn_app().await;
+    app.login().await;
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = publish_issue(&app).await;
+    // Only one of the two deliveries is due, and makes up the batch
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET execute_after = NOW() + INTERVAL '1 hour'
+        WHERE subscriber_email = (
+            SELECT MIN(subscriber_email) FROM issue_delivery_queue
+        )
+        "#
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(DelayedResponder(std::time::Duration::from_secs(1)))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let cancel = async {
+        tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+        app.post_cancel(issue_id).await
+    };
+    let ((), response) =
+        tokio::join!(app.dispatch_all_pending_emails(), cancel);
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let issue = get_issue(&app, issue_id).await;
+    assert_eq!(issue["status"], "cancelled");
+    assert_eq!(issue["delivery"]["sent"], 1);
+    assert_eq!(issue["delivery"]["cancelled"], 1);
+    assert_eq!(issue["delivery"]["queued"], 0);
+}
+
+#[tokio::test]
+async fn paused_issues_can_be_cancelled() {
+    let app = spawn_app().await;
+    app.login().await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = publish_issue(&app).await;
+    app.post_pause(issue_id).await;
+
+    let response = app.post_cancel(issue_id).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let issue = get_issue(&app, issue_id).await;
+    assert_eq!(issue["status"], "cancelled");
+    assert_eq!(issue["delivery"]["cancelled"], 1);
+
+    let response = app.post_resume(issue_id).await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn only_paused_issues_can_be_resumed() {
+    let app = spawn_app().await;
+    app.login().await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = publish_issue(&app).await;
+
+    let response = app.post_resume(issue_id).await;
+
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    assert_eq!(get_issue(&app, issue_id).await["status"], "published");
+}
+
+#[tokio::test]
+async fn delivered_issues_cannot_be_paused_or_cancelled() {
+    let app = spawn_app().await;
+    app.login().await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = publish_issue(&app).await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder::default())
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    let response = app.post_pause(issue_id).await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let response = app.post_cancel(issue_id).await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    assert_eq!(get_issue(&app, issue_id).await["status"], "published");
+}
+
+#[tokio::test]
+async fn failures_of_a_cancelled_issue_are_not_requeued() {
+    let app = spawn_app().await;
+    app.login().await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = publish_issue(&app).await;
+    app.post_cancel(issue_id).await;
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_failures (
+            newsletter_issue_id, subscriber_email, last_error, n_attempts,
+            enqueued_at
+        )
+        VALUES ($1, 'failed@example.com', 'Rejected', 1, NOW())
+        "#,
+        issue_id
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+
+    let response = app
+        .post_retry_delivery_failures(
+            issue_id,
+            &json!({ "subscriber_emails": ["failed@example.com"] }),
+        )
+        .await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["requeued"], 0);
+    assert_eq!(get_issue(&app, issue_id).await["delivery"]["queued"], 0);
+}
+
+#[tokio::test]
+async fn unknown_issues_cannot_be_paused() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    let response = app.post_pause(Uuid::new_v4()).await;
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn anonymous_users_cannot_pause_issues() {
+    let app = spawn_app().await;
+
+    let response = app.post_pause(Uuid::new_v4()).await;
+
+    assert_is_redirect_to(&response, "/login");
+}
@@ -0,0 +1,215 @@
+use reqwest::StatusCode;
+use serde_json::{Value, json};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helper::{
+    PostmarkBatchResponder, TestApp, assert_is_redirect_to,
+    create_confirmed_subscriber, spawn_app,
+};
+
+/// Publishes an issue to a single confirmed subscriber and lets every
+/// delivery attempt fail until the retry budget is exhausted.
+async fn publish_undeliverable_issue(app: &TestApp) -> Uuid {
+    create_confirmed_subscriber(app).await;
+
+    let _mock_guard = Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(2)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_newsletters(&json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        },
+        "idempotency_key": Uuid::new_v4().to_string()
+    }))
+    .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Skip the backoff wait of the first retry
+    sqlx::query!("UPDATE issue_delivery_queue SET execute_after = NOW()")
+        .execute(&app.pool)
+        .await
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id
+}
+
+#[tokio::test]
+async fn deliveries_that_exhaust_their_retries_are_kept_as_failures() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    let issue_id = publish_undeliverable_issue(&app).await;
+
+    let queued =
+        sqlx::query!("SELECT subscriber_email FROM issue_delivery_queue")
+            .fetch_all(&app.pool)
+            .await
+            .unwrap();
+    assert!(queued.is_empty());
+
+    let response = app.get_delivery_failures(issue_id).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let failures: Vec<Value> = response.json().await.unwrap();
+    assert_eq!(failures.len(), 1);
+    assert_eq!(failures[0]["n_attempts"], 2);
+    assert!(
+        failures[0]["last_error"]
+            .as_str()
+            .unwrap()
+            .contains("500 Internal Server Error")
+    );
+}
+
+#[tokio::test]
+async fn permanently_rejected_deliveries_are_not_retried() {
+    let app = spawn_app().await;
+    app.login().await;
+    create_confirmed_subscriber(&app).await;
+    let subscriber_email = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap()
+        .email;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder {
+            // Inactive recipient
+            rejections: vec![(subscriber_email, 406)],
+        })
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletters(&json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        },
+        "idempotency_key": Uuid::new_v4().to_string()
+    }))
+    .await;
+    app.dispatch_all_pending_emails().await;
+
+    let issue_id =
+        sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+            .fetch_one(&app.pool)
+            .await
+            .unwrap()
+            .newsletter_issue_id;
+    let failures: Vec<Value> = app
+        .get_delivery_failures(issue_id)
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(failures.len(), 1);
+    assert_eq!(failures[0]["n_attempts"], 1);
+    assert!(
+        failures[0]["last_error"]
+            .as_str()
+            .unwrap()
+            .contains("rejected permanently")
+    );
+}
+
+#[tokio::test]
+async fn failed_deliveries_can_be_re_enqueued() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    let issue_id = publish_undeliverable_issue(&app).await;
+    let failures: Vec<Value> = app
+        .get_delivery_failures(issue_id)
+        .await
+        .json()
+        .await
+        .unwrap();
+    let subscriber_email = failures[0]["subscriber_email"].clone();
+
+    let response = app
+        .post_retry_delivery_failures(
+            issue_id,
+            &json!({ "subscriber_emails": [subscriber_email] }),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["requeued"], 1);
+
+    let failures: Vec<Value> = app
+        .get_delivery_failures(issue_id)
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert!(failures.is_empty());
+    let issue: Value = app
+        .get_newsletter_issue(issue_id)
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(issue["delivery"]["failed"], 0);
+    assert_eq!(issue["delivery"]["queued"], 1);
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn retrying_nothing_is_rejected_with_400() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    let response = app
+        .post_retry_delivery_failures(
+            Uuid::new_v4(),
+            &json!({ "subscriber_emails": [] }),
+        )
+        .await;
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn retrying_failures_of_an_unknown_issue_returns_404() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    let response = app
+        .post_retry_delivery_failures(
+            Uuid::new_v4(),
+            &json!({ "subscriber_emails": ["reader@example.com"] }),
+        )
+        .await;
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn anonymous_users_cannot_see_delivery_failures() {
+    let app = spawn_app().await;
+
+    let response = app.get_delivery_failures(Uuid::new_v4()).await;
+
+    assert_is_redirect_to(&response, "/login");
+}
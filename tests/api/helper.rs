@@ -2,10 +2,11 @@ use argon2::Argon2;
 use argon2::password_hash::PasswordHasher;
 use argon2::password_hash::SaltString;
 use argon2::password_hash::rand_core::OsRng;
+use craft::background_workers::ExecutionOutput;
+use craft::background_workers::email_outbox_worker::try_send_outbox_email;
 use craft::background_workers::idempotency_expire_wroker::try_clean_expired_idempotency;
-use craft::background_workers::issue_delivery_worker::{
-    ExecutionOutput, try_execute_task,
-};
+use craft::background_workers::issue_delivery_worker::try_execute_task;
+use craft::background_workers::subscription_token_expire_worker::try_clean_expired_subscription_tokens;
 use craft::configuration::Settings;
 use craft::email_client::EmailClient;
 use craft::startup::Application;
@@ -22,10 +23,42 @@ use sqlx::{Connection, Executor, PgConnection, PgPool};
 use std::collections::HashMap;
 use std::time::Duration;
 
-use craft::configuration::DBSettings;
+use craft::configuration::{DBSettings, EmailBackend, WebhookSettings};
 use reqwest::Url;
 use uuid::Uuid;
-use wiremock::{MockServer, Request};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, Request, Respond, ResponseTemplate};
+
+pub const WEBHOOK_USERNAME: &str = "postmark";
+pub const WEBHOOK_PASSWORD: &str = "webhook-secret";
+
+/// Answers Postmark's `/email/batch` with one entry per message, rejecting
+/// the messages addressed to one of `rejections` with its error code.
+#[derive(Default)]
+pub struct PostmarkBatchResponder {
+    pub rejections: Vec<(String, i64)>,
+}
+
+impl Respond for PostmarkBatchResponder {
+    fn respond(&self, request: &Request) -> ResponseTemplate {
+        let messages: Vec<Value> =
+            serde_json::from_slice(&request.body).unwrap();
+        let entries: Vec<Value> = messages
+            .iter()
+            .map(|m| {
+                let to = m["To"].as_str().unwrap();
+                match self.rejections.iter().find(|(r, _)| r == to) {
+                    Some((_, code)) => {
+                        json!({ "ErrorCode": code, "Message": "Rejected" })
+                    }
+                    None => json!({ "ErrorCode": 0, "Message": "OK" }),
+                }
+            })
+            .collect();
+
+        ResponseTemplate::new(200).set_body_json(entries)
+    }
+}
 
 pub struct TestUser {
     pub user_id: Uuid,
@@ -92,6 +125,173 @@ impl TestApp {
             .expect("Failed to execute request.")
     }
 
+    pub async fn post_subscriptions_json(&self, body: &Value) -> Response {
+        self.api_client
+            .post(format!("{}/subscriptions", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_lists(&self) -> Response {
+        self.api_client
+            .get(format!("{}/admin/lists", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_list(&self, body: &Value) -> Response {
+        self.api_client
+            .post(format!("{}/admin/lists", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_subscribers(&self, query: &str) -> Response {
+        self.api_client
+            .get(format!("{}/admin/subscribers?{query}", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_subscriber_import(
+        &self,
+        query: &str,
+        csv: &str,
+    ) -> Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/subscribers/import?{query}",
+                &self.address
+            ))
+            .header("Content-Type", "text/csv")
+            .body(csv.to_owned())
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_subscriber_export(&self, query: &str) -> Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/subscribers/export?{query}",
+                &self.address
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_subscriber(&self, subscriber_id: Uuid) -> Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/subscribers/{subscriber_id}",
+                &self.address
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_confirm_subscriber(
+        &self,
+        subscriber_id: Uuid,
+    ) -> Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/subscribers/{subscriber_id}/confirm",
+                &self.address
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_reactivate_subscriber(
+        &self,
+        subscriber_id: Uuid,
+    ) -> Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/subscribers/{subscriber_id}/reactivate",
+                &self.address
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_unsubscribe_subscriber(
+        &self,
+        subscriber_id: Uuid,
+    ) -> Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/subscribers/{subscriber_id}/unsubscribe",
+                &self.address
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn delete_subscriber(&self, subscriber_id: Uuid) -> Response {
+        self.api_client
+            .delete(format!(
+                "{}/admin/subscribers/{subscriber_id}",
+                &self.address
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn put_subscriber_tags(
+        &self,
+        subscriber_id: Uuid,
+        body: &Value,
+    ) -> Response {
+        self.api_client
+            .put(format!(
+                "{}/admin/subscribers/{subscriber_id}/tags",
+                &self.address
+            ))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn patch_subscriber_attributes(
+        &self,
+        subscriber_id: Uuid,
+        body: &Value,
+    ) -> Response {
+        self.api_client
+            .patch(format!(
+                "{}/admin/subscribers/{subscriber_id}/attributes",
+                &self.address
+            ))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_data_request(&self, body: &Value) -> Response {
+        self.api_client
+            .post(format!("{}/subscriptions/data-requests", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
     pub async fn post_newsletters(&self, body: &Value) -> reqwest::Response {
         self.api_client
             .post(format!("{}/admin/newsletters", &self.address))
@@ -101,6 +301,200 @@ impl TestApp {
             .expect("Failed to execute request.")
     }
 
+    pub async fn get_unsubscribe(&self, token: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/subscriptions/unsubscribe", &self.address))
+            .query(&[("token", token)])
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_unsubscribe(
+        &self,
+        token: Option<&str>,
+    ) -> reqwest::Response {
+        let mut url =
+            Url::parse(&format!("{}/subscriptions/unsubscribe", &self.address))
+                .unwrap();
+        if let Some(token) = token {
+            url.query_pairs_mut().append_pair("token", token);
+        }
+
+        self.api_client
+            .post(url)
+            .form(&[("List-Unsubscribe", "One-Click")])
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_newsletter_issues(&self, query: &str) -> Response {
+        self.api_client
+            .get(format!("{}/admin/newsletters?{query}", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_web_version(&self, issue_id: Uuid) -> Response {
+        self.api_client
+            .get(format!("{}/newsletters/{issue_id}", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_newsletter_issue(&self, issue_id: Uuid) -> Response {
+        self.api_client
+            .get(format!("{}/admin/newsletters/{issue_id}", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_reschedule(
+        &self,
+        issue_id: Uuid,
+        body: &Value,
+    ) -> Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/newsletters/{issue_id}/reschedule",
+                &self.address
+            ))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_pause(&self, issue_id: Uuid) -> Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/newsletters/{issue_id}/pause",
+                &self.address
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_resume(&self, issue_id: Uuid) -> Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/newsletters/{issue_id}/resume",
+                &self.address
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_cancel(&self, issue_id: Uuid) -> Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/newsletters/{issue_id}/cancel",
+                &self.address
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_draft(&self, body: &Value) -> Response {
+        self.api_client
+            .post(format!("{}/admin/newsletters/drafts", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn put_draft(&self, issue_id: Uuid, body: &Value) -> Response {
+        self.api_client
+            .put(format!("{}/admin/newsletters/{issue_id}", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_test_send(
+        &self,
+        issue_id: Uuid,
+        body: &Value,
+    ) -> Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/newsletters/{issue_id}/test",
+                &self.address
+            ))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_publish_draft(
+        &self,
+        issue_id: Uuid,
+        body: &Value,
+    ) -> Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/newsletters/{issue_id}/publish",
+                &self.address
+            ))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_delivery_failures(&self, issue_id: Uuid) -> Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/newsletters/{issue_id}/failures",
+                &self.address
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_retry_delivery_failures(
+        &self,
+        issue_id: Uuid,
+        body: &Value,
+    ) -> Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/newsletters/{issue_id}/failures/retry",
+                &self.address
+            ))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_postmark_webhook(
+        &self,
+        body: &Value,
+        credentials: Option<(&str, &str)>,
+    ) -> Response {
+        let mut request = self
+            .api_client
+            .post(format!("{}/webhooks/postmark", &self.address))
+            .json(body);
+        if let Some((username, password)) = credentials {
+            request = request.basic_auth(username, Some(password));
+        }
+
+        request.send().await.expect("Failed to execute request.")
+    }
+
     pub async fn post_login(&self, body: &Value) -> reqwest::Response {
         self.api_client
             .post(format!("{}/login", &self.address))
@@ -191,7 +585,19 @@ impl TestApp {
     pub async fn dispatch_all_pending_emails(&self) {
         loop {
             if let ExecutionOutput::NoAvaliableTask =
-                try_execute_task(&self.pool, &self.email_client)
+                try_execute_task(&self.pool, &self.email_client, &self.address)
+                    .await
+                    .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
+    pub async fn dispatch_all_outbox_emails(&self) {
+        loop {
+            if let ExecutionOutput::NoAvaliableTask =
+                try_send_outbox_email(&self.pool, &self.email_client)
                     .await
                     .unwrap()
             {
@@ -205,6 +611,15 @@ impl TestApp {
             .await
             .unwrap();
     }
+
+    pub async fn clean_all_subscription_tokens(&self) {
+        try_clean_expired_subscription_tokens(
+            &self.pool,
+            &Duration::from_secs(0),
+        )
+        .await
+        .unwrap();
+    }
 }
 
 static INIT_SUBSCRIBER: Lazy<()> = Lazy::new(|| {
@@ -277,9 +692,15 @@ fn get_test_config(email_server_uri: String) -> Settings {
         "test_{}",
         uuid::Uuid::new_v4().to_string().replace('-', "_")
     );
+    c.email_client.backend = EmailBackend::Postmark;
     c.email_client.base_url = email_server_uri;
-    c.email_client.retries_limit = 2;
-    c.email_client.retry_wait_seconds = 1;
+    c.email_client.retry.max_attempts = 2;
+    c.email_client.retry.base = Duration::from_secs(1);
+    c.email_client.retry.cap = Duration::from_secs(1);
+    c.email_client.webhook = Some(WebhookSettings {
+        username: WEBHOOK_USERNAME.into(),
+        password: WEBHOOK_PASSWORD.into(),
+    });
 
     c
 }
@@ -344,3 +765,34 @@ pub fn assert_is_redirect_to(response: &reqwest::Response, url: &str) {
         url, location
     );
 }
+
+pub async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
+    let body = valid_subscriber();
+
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .named("Create unconfirmed subscriber")
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_subscriptions(&body).await;
+    app.dispatch_all_outbox_emails().await;
+
+    let request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+
+    app.extract_links(request)
+}
+
+pub async fn create_confirmed_subscriber(app: &TestApp) {
+    let confirm_link = create_unconfirmed_subscriber(app).await.html;
+
+    reqwest::get(confirm_link).await.unwrap();
+}
@@ -0,0 +1,233 @@
+use reqwest::{StatusCode, Url};
+use serde_json::{Value, json};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helper::{
+    ConfirmationLinks, TestApp, assert_is_redirect_to,
+    create_confirmed_subscriber, spawn_app,
+};
+
+async fn create_list(app: &TestApp, slug: &str) {
+    let response = app
+        .post_list(&json!({ "slug": slug, "name": "Weekly digest" }))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+async fn subscribe(app: &TestApp, email: &str, lists: &[&str]) -> Url {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscriptions_json(&json!({
+            "name": "le guin",
+            "email": email,
+            "lists": lists,
+        }))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    app.dispatch_all_outbox_emails().await;
+
+    let request = app.email_server.received_requests().await.unwrap();
+    let ConfirmationLinks { html, .. } =
+        app.extract_links(request.last().unwrap());
+    html
+}
+
+async fn publish_to(app: &TestApp, lists: &[&str]) -> Uuid {
+    let response = app
+        .post_newsletters(&json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            },
+            "idempotency_key": Uuid::new_v4().to_string(),
+            "lists": lists,
+        }))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: Value = response.json().await.unwrap();
+    body["newsletter_issue_id"]
+        .as_str()
+        .unwrap()
+        .parse()
+        .unwrap()
+}
+
+async fn recipients(app: &TestApp, issue_id: Uuid) -> Vec<String> {
+    sqlx::query!(
+        r#"
+        SELECT subscriber_email
+        FROM issue_delivery_queue
+        WHERE newsletter_issue_id = $1
+        ORDER BY subscriber_email
+        "#,
+        issue_id
+    )
+    .fetch_all(&app.pool)
+    .await
+    .unwrap()
+    .into_iter()
+    .map(|r| r.subscriber_email)
+    .collect()
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_manage_lists() {
+    let app = spawn_app().await;
+
+    let response = app.get_lists().await;
+    assert_is_redirect_to(&response, "/login");
+    let response = app
+        .post_list(&json!({ "slug": "weekly", "name": "Weekly" }))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn created_lists_are_listed_after_the_default_one() {
+    let app = spawn_app().await;
+    app.login().await;
+    create_confirmed_subscriber(&app).await;
+
+    create_list(&app, "weekly").await;
+
+    let body: Value = app.get_lists().await.json().await.unwrap();
+    let lists = body["lists"].as_array().unwrap();
+    assert_eq!(lists.len(), 2);
+    assert_eq!(lists[0]["slug"], "newsletter");
+    assert_eq!(lists[0]["confirmed"], 1);
+    assert_eq!(lists[1]["slug"], "weekly");
+    assert_eq!(lists[1]["name"], "Weekly digest");
+    assert_eq!(lists[1]["confirmed"], 0);
+}
+
+#[tokio::test]
+async fn list_slugs_are_unique() {
+    let app = spawn_app().await;
+    app.login().await;
+    create_list(&app, "weekly").await;
+
+    let response = app
+        .post_list(&json!({ "slug": "weekly", "name": "Another" }))
+        .await;
+
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn malformed_list_slugs_are_rejected() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    for slug in ["Weekly", "weekly digest", "", "-weekly"] {
+        let response = app
+            .post_list(&json!({ "slug": slug, "name": "Weekly" }))
+            .await;
+        assert!(
+            response.status().is_client_error(),
+            "The API accepted the slug {slug:?}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn subscribing_to_an_unknown_list_is_rejected() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_subscriptions_json(&json!({
+            "name": "le guin",
+            "email": "ursula_le_guin@gmail.com",
+            "lists": ["weekly"],
+        }))
+        .await;
+
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[tokio::test]
+async fn issues_only_go_to_confirmed_members_of_their_lists() {
+    let app = spawn_app().await;
+    app.login().await;
+    create_list(&app, "weekly").await;
+    let weekly = subscribe(&app, "weekly@example.com", &["weekly"]).await;
+    reqwest::get(weekly).await.unwrap();
+    subscribe(&app, "newsletter@example.com", &[]).await;
+    let both =
+        subscribe(&app, "both@example.com", &["weekly", "newsletter"]).await;
+    reqwest::get(both).await.unwrap();
+
+    let issue_id = publish_to(&app, &["weekly"]).await;
+    assert_eq!(
+        recipients(&app, issue_id).await,
+        ["both@example.com", "weekly@example.com"]
+    );
+
+    let issue_id = publish_to(&app, &[]).await;
+    assert_eq!(recipients(&app, issue_id).await, ["both@example.com"]);
+}
+
+#[tokio::test]
+async fn members_of_several_targeted_lists_are_sent_the_issue_once() {
+    let app = spawn_app().await;
+    app.login().await;
+    create_list(&app, "weekly").await;
+    let link =
+        subscribe(&app, "both@example.com", &["weekly", "newsletter"]).await;
+    reqwest::get(link).await.unwrap();
+
+    let issue_id = publish_to(&app, &["weekly", "newsletter"]).await;
+
+    assert_eq!(recipients(&app, issue_id).await, ["both@example.com"]);
+}
+
+#[tokio::test]
+async fn joining_another_list_has_to_be_confirmed_too() {
+    let app = spawn_app().await;
+    app.login().await;
+    create_list(&app, "weekly").await;
+    let link = subscribe(&app, "reader@example.com", &[]).await;
+    reqwest::get(link).await.unwrap();
+
+    let link = subscribe(&app, "reader@example.com", &["weekly"]).await;
+    let issue_id = publish_to(&app, &["weekly"]).await;
+    assert!(recipients(&app, issue_id).await.is_empty());
+
+    reqwest::get(link).await.unwrap();
+    let issue_id = publish_to(&app, &["weekly"]).await;
+    assert_eq!(recipients(&app, issue_id).await, ["reader@example.com"]);
+}
+
+#[tokio::test]
+async fn publishing_to_an_unknown_list_is_rejected() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    let response = app
+        .post_newsletters(&json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            },
+            "idempotency_key": Uuid::new_v4().to_string(),
+            "lists": ["weekly"],
+        }))
+        .await;
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let issues =
+        sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+            .fetch_all(&app.pool)
+            .await
+            .unwrap();
+    assert!(issues.is_empty());
+}
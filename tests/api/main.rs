@@ -1,8 +1,25 @@
 mod admin_dashboard;
+mod admin_subscribers;
 mod change_password;
+mod data_requests;
+mod delivery_control;
+mod delivery_failures;
 mod health_check;
 mod helper;
+mod lists;
 mod login;
 mod newsletter;
+mod newsletter_archive;
+mod newsletter_drafts;
+mod newsletter_html;
+mod newsletter_issue;
+mod newsletter_markdown;
+mod newsletter_schedule;
+mod newsletter_templates;
+mod segments;
+mod subscriber_export;
+mod subscriber_import;
 mod subscriptions;
 mod subscriptions_confim;
+mod unsubscribe;
+mod webhooks;
@@ -1,5 +1,6 @@
 use std::time::Duration;
 
+use craft::domain::subscriber::SubscriberStatus;
 use reqwest::StatusCode;
 use serde_json::json;
 use wiremock::{
@@ -8,8 +9,8 @@ use wiremock::{
 };
 
 use crate::helper::{
-    ConfirmationLinks, TestApp, assert_is_redirect_to, spawn_app,
-    valid_subscriber,
+    PostmarkBatchResponder, assert_is_redirect_to, create_confirmed_subscriber,
+    create_unconfirmed_subscriber, spawn_app,
 };
 
 #[tokio::test]
@@ -50,7 +51,7 @@ async fn donot_send_to_unconfirmed_subscribers() {
     });
 
     Mock::given(any())
-        .respond_with(ResponseTemplate::new(200))
+        .respond_with(PostmarkBatchResponder::default())
         .expect(0)
         .mount(&app.email_server)
         .await;
@@ -60,6 +61,46 @@ async fn donot_send_to_unconfirmed_subscribers() {
     assert_eq!(response.status(), StatusCode::OK)
 }
 
+#[tokio::test]
+async fn pending_subscribers_receive_nothing() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    create_unconfirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+
+    let body = json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        },
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    let response = app.post_newsletters(&body).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let queued = sqlx::query!(
+        r#"
+        SELECT s.status
+        FROM issue_delivery_queue q
+        JOIN subscriptions s ON s.email = q.subscriber_email
+        "#
+    )
+    .fetch_all(&app.pool)
+    .await
+    .unwrap();
+    assert_eq!(queued.len(), 1);
+    assert_eq!(queued[0].status, SubscriberStatus::Confirmed.to_string());
+
+    when_sending_an_email()
+        .respond_with(PostmarkBatchResponder::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+}
+
 #[tokio::test]
 async fn send_to_confirmed_subscribers() {
     let app = spawn_app().await;
@@ -81,7 +122,7 @@ async fn send_to_confirmed_subscribers() {
     });
 
     Mock::given(any())
-        .respond_with(ResponseTemplate::new(200))
+        .respond_with(PostmarkBatchResponder::default())
         .expect(1)
         .mount(&app.email_server)
         .await;
@@ -127,30 +168,6 @@ async fn return_400_for_invalid_body() {
     }
 }
 
-async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
-    let body = valid_subscriber();
-
-    let _mock_guard = Mock::given(path("/email"))
-        .and(method("POST"))
-        .respond_with(ResponseTemplate::new(200))
-        .named("Create unconfirmed subscriber")
-        .expect(1)
-        .mount_as_scoped(&app.email_server)
-        .await;
-
-    app.post_subscriptions(&body).await;
-
-    let request = &app
-        .email_server
-        .received_requests()
-        .await
-        .unwrap()
-        .pop()
-        .unwrap();
-
-    app.extract_links(request)
-}
-
 #[tokio::test]
 async fn newsletter_creation_is_idempotent() {
     let app = spawn_app().await;
@@ -167,7 +184,7 @@ async fn newsletter_creation_is_idempotent() {
     });
 
     Mock::given(any())
-        .respond_with(ResponseTemplate::new(200))
+        .respond_with(PostmarkBatchResponder::default())
         .up_to_n_times(1)
         .expect(1)
         .mount(&app.email_server)
@@ -177,7 +194,7 @@ async fn newsletter_creation_is_idempotent() {
     assert_eq!(response.status(), StatusCode::OK);
 
     Mock::given(any())
-        .respond_with(ResponseTemplate::new(200))
+        .respond_with(PostmarkBatchResponder::default())
         .expect(0)
         .mount(&app.email_server)
         .await;
@@ -202,7 +219,7 @@ async fn expired_idempotency_is_cleaned() {
     });
 
     Mock::given(any())
-        .respond_with(ResponseTemplate::new(200))
+        .respond_with(PostmarkBatchResponder::default())
         .up_to_n_times(1)
         .expect(1)
         .mount(&app.email_server)
@@ -214,7 +231,7 @@ async fn expired_idempotency_is_cleaned() {
     app.clean_all_idempotency().await;
 
     Mock::given(any())
-        .respond_with(ResponseTemplate::new(200))
+        .respond_with(PostmarkBatchResponder::default())
         .expect(1)
         .mount(&app.email_server)
         .await;
@@ -238,10 +255,11 @@ async fn concurrent_push_newsletter_is_handled_gracefully() {
         "idempotency_key": uuid::Uuid::new_v4().to_string()
     });
 
-    Mock::given(path("/email"))
-        .and(method("POST"))
+    when_sending_an_email()
         .respond_with(
-            ResponseTemplate::new(200).set_delay(Duration::from_secs(2)),
+            ResponseTemplate::new(200)
+                .set_body_json(json!([{ "ErrorCode": 0, "Message": "OK" }]))
+                .set_delay(Duration::from_secs(2)),
         )
         .expect(1)
         .mount(&app.email_server)
@@ -282,44 +300,183 @@ async fn retry_to_delivery_email_for_transient_errors() {
     assert_eq!(rep.status(), StatusCode::OK);
     app.dispatch_all_pending_emails().await;
 
+    // The retry is jittered anywhere between now and the 1s cap
+    let scheduled = sqlx::query!(
+        r#"
+        SELECT
+            n_retries,
+            execute_after <= NOW() + INTERVAL '1 second' AS "within_cap!"
+        FROM issue_delivery_queue
+        "#
+    )
+    .fetch_one(&app.pool)
+    .await
+    .unwrap();
+    assert_eq!(scheduled.n_retries, 1);
+    assert!(scheduled.within_cap);
+
     {
+        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
         let _mock_guard = when_sending_an_email()
-            .respond_with(ResponseTemplate::new(200))
-            .named("Donot retry until scheduled time")
-            .expect(0)
+            .respond_with(PostmarkBatchResponder::default())
+            .expect(1)
             .mount_as_scoped(&app.email_server)
             .await;
         app.dispatch_all_pending_emails().await;
     }
 
+    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+    when_sending_an_email()
+        .respond_with(PostmarkBatchResponder::default())
+        .named("No task left")
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn newsletters_carry_a_one_click_unsubscribe_link() {
+    let app = spawn_app().await;
+    app.login().await;
+    create_confirmed_subscriber(&app).await;
+
+    let body = json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        },
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+
     {
-        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
         let _mock_guard = when_sending_an_email()
-            .respond_with(ResponseTemplate::new(200))
+            .respond_with(PostmarkBatchResponder::default())
             .expect(1)
             .mount_as_scoped(&app.email_server)
             .await;
+        app.post_newsletters(&body).await;
         app.dispatch_all_pending_emails().await;
     }
 
-    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+    let request = app.email_server.received_requests().await.unwrap();
+    let batch: serde_json::Value =
+        serde_json::from_slice(&request.last().unwrap().body).unwrap();
+    let headers = batch[0]["Headers"].as_array().unwrap();
+    assert_eq!(headers[0]["Name"], "List-Unsubscribe");
+    assert_eq!(headers[1]["Name"], "List-Unsubscribe-Post");
+    assert_eq!(headers[1]["Value"], "List-Unsubscribe=One-Click");
+
+    let unsubscribe_url = headers[0]["Value"]
+        .as_str()
+        .unwrap()
+        .trim_start_matches('<')
+        .trim_end_matches('>')
+        .to_owned();
+    let response = reqwest::Client::new()
+        .post(unsubscribe_url)
+        .form(&[("List-Unsubscribe", "One-Click")])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
     when_sending_an_email()
-        .respond_with(ResponseTemplate::new(200))
-        .named("No task left")
+        .respond_with(PostmarkBatchResponder::default())
+        .named("Unsubscribed subscribers receive nothing")
         .expect(0)
         .mount(&app.email_server)
         .await;
+    let body = json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        },
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    app.post_newsletters(&body).await;
     app.dispatch_all_pending_emails().await;
 }
 
-fn when_sending_an_email() -> MockBuilder {
-    Mock::given(path("/email")).and(method("POST"))
+#[tokio::test]
+async fn issues_are_delivered_in_batches() {
+    let app = spawn_app().await;
+    app.login().await;
+    for _ in 0..3 {
+        create_confirmed_subscriber(&app).await;
+    }
+
+    when_sending_an_email()
+        .respond_with(PostmarkBatchResponder::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let body = json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        },
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    app.post_newsletters(&body).await;
+    app.dispatch_all_pending_emails().await;
+
+    let request = app.email_server.received_requests().await.unwrap();
+    let batch: Vec<serde_json::Value> =
+        serde_json::from_slice(&request.last().unwrap().body).unwrap();
+    assert_eq!(batch.len(), 3);
 }
 
-async fn create_confirmed_subscriber(app: &TestApp) {
-    let confirm_link = create_unconfirmed_subscriber(app).await.html;
+#[tokio::test]
+async fn only_rejected_recipients_of_a_batch_are_retried() {
+    let app = spawn_app().await;
+    app.login().await;
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+
+    let rejected = sqlx::query!("SELECT email FROM subscriptions LIMIT 1")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap()
+        .email;
 
-    reqwest::get(confirm_link).await.unwrap();
+    {
+        let _mock_guard = when_sending_an_email()
+            .respond_with(PostmarkBatchResponder {
+                // "Not allowed to send", an account problem worth retrying
+                rejections: vec![(rejected.clone(), 405)],
+            })
+            .expect(1)
+            .mount_as_scoped(&app.email_server)
+            .await;
+        let body = json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            },
+            "idempotency_key": uuid::Uuid::new_v4().to_string()
+        });
+        app.post_newsletters(&body).await;
+        app.dispatch_all_pending_emails().await;
+    }
+
+    let queued = sqlx::query!(
+        "SELECT subscriber_email, n_retries FROM issue_delivery_queue"
+    )
+    .fetch_all(&app.pool)
+    .await
+    .unwrap();
+    assert_eq!(queued.len(), 1);
+    assert_eq!(queued[0].subscriber_email, rejected);
+    assert_eq!(queued[0].n_retries, 1);
+}
+
+fn when_sending_an_email() -> MockBuilder {
+    Mock::given(path("/email/batch")).and(method("POST"))
 }
 
 // #[tokio::test]
@@ -0,0 +1,164 @@
+use reqwest::{StatusCode, Url};
+use serde_json::{Value, json};
+use uuid::Uuid;
+use wiremock::Mock;
+use wiremock::matchers::{method, path};
+
+use crate::helper::{
+    PostmarkBatchResponder, TestApp, assert_is_redirect_to,
+    create_confirmed_subscriber, spawn_app,
+};
+
+async fn publish_issue(app: &TestApp, title: &str) -> Uuid {
+    let response = app
+        .post_newsletters(&json!({
+            "title": title,
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            },
+            "idempotency_key": Uuid::new_v4().to_string()
+        }))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    sqlx::query!(
+        "SELECT newsletter_issue_id FROM newsletter_issues WHERE title = $1",
+        title
+    )
+    .fetch_one(&app.pool)
+    .await
+    .unwrap()
+    .newsletter_issue_id
+}
+
+#[tokio::test]
+async fn issues_are_listed_newest_first_and_paginated() {
+    let app = spawn_app().await;
+    app.login().await;
+    let first = publish_issue(&app, "First").await;
+    let second = publish_issue(&app, "Second").await;
+    let third = publish_issue(&app, "Third").await;
+
+    let response = app.get_newsletter_issues("per_page=2").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let page: Value = response.json().await.unwrap();
+    assert_eq!(page["total"], 3);
+    assert_eq!(page["page"], 1);
+    let ids: Vec<_> = page["issues"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|i| i["newsletter_issue_id"].as_str().unwrap().to_owned())
+        .collect();
+    assert_eq!(ids, vec![third.to_string(), second.to_string()]);
+    assert!(
+        page["issues"][0]["web_url"]
+            .as_str()
+            .unwrap()
+            .ends_with(&format!("/newsletters/{third}"))
+    );
+
+    let response = app.get_newsletter_issues("per_page=2&page=2").await;
+    let page: Value = response.json().await.unwrap();
+    assert_eq!(page["issues"].as_array().unwrap().len(), 1);
+    assert_eq!(page["issues"][0]["newsletter_issue_id"], first.to_string());
+}
+
+#[tokio::test]
+async fn invalid_pagination_is_rejected() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    for query in ["page=0", "per_page=0", "per_page=101"] {
+        let response = app.get_newsletter_issues(query).await;
+
+        assert_eq!(
+            response.status(),
+            StatusCode::BAD_REQUEST,
+            "The API did not reject {query}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn the_issue_view_includes_its_content() {
+    let app = spawn_app().await;
+    app.login().await;
+    let issue_id = publish_issue(&app, "Newsletter title").await;
+
+    let issue: Value = app
+        .get_newsletter_issue(issue_id)
+        .await
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(issue["content"]["text"], "Newsletter body as plain text");
+    assert_eq!(issue["content"]["html"], "<p>Newsletter body as HTML</p>");
+}
+
+#[tokio::test]
+async fn the_web_version_is_public() {
+    let app = spawn_app().await;
+    app.login().await;
+    let issue_id = publish_issue(&app, "Fish & <Chips>").await;
+    app.post_logout().await;
+
+    let response = app.get_web_version(issue_id).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let html = response.text().await.unwrap();
+    assert!(html.contains("<h1>Fish &amp; &lt;Chips&gt;</h1>"));
+    assert!(html.contains("<p>Newsletter body as HTML</p>"));
+}
+
+#[tokio::test]
+async fn unknown_web_versions_are_not_found() {
+    let app = spawn_app().await;
+
+    let response = app.get_web_version(Uuid::new_v4()).await;
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn emails_link_to_the_web_version() {
+    let app = spawn_app().await;
+    app.login().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let issue_id = publish_issue(&app, "Newsletter title").await;
+    app.dispatch_all_pending_emails().await;
+
+    let requests = app.email_server.received_requests().await.unwrap();
+    let messages: Vec<Value> =
+        serde_json::from_slice(&requests.last().unwrap().body).unwrap();
+    let text_body = messages[0]["TextBody"].as_str().unwrap();
+    let link = text_body
+        .lines()
+        .find_map(|l| l.strip_prefix("View in browser: "))
+        .expect("No web version link in the email");
+    assert!(messages[0]["HtmlBody"].as_str().unwrap().contains(link));
+
+    let mut link = Url::parse(link).unwrap();
+    assert_eq!(link.path(), format!("/newsletters/{issue_id}"));
+    link.set_port(Some(app.port)).unwrap();
+    let response = reqwest::get(link).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn anonymous_users_cannot_list_issues() {
+    let app = spawn_app().await;
+
+    let response = app.get_newsletter_issues("").await;
+
+    assert_is_redirect_to(&response, "/login");
+}
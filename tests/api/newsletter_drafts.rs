@@ -0,0 +1,252 @@
+use reqwest::StatusCode;
+use serde_json::{Value, json};
+use uuid::Uuid;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helper::{
+    PostmarkBatchResponder, TestApp, assert_is_redirect_to,
+    create_confirmed_subscriber, spawn_app,
+};
+
+fn draft_body(title: &str) -> Value {
+    json!({
+        "title": title,
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })
+}
+
+async fn create_draft(app: &TestApp) -> Uuid {
+    let response = app.post_draft(&draft_body("Draft title")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body: Value = response.json().await.unwrap();
+    body["newsletter_issue_id"]
+        .as_str()
+        .unwrap()
+        .parse()
+        .unwrap()
+}
+
+async fn get_issue(app: &TestApp, issue_id: Uuid) -> Value {
+    let response = app.get_newsletter_issue(issue_id).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    response.json().await.unwrap()
+}
+
+async fn n_queued(app: &TestApp) -> i64 {
+    sqlx::query!(r#"SELECT COUNT(*) AS "n!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.pool)
+        .await
+        .unwrap()
+        .n
+}
+
+#[tokio::test]
+async fn drafts_are_not_enqueued() {
+    let app = spawn_app().await;
+    app.login().await;
+    create_confirmed_subscriber(&app).await;
+
+    let issue_id = create_draft(&app).await;
+
+    let issue = get_issue(&app, issue_id).await;
+    assert_eq!(issue["status"], "draft");
+    assert_eq!(issue["published_at"], Value::Null);
+    assert_eq!(issue["delivery"]["recipients"], 0);
+    assert_eq!(issue["delivery"]["complete"], false);
+    assert_eq!(n_queued(&app).await, 0);
+    let response = app.get_web_version(issue_id).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn drafts_can_be_edited() {
+    let app = spawn_app().await;
+    app.login().await;
+    let issue_id = create_draft(&app).await;
+
+    let response = app
+        .put_draft(
+            issue_id,
+            &json!({
+                "title": "Edited title",
+                "content": { "text": "Edited text", "html": "<p>Edited</p>" }
+            }),
+        )
+        .await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let issue = get_issue(&app, issue_id).await;
+    assert_eq!(issue["title"], "Edited title");
+    assert_eq!(issue["content"]["text"], "Edited text");
+    assert_eq!(issue["content"]["html"], "<p>Edited</p>");
+}
+
+#[tokio::test]
+async fn published_issues_cannot_be_edited() {
+    let app = spawn_app().await;
+    app.login().await;
+    let issue_id = create_draft(&app).await;
+    let response = app
+        .post_publish_draft(
+            issue_id,
+            &json!({ "idempotency_key": Uuid::new_v4().to_string() }),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = app.put_draft(issue_id, &draft_body("Too late")).await;
+
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    assert_eq!(get_issue(&app, issue_id).await["title"], "Draft title");
+}
+
+#[tokio::test]
+async fn unknown_drafts_cannot_be_edited() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    let response = app.put_draft(Uuid::new_v4(), &draft_body("Title")).await;
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_sends_go_only_to_the_given_addresses() {
+    let app = spawn_app().await;
+    app.login().await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = create_draft(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_test_send(
+            issue_id,
+            &json!({ "recipients": ["editor@example.com", "qa@example.com"] }),
+        )
+        .await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let requests = app.email_server.received_requests().await.unwrap();
+    // Skipping the subscriber's confirmation email
+    let recipients: Vec<_> = requests[requests.len() - 2..]
+        .iter()
+        .map(|r| {
+            let body: Value = serde_json::from_slice(&r.body).unwrap();
+            assert_eq!(body["Subject"], "[Test] Draft title");
+            body["To"].as_str().unwrap().to_owned()
+        })
+        .collect();
+    assert_eq!(recipients, vec!["editor@example.com", "qa@example.com"]);
+    assert_eq!(n_queued(&app).await, 0);
+    assert_eq!(get_issue(&app, issue_id).await["status"], "draft");
+}
+
+#[tokio::test]
+async fn invalid_test_sends_are_rejected() {
+    let app = spawn_app().await;
+    app.login().await;
+    let issue_id = create_draft(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let too_many: Vec<_> =
+        (0..11).map(|i| format!("editor{i}@example.com")).collect();
+
+    for (recipients, description) in [
+        (json!([]), "no recipients"),
+        (json!(["not-an-email"]), "an invalid email"),
+        (json!(too_many), "too many recipients"),
+    ] {
+        let response = app
+            .post_test_send(issue_id, &json!({ "recipients": recipients }))
+            .await;
+
+        assert_eq!(
+            response.status(),
+            StatusCode::BAD_REQUEST,
+            "The API did not reject a test send with {description}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn publishing_a_draft_delivers_it_once() {
+    let app = spawn_app().await;
+    app.login().await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = create_draft(&app).await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let body = json!({ "idempotency_key": Uuid::new_v4().to_string() });
+
+    let response = app.post_publish_draft(issue_id, &body).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    // Retrying with the same key replays the saved response
+    let response = app.post_publish_draft(issue_id, &body).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    assert_eq!(n_queued(&app).await, 1);
+    let issue = get_issue(&app, issue_id).await;
+    assert_eq!(issue["status"], "published");
+    assert!(issue["published_at"].is_string());
+    app.dispatch_all_pending_emails().await;
+
+    // Publishing again under a new key is a conflict
+    let response = app
+        .post_publish_draft(
+            issue_id,
+            &json!({ "idempotency_key": Uuid::new_v4().to_string() }),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn drafts_can_be_scheduled() {
+    let app = spawn_app().await;
+    app.login().await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = create_draft(&app).await;
+    let send_at = time::OffsetDateTime::now_utc() + time::Duration::HOUR;
+
+    let response = app
+        .post_publish_draft(
+            issue_id,
+            &json!({
+                "idempotency_key": Uuid::new_v4().to_string(),
+                "send_at": send_at
+                    .format(&time::format_description::well_known::Rfc3339)
+                    .unwrap(),
+            }),
+        )
+        .await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(get_issue(&app, issue_id).await["status"], "scheduled");
+    assert_eq!(n_queued(&app).await, 1);
+}
+
+#[tokio::test]
+async fn anonymous_users_cannot_create_drafts() {
+    let app = spawn_app().await;
+
+    let response = app.post_draft(&draft_body("Title")).await;
+
+    assert_is_redirect_to(&response, "/login");
+}
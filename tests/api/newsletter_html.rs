@@ -0,0 +1,165 @@
+use reqwest::StatusCode;
+use serde_json::{Value, json};
+use uuid::Uuid;
+use wiremock::Mock;
+use wiremock::matchers::{method, path};
+
+use crate::helper::{
+    PostmarkBatchResponder, TestApp, create_confirmed_subscriber, spawn_app,
+};
+
+const HTML: &str = r#"<style>
+    p { color: #333; }
+    a:hover { color: red; }
+</style>
+<link rel="stylesheet" href="https://example.com/theme.css">
+<p onclick="track()">Hi {{ name }}</p>
+<script>alert('boo')</script>"#;
+
+async fn get_html(app: &TestApp, issue_id: &str) -> String {
+    let issue: Value = app
+        .get_newsletter_issue(issue_id.parse().unwrap())
+        .await
+        .json()
+        .await
+        .unwrap();
+    issue["content"]["html"].as_str().unwrap().to_owned()
+}
+
+fn assert_report(body: &Value) {
+    let report = &body["html_report"];
+    assert_eq!(
+        report["removed_elements"],
+        json!(["link", "script", "style"])
+    );
+    assert_eq!(report["removed_attributes"], json!(["onclick"]));
+    assert_eq!(report["skipped_css_rules"], json!(["a:hover"]));
+}
+
+#[tokio::test]
+async fn published_html_is_inlined_and_sanitized() {
+    let app = spawn_app().await;
+    app.login().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let body = json!({
+        "title": "Newsletter title",
+        "content": { "text": "Hi {{ name }}", "html": HTML },
+        "idempotency_key": Uuid::new_v4().to_string()
+    });
+
+    let response = app.post_newsletters(&body).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let response_body: Value = response.json().await.unwrap();
+    assert_report(&response_body);
+    let issue_id = response_body["newsletter_issue_id"].as_str().unwrap();
+    let html = get_html(&app, issue_id).await;
+    assert!(html.contains(r#"<p style="color: #333">Hi {{ name }}</p>"#));
+    assert!(!html.contains("<script"));
+    assert!(!html.contains("theme.css"));
+
+    // A retry gets the same report back
+    let replayed: Value =
+        app.post_newsletters(&body).await.json().await.unwrap();
+    assert_eq!(replayed, response_body);
+
+    app.dispatch_all_pending_emails().await;
+    let requests = app.email_server.received_requests().await.unwrap();
+    let messages: Vec<Value> =
+        serde_json::from_slice(&requests.last().unwrap().body).unwrap();
+    let html_body = messages[0]["HtmlBody"].as_str().unwrap();
+    assert!(html_body.contains(r#"<p style="color: #333">Hi "#));
+    assert!(!html_body.contains("<script"));
+}
+
+#[tokio::test]
+async fn markup_output_by_templates_is_sanitized_too() {
+    let app = spawn_app().await;
+    app.login().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let html = r#"{{ "<script>alert('boo')</script>"|safe }}<p>Hi</p>"#;
+
+    let response = app
+        .post_newsletters(&json!({
+            "title": "Newsletter title",
+            "content": { "text": "Hi", "html": html },
+            "idempotency_key": Uuid::new_v4().to_string()
+        }))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    app.dispatch_all_pending_emails().await;
+
+    let requests = app.email_server.received_requests().await.unwrap();
+    let messages: Vec<Value> =
+        serde_json::from_slice(&requests.last().unwrap().body).unwrap();
+    let html_body = messages[0]["HtmlBody"].as_str().unwrap();
+    assert!(html_body.contains("<p>Hi</p>"));
+    assert!(!html_body.contains("<script"));
+}
+
+#[tokio::test]
+async fn draft_html_is_prepared_when_published() {
+    let app = spawn_app().await;
+    app.login().await;
+    let response = app
+        .post_draft(&json!({
+            "title": "Draft title",
+            "content": { "text": "Hi", "html": HTML }
+        }))
+        .await;
+    let body: Value = response.json().await.unwrap();
+    let issue_id = body["newsletter_issue_id"].as_str().unwrap().to_owned();
+    // Drafts are kept as written until then
+    assert_eq!(get_html(&app, &issue_id).await, HTML);
+
+    let response = app
+        .post_publish_draft(
+            issue_id.parse().unwrap(),
+            &json!({ "idempotency_key": Uuid::new_v4().to_string() }),
+        )
+        .await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["newsletter_issue_id"], issue_id);
+    assert_report(&body);
+    let html = get_html(&app, &issue_id).await;
+    assert!(html.contains(r#"<p style="color: #333">Hi {{ name }}</p>"#));
+    assert!(!html.contains("<script"));
+}
+
+#[tokio::test]
+async fn clean_html_is_reported_as_unchanged() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    let response = app
+        .post_newsletters(&json!({
+            "title": "Newsletter title",
+            "content": { "text": "Hi", "html": "<p>Hi</p>" },
+            "idempotency_key": Uuid::new_v4().to_string()
+        }))
+        .await;
+
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(
+        body["html_report"],
+        json!({
+            "removed_elements": [],
+            "removed_attributes": [],
+            "skipped_css_rules": [],
+        })
+    );
+}
@@ -0,0 +1,130 @@
+use reqwest::StatusCode;
+use serde_json::{Value, json};
+use uuid::Uuid;
+use wiremock::Mock;
+use wiremock::matchers::{method, path};
+
+use crate::helper::{
+    PostmarkBatchResponder, TestApp, assert_is_redirect_to,
+    create_confirmed_subscriber, spawn_app,
+};
+
+async fn publish_issue(app: &TestApp) -> Uuid {
+    let response = app
+        .post_newsletters(&json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            },
+            "idempotency_key": Uuid::new_v4().to_string()
+        }))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id
+}
+
+async fn delivery(app: &TestApp, issue_id: Uuid) -> Value {
+    let response = app.get_newsletter_issue(issue_id).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let issue: Value = response.json().await.unwrap();
+    assert_eq!(issue["newsletter_issue_id"], issue_id.to_string());
+    assert_eq!(issue["title"], "Newsletter title");
+    issue["delivery"].clone()
+}
+
+#[tokio::test]
+async fn delivery_progress_is_tracked_until_the_issue_is_sent() {
+    let app = spawn_app().await;
+    app.login().await;
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+
+    let issue_id = publish_issue(&app).await;
+
+    let progress = delivery(&app, issue_id).await;
+    assert_eq!(progress["recipients"], 2);
+    assert_eq!(progress["queued"], 2);
+    assert_eq!(progress["sent"], 0);
+    assert_eq!(progress["first_sent_at"], Value::Null);
+    assert_eq!(progress["complete"], false);
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder::default())
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    let progress = delivery(&app, issue_id).await;
+    assert_eq!(progress["recipients"], 2);
+    assert_eq!(progress["queued"], 0);
+    assert_eq!(progress["sent"], 2);
+    assert_eq!(progress["failed"], 0);
+    assert!(progress["first_sent_at"].is_string());
+    assert!(progress["last_sent_at"].is_string());
+    assert_eq!(progress["complete"], true);
+}
+
+#[tokio::test]
+async fn failed_and_retrying_deliveries_are_counted() {
+    let app = spawn_app().await;
+    app.login().await;
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+    let emails: Vec<String> =
+        sqlx::query!("SELECT email FROM subscriptions ORDER BY email")
+            .fetch_all(&app.pool)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|r| r.email)
+            .collect();
+
+    let issue_id = publish_issue(&app).await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder {
+            // An inactive recipient, and an account problem worth retrying
+            rejections: vec![
+                (emails[0].clone(), 406),
+                (emails[1].clone(), 405),
+            ],
+        })
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    let progress = delivery(&app, issue_id).await;
+    assert_eq!(progress["recipients"], 2);
+    assert_eq!(progress["queued"], 1);
+    assert_eq!(progress["retrying"], 1);
+    assert_eq!(progress["sent"], 0);
+    assert_eq!(progress["failed"], 1);
+    assert_eq!(progress["complete"], false);
+}
+
+#[tokio::test]
+async fn unknown_issues_are_not_found() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    let response = app.get_newsletter_issue(Uuid::new_v4()).await;
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn anonymous_users_cannot_see_delivery_progress() {
+    let app = spawn_app().await;
+
+    let response = app.get_newsletter_issue(Uuid::new_v4()).await;
+
+    assert_is_redirect_to(&response, "/login");
+}
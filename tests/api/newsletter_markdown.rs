@@ -0,0 +1,179 @@
+use reqwest::StatusCode;
+use serde_json::{Value, json};
+use uuid::Uuid;
+use wiremock::Mock;
+use wiremock::matchers::{method, path};
+
+use crate::helper::{
+    PostmarkBatchResponder, TestApp, create_confirmed_subscriber, spawn_app,
+};
+
+const MARKDOWN: &str = "\
+# Weekly news
+
+Hi {{ name }}, read [the post](https://example.com/post).
+
+<script>alert('boo')</script>";
+
+async fn get_issue(app: &TestApp) -> Value {
+    let issue_id =
+        sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+            .fetch_one(&app.pool)
+            .await
+            .unwrap()
+            .newsletter_issue_id;
+    app.get_newsletter_issue(issue_id)
+        .await
+        .json()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn markdown_issues_are_rendered_to_both_bodies() {
+    let app = spawn_app().await;
+    app.login().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletters(&json!({
+            "title": "Newsletter title",
+            "content": { "markdown": MARKDOWN },
+            "idempotency_key": Uuid::new_v4().to_string()
+        }))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let issue = get_issue(&app).await;
+    assert_eq!(issue["content"]["markdown"], MARKDOWN);
+    let html = issue["content"]["html"].as_str().unwrap();
+    assert!(html.contains("<h1>Weekly news</h1>"));
+    assert!(html.contains(r#"<a href="https://example.com/post""#));
+    assert!(!html.contains("<script"));
+    let text = issue["content"]["text"].as_str().unwrap();
+    assert!(text.starts_with("Weekly news\n\nHi {{ name }}, read the post"));
+    assert!(text.contains("(https://example.com/post)"));
+    assert!(!text.contains("script"));
+
+    app.dispatch_all_pending_emails().await;
+    let requests = app.email_server.received_requests().await.unwrap();
+    let messages: Vec<Value> =
+        serde_json::from_slice(&requests.last().unwrap().body).unwrap();
+    let html_body = messages[0]["HtmlBody"].as_str().unwrap();
+    assert!(html_body.contains("<h1>Weekly news</h1>"));
+    assert!(!html_body.contains("{{ name }}"));
+}
+
+#[tokio::test]
+async fn templated_links_are_personalised() {
+    let app = spawn_app().await;
+    app.login().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletters(&json!({
+            "title": "Newsletter title",
+            "content": { "markdown": "[Leave us]({{unsubscribe_url}})" },
+            "idempotency_key": Uuid::new_v4().to_string()
+        }))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let issue = get_issue(&app).await;
+    let html = issue["content"]["html"].as_str().unwrap();
+    assert!(html.contains(r#"href="{{unsubscribe_url}}""#));
+
+    app.dispatch_all_pending_emails().await;
+    let requests = app.email_server.received_requests().await.unwrap();
+    let messages: Vec<Value> =
+        serde_json::from_slice(&requests.last().unwrap().body).unwrap();
+    let html_body = messages[0]["HtmlBody"].as_str().unwrap();
+    let link = html_body.split("Leave us</a>").next().unwrap();
+    let href = link.rsplit(r#"href=""#).next().unwrap();
+    assert!(href.contains("/subscriptions/unsubscribe?token="));
+}
+
+#[tokio::test]
+async fn hand_written_issues_have_no_markdown_source() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    let response = app
+        .post_newsletters(&json!({
+            "title": "Newsletter title",
+            "content": { "text": "Plain text", "html": "<p>HTML</p>" },
+            "idempotency_key": Uuid::new_v4().to_string()
+        }))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let issue = get_issue(&app).await;
+    assert_eq!(issue["content"]["markdown"], Value::Null);
+    assert_eq!(issue["content"]["html"], "<p>HTML</p>");
+}
+
+#[tokio::test]
+async fn markdown_drafts_are_rendered_again_when_edited() {
+    let app = spawn_app().await;
+    app.login().await;
+    let response = app
+        .post_draft(&json!({
+            "title": "Draft title",
+            "content": { "markdown": "First *draft*" }
+        }))
+        .await;
+    let body: Value = response.json().await.unwrap();
+    let issue_id: Uuid = body["newsletter_issue_id"]
+        .as_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+
+    let response = app
+        .put_draft(
+            issue_id,
+            &json!({
+                "title": "Draft title",
+                "content": { "markdown": "Second **draft**" }
+            }),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let issue = get_issue(&app).await;
+    assert_eq!(issue["content"]["markdown"], "Second **draft**");
+    assert_eq!(issue["content"]["text"], "Second draft");
+    assert!(
+        issue["content"]["html"]
+            .as_str()
+            .unwrap()
+            .contains("<strong>draft</strong>")
+    );
+}
+
+#[tokio::test]
+async fn markdown_with_a_broken_template_is_rejected() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    let response = app
+        .post_newsletters(&json!({
+            "title": "Newsletter title",
+            "content": { "markdown": "Hi {{ first_name }}" },
+            "idempotency_key": Uuid::new_v4().to_string()
+        }))
+        .await;
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
@@ -0,0 +1,296 @@
+use std::time::Duration as StdDuration;
+
+use reqwest::StatusCode;
+use serde_json::{Value, json};
+use time::format_description::well_known::Rfc3339;
+use time::{Duration, OffsetDateTime};
+use uuid::Uuid;
+use wiremock::Mock;
+use wiremock::matchers::{any, method, path};
+
+use crate::helper::{
+    PostmarkBatchResponder, TestApp, assert_is_redirect_to,
+    create_confirmed_subscriber, spawn_app,
+};
+
+fn in_future(offset: Duration) -> OffsetDateTime {
+    (OffsetDateTime::now_utc() + offset)
+        .replace_nanosecond(0)
+        .unwrap()
+}
+
+fn rfc3339(t: OffsetDateTime) -> String {
+    t.format(&Rfc3339).unwrap()
+}
+
+async fn schedule_issue(app: &TestApp, send_at: OffsetDateTime) -> Uuid {
+    let response = app
+        .post_newsletters(&json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            },
+            "idempotency_key": Uuid::new_v4().to_string(),
+            "send_at": rfc3339(send_at),
+        }))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id
+}
+
+async fn status(app: &TestApp, issue_id: Uuid) -> Value {
+    let issue: Value = app
+        .get_newsletter_issue(issue_id)
+        .await
+        .json()
+        .await
+        .unwrap();
+    issue["status"].clone()
+}
+
+async fn execute_after(app: &TestApp, issue_id: Uuid) -> Vec<OffsetDateTime> {
+    sqlx::query!(
+        r#"
+        SELECT execute_after
+        FROM issue_delivery_queue
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .fetch_all(&app.pool)
+    .await
+    .unwrap()
+    .into_iter()
+    .map(|r| r.execute_after)
+    .collect()
+}
+
+#[tokio::test]
+async fn scheduled_issues_are_not_delivered_before_their_send_time() {
+    let app = spawn_app().await;
+    app.login().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(any())
+        .respond_with(PostmarkBatchResponder::default())
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let send_at = in_future(Duration::hours(1));
+
+    let issue_id = schedule_issue(&app, send_at).await;
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(status(&app, issue_id).await, "scheduled");
+    assert_eq!(execute_after(&app, issue_id).await, vec![send_at]);
+    let response = app.get_web_version(issue_id).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn scheduled_issues_are_delivered_once_due() {
+    let app = spawn_app().await;
+    app.login().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let issue_id =
+        schedule_issue(&app, OffsetDateTime::now_utc() + Duration::SECOND)
+            .await;
+    tokio::time::sleep(StdDuration::from_millis(1500)).await;
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(status(&app, issue_id).await, "published");
+    let response = app.get_web_version(issue_id).await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn scheduled_issues_with_nothing_queued_are_not_complete() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    let issue_id = schedule_issue(&app, in_future(Duration::hours(1))).await;
+
+    let issue: Value = app
+        .get_newsletter_issue(issue_id)
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(issue["delivery"]["queued"], 0);
+    assert_eq!(issue["delivery"]["complete"], false);
+}
+
+#[tokio::test]
+async fn send_times_in_the_past_are_rejected() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    let response = app
+        .post_newsletters(&json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            },
+            "idempotency_key": Uuid::new_v4().to_string(),
+            "send_at": rfc3339(OffsetDateTime::now_utc() - Duration::HOUR),
+        }))
+        .await;
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn rescheduling_moves_the_pending_deliveries() {
+    let app = spawn_app().await;
+    app.login().await;
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = schedule_issue(&app, in_future(Duration::hours(1))).await;
+    let send_at = in_future(Duration::days(1));
+
+    let response = app
+        .post_reschedule(issue_id, &json!({ "send_at": rfc3339(send_at) }))
+        .await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(execute_after(&app, issue_id).await, vec![send_at, send_at]);
+    let issue: Value = app
+        .get_newsletter_issue(issue_id)
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(issue["published_at"], rfc3339(send_at));
+    assert_eq!(issue["status"], "scheduled");
+}
+
+#[tokio::test]
+async fn rescheduling_into_the_past_is_rejected() {
+    let app = spawn_app().await;
+    app.login().await;
+    let send_at = in_future(Duration::hours(1));
+    let issue_id = schedule_issue(&app, send_at).await;
+
+    let response = app
+        .post_reschedule(
+            issue_id,
+            &json!({
+                "send_at": rfc3339(OffsetDateTime::now_utc() - Duration::HOUR)
+            }),
+        )
+        .await;
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let issue: Value = app
+        .get_newsletter_issue(issue_id)
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(issue["published_at"], rfc3339(send_at));
+}
+
+#[tokio::test]
+async fn cancelling_drops_the_pending_deliveries() {
+    let app = spawn_app().await;
+    app.login().await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = schedule_issue(&app, in_future(Duration::hours(1))).await;
+
+    let response = app.post_cancel(issue_id).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(status(&app, issue_id).await, "cancelled");
+    assert!(execute_after(&app, issue_id).await.is_empty());
+
+    // A cancelled issue can no longer be rescheduled
+    let send_at = in_future(Duration::days(1));
+    let response = app
+        .post_reschedule(issue_id, &json!({ "send_at": rfc3339(send_at) }))
+        .await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn resuming_a_paused_issue_keeps_it_scheduled() {
+    let app = spawn_app().await;
+    app.login().await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = schedule_issue(&app, in_future(Duration::hours(1))).await;
+    app.post_pause(issue_id).await;
+
+    let response = app.post_resume(issue_id).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(status(&app, issue_id).await, "scheduled");
+    let send_at = in_future(Duration::days(1));
+    let response = app
+        .post_reschedule(issue_id, &json!({ "send_at": rfc3339(send_at) }))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn issues_sent_right_away_cannot_be_changed() {
+    let app = spawn_app().await;
+    app.login().await;
+    let response = app
+        .post_newsletters(&json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            },
+            "idempotency_key": Uuid::new_v4().to_string(),
+        }))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let issue_id =
+        sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+            .fetch_one(&app.pool)
+            .await
+            .unwrap()
+            .newsletter_issue_id;
+    assert_eq!(status(&app, issue_id).await, "published");
+
+    let response = app.post_cancel(issue_id).await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    let send_at = in_future(Duration::days(1));
+    let response = app
+        .post_reschedule(issue_id, &json!({ "send_at": rfc3339(send_at) }))
+        .await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn unknown_issues_cannot_be_changed() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    let response = app.post_cancel(Uuid::new_v4()).await;
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn anonymous_users_cannot_cancel_issues() {
+    let app = spawn_app().await;
+
+    let response = app.post_cancel(Uuid::new_v4()).await;
+
+    assert_is_redirect_to(&response, "/login");
+}
@@ -0,0 +1,198 @@
+use std::collections::HashMap;
+
+use reqwest::{StatusCode, Url};
+use serde_json::{Value, json};
+use uuid::Uuid;
+use wiremock::Mock;
+use wiremock::matchers::{method, path};
+
+use crate::helper::{
+    PostmarkBatchResponder, TestApp, create_confirmed_subscriber, spawn_app,
+};
+
+fn newsletter(text: &str, html: &str) -> Value {
+    json!({
+        "title": "Newsletter title",
+        "content": { "text": text, "html": html },
+        "idempotency_key": Uuid::new_v4().to_string()
+    })
+}
+
+async fn n_issues_and_tasks(app: &TestApp) -> (i64, i64) {
+    let record = sqlx::query!(
+        r#"
+        SELECT
+            (SELECT COUNT(*) FROM newsletter_issues) AS "issues!",
+            (SELECT COUNT(*) FROM issue_delivery_queue) AS "tasks!"
+        "#
+    )
+    .fetch_one(&app.pool)
+    .await
+    .unwrap();
+    (record.issues, record.tasks)
+}
+
+#[tokio::test]
+async fn each_subscriber_gets_their_own_rendering() {
+    let app = spawn_app().await;
+    app.login().await;
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+    let subscribers: HashMap<String, (String, String)> = sqlx::query!(
+        "SELECT email, name, unsubscribe_token FROM subscriptions"
+    )
+    .fetch_all(&app.pool)
+    .await
+    .unwrap()
+    .into_iter()
+    .map(|r| (r.email, (r.name, r.unsubscribe_token)))
+    .collect();
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletters(&newsletter(
+            "Hi {{ name }}, unsubscribe at {{ unsubscribe_url }}",
+            r#"<p>Hi {{ name }}</p><a href="{{ view_online_url }}">Online</a>"#,
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    app.dispatch_all_pending_emails().await;
+
+    let requests = app.email_server.received_requests().await.unwrap();
+    let messages: Vec<Value> =
+        serde_json::from_slice(&requests.last().unwrap().body).unwrap();
+    assert_eq!(messages.len(), 2);
+    for message in messages {
+        let (name, token) = &subscribers[message["To"].as_str().unwrap()];
+        let text_body = message["TextBody"].as_str().unwrap();
+        let html_body = message["HtmlBody"].as_str().unwrap();
+        assert!(text_body.starts_with(&format!("Hi {name}, unsubscribe at ")));
+        assert!(
+            text_body
+                .lines()
+                .next()
+                .unwrap()
+                .ends_with(&format!("token={token}"))
+        );
+        assert!(html_body.contains("/newsletters/"));
+        assert!(!html_body.contains("{{"));
+    }
+}
+
+#[tokio::test]
+async fn broken_templates_are_rejected_before_anything_is_enqueued() {
+    let app = spawn_app().await;
+    app.login().await;
+    create_confirmed_subscriber(&app).await;
+
+    for (text, html, description) in [
+        ("Hi {{ name", "<p>Hi</p>", "an unclosed tag"),
+        ("Hi", "<p>{% for %}</p>", "a broken block"),
+        ("Hi {{ first_name }}", "<p>Hi</p>", "an unknown variable"),
+    ] {
+        let response = app.post_newsletters(&newsletter(text, html)).await;
+
+        assert_eq!(
+            response.status(),
+            StatusCode::BAD_REQUEST,
+            "The API did not reject a newsletter with {description}"
+        );
+    }
+    assert_eq!(n_issues_and_tasks(&app).await, (0, 0));
+}
+
+#[tokio::test]
+async fn drafts_with_broken_templates_cannot_be_published() {
+    let app = spawn_app().await;
+    app.login().await;
+    create_confirmed_subscriber(&app).await;
+    let response = app
+        .post_draft(&json!({
+            "title": "Draft title",
+            "content": { "text": "Hi {{ nmae }}", "html": "<p>Hi</p>" }
+        }))
+        .await;
+    let body: Value = response.json().await.unwrap();
+    let issue_id: Uuid = body["newsletter_issue_id"]
+        .as_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+
+    let response = app
+        .post_publish_draft(
+            issue_id,
+            &json!({ "idempotency_key": Uuid::new_v4().to_string() }),
+        )
+        .await;
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(n_issues_and_tasks(&app).await, (1, 0));
+    let issue: Value = app
+        .get_newsletter_issue(issue_id)
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(issue["status"], "draft");
+}
+
+#[tokio::test]
+async fn the_web_version_is_rendered_for_a_generic_reader() {
+    let app = spawn_app().await;
+    app.login().await;
+    let response = app
+        .post_newsletters(&newsletter("Hi {{ name }}", "<p>Hi {{ name }}</p>"))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let issue_id =
+        sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+            .fetch_one(&app.pool)
+            .await
+            .unwrap()
+            .newsletter_issue_id;
+
+    let html = app.get_web_version(issue_id).await.text().await.unwrap();
+
+    assert!(html.contains("<p>Hi Subscriber</p>"));
+}
+
+#[tokio::test]
+async fn the_unsubscribe_link_of_the_web_version_works() {
+    let app = spawn_app().await;
+    app.login().await;
+    let response = app
+        .post_newsletters(&newsletter(
+            "Bye: {{ unsubscribe_url }}",
+            r#"<a href="{{ unsubscribe_url }}">Bye</a>"#,
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let issue_id =
+        sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+            .fetch_one(&app.pool)
+            .await
+            .unwrap()
+            .newsletter_issue_id;
+    let html = app.get_web_version(issue_id).await.text().await.unwrap();
+    // The href is HTML-escaped, as a browser would read it
+    let href = html
+        .split(r#"<a href=""#)
+        .nth(1)
+        .and_then(|rest| rest.split('"').next())
+        .unwrap()
+        .replace("&#x2f;", "/");
+    let mut link = Url::parse(&href).unwrap();
+    link.set_port(Some(app.port)).unwrap();
+
+    let response = reqwest::get(link).await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let page = response.text().await.unwrap();
+    assert!(page.contains("unsubscribe link"));
+}
@@ -0,0 +1,352 @@
+use reqwest::StatusCode;
+use serde_json::{Value, json};
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helper::{TestApp, assert_is_redirect_to, spawn_app};
+
+/// Signs up and confirms `email`, returning its subscriber id.
+async fn subscribe(app: &TestApp, email: &str, profile: Value) -> Uuid {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    let mut body = json!({ "name": "le guin", "email": email });
+    body.as_object_mut()
+        .unwrap()
+        .extend(profile.as_object().unwrap().clone());
+    let response = app.post_subscriptions_json(&body).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    app.dispatch_all_outbox_emails().await;
+
+    let request = app.email_server.received_requests().await.unwrap();
+    let link = app.extract_links(request.last().unwrap()).html;
+    reqwest::get(link).await.unwrap();
+
+    sqlx::query!("SELECT id FROM subscriptions WHERE email = $1", email)
+        .fetch_one(&app.pool)
+        .await
+        .unwrap()
+        .id
+}
+
+async fn profile(app: &TestApp, subscriber_id: Uuid) -> (Vec<String>, Value) {
+    let record = sqlx::query!(
+        r#"
+        SELECT tags, attributes::text AS "attributes!"
+        FROM subscriptions
+        WHERE id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_one(&app.pool)
+    .await
+    .unwrap();
+    (
+        record.tags,
+        serde_json::from_str(&record.attributes).unwrap(),
+    )
+}
+
+fn issue_for(segment: Value) -> Value {
+    json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        },
+        "idempotency_key": Uuid::new_v4().to_string(),
+        "segment": segment,
+    })
+}
+
+async fn recipients_of(app: &TestApp, segment: Value) -> Vec<String> {
+    let response = app.post_newsletters(&issue_for(segment)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: Value = response.json().await.unwrap();
+    let issue_id: Uuid = body["newsletter_issue_id"]
+        .as_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+
+    sqlx::query!(
+        r#"
+        SELECT subscriber_email
+        FROM issue_delivery_queue
+        WHERE newsletter_issue_id = $1
+        ORDER BY subscriber_email
+        "#,
+        issue_id
+    )
+    .fetch_all(&app.pool)
+    .await
+    .unwrap()
+    .into_iter()
+    .map(|r| r.subscriber_email)
+    .collect()
+}
+
+async fn subscribe_audience(app: &TestApp) {
+    subscribe(
+        app,
+        "beta-pro@example.com",
+        json!({"tags": ["beta"], "attributes": {"plan": "pro"}}),
+    )
+    .await;
+    subscribe(
+        app,
+        "beta-free@example.com",
+        json!({"tags": ["beta"], "attributes": {"plan": "free"}}),
+    )
+    .await;
+    subscribe(app, "nobody@example.com", json!({})).await;
+}
+
+#[tokio::test]
+async fn tags_and_attributes_can_be_given_at_signup() {
+    let app = spawn_app().await;
+
+    let subscriber_id = subscribe(
+        &app,
+        "reader@example.com",
+        json!({"tags": ["beta"], "attributes": {"plan": "pro", "seats": 3}}),
+    )
+    .await;
+
+    let (tags, attributes) = profile(&app, subscriber_id).await;
+    assert_eq!(tags, ["beta"]);
+    assert_eq!(attributes, json!({"plan": "pro", "seats": 3}));
+}
+
+#[tokio::test]
+async fn signing_up_again_does_not_change_the_profile() {
+    let app = spawn_app().await;
+    let subscriber_id = subscribe(
+        &app,
+        "reader@example.com",
+        json!({"tags": ["beta"], "attributes": {"plan": "pro"}}),
+    )
+    .await;
+
+    let response = app
+        .post_subscriptions_json(&json!({
+            "name": "le guin",
+            "email": "reader@example.com",
+            "tags": ["beta", "conference"],
+            "attributes": {"plan": "free", "city": "Lyon"},
+        }))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Anyone can post an address: only an admin changes what is stored.
+    let (tags, attributes) = profile(&app, subscriber_id).await;
+    assert_eq!(tags, ["beta"]);
+    assert_eq!(attributes, json!({"plan": "pro"}));
+}
+
+#[tokio::test]
+async fn invalid_tags_at_signup_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_subscriptions_json(&json!({
+            "name": "le guin",
+            "email": "reader@example.com",
+            "tags": ["two words"],
+        }))
+        .await;
+
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_edit_subscriber_profiles() {
+    let app = spawn_app().await;
+    let subscriber_id = Uuid::new_v4();
+
+    let response = app
+        .put_subscriber_tags(subscriber_id, &json!({"tags": ["beta"]}))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+    let response = app
+        .patch_subscriber_attributes(subscriber_id, &json!({"plan": "pro"}))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn admins_can_replace_the_tags_of_a_subscriber() {
+    let app = spawn_app().await;
+    app.login().await;
+    let subscriber_id =
+        subscribe(&app, "reader@example.com", json!({"tags": ["beta"]})).await;
+
+    let response = app
+        .put_subscriber_tags(
+            subscriber_id,
+            &json!({"tags": ["vip", "conference", "vip"]}),
+        )
+        .await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["tags"], json!(["conference", "vip"]));
+    let (tags, _) = profile(&app, subscriber_id).await;
+    assert_eq!(tags, ["conference", "vip"]);
+}
+
+#[tokio::test]
+async fn attribute_patches_are_merged_and_null_removes_attributes() {
+    let app = spawn_app().await;
+    app.login().await;
+    let subscriber_id = subscribe(
+        &app,
+        "reader@example.com",
+        json!({"attributes": {"plan": "pro", "city": "Lyon"}}),
+    )
+    .await;
+
+    let response = app
+        .patch_subscriber_attributes(
+            subscriber_id,
+            &json!({"plan": "team", "city": null, "seats": 5}),
+        )
+        .await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let expected = json!({"plan": "team", "seats": 5});
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["attributes"], expected);
+    let (_, attributes) = profile(&app, subscriber_id).await;
+    assert_eq!(attributes, expected);
+}
+
+#[tokio::test]
+async fn editing_an_unknown_subscriber_returns_404() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    let response = app
+        .put_subscriber_tags(Uuid::new_v4(), &json!({"tags": ["beta"]}))
+        .await;
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn issues_can_target_a_tag() {
+    let app = spawn_app().await;
+    app.login().await;
+    subscribe_audience(&app).await;
+
+    let recipients = recipients_of(&app, json!({"tag": "beta"})).await;
+
+    assert_eq!(
+        recipients,
+        ["beta-free@example.com", "beta-pro@example.com"]
+    );
+}
+
+#[tokio::test]
+async fn issues_can_target_an_attribute_value() {
+    let app = spawn_app().await;
+    app.login().await;
+    subscribe_audience(&app).await;
+
+    let segment = json!({"attribute": {"name": "plan", "equals": "pro"}});
+    let recipients = recipients_of(&app, segment).await;
+
+    assert_eq!(recipients, ["beta-pro@example.com"]);
+}
+
+#[tokio::test]
+async fn negated_conditions_include_subscribers_without_the_attribute() {
+    let app = spawn_app().await;
+    app.login().await;
+    subscribe_audience(&app).await;
+
+    let segment =
+        json!({"not": {"attribute": {"name": "plan", "equals": "free"}}});
+    let recipients = recipients_of(&app, segment).await;
+
+    assert_eq!(recipients, ["beta-pro@example.com", "nobody@example.com"]);
+}
+
+#[tokio::test]
+async fn conditions_can_be_combined() {
+    let app = spawn_app().await;
+    app.login().await;
+    subscribe_audience(&app).await;
+
+    let segment = json!({"any": [
+        {"all": [
+            {"tag": "beta"},
+            {"attribute": {"name": "plan", "equals": "free"}},
+        ]},
+        {"not": {"tag": "beta"}},
+    ]});
+    let recipients = recipients_of(&app, segment).await;
+
+    assert_eq!(recipients, ["beta-free@example.com", "nobody@example.com"]);
+}
+
+#[tokio::test]
+async fn issues_can_target_subscribers_by_signup_date() {
+    let app = spawn_app().await;
+    app.login().await;
+    subscribe(&app, "early@example.com", json!({})).await;
+    let cutoff = OffsetDateTime::now_utc().format(&Rfc3339).unwrap();
+    subscribe(&app, "late@example.com", json!({})).await;
+
+    let before =
+        recipients_of(&app, json!({"subscribed_before": cutoff})).await;
+    let after = recipients_of(&app, json!({"subscribed_after": cutoff})).await;
+
+    assert_eq!(before, ["early@example.com"]);
+    assert_eq!(after, ["late@example.com"]);
+}
+
+#[tokio::test]
+async fn the_issue_shows_the_segment_it_was_sent_to() {
+    let app = spawn_app().await;
+    app.login().await;
+    let segment = json!({"tag": "beta"});
+    let response = app.post_newsletters(&issue_for(segment.clone())).await;
+    let body: Value = response.json().await.unwrap();
+    let issue_id: Uuid = body["newsletter_issue_id"]
+        .as_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+
+    let issue: Value = app
+        .get_newsletter_issue(issue_id)
+        .await
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(issue["audience"]["lists"], json!(["newsletter"]));
+    assert_eq!(issue["audience"]["segment"], segment);
+}
+
+#[tokio::test]
+async fn oversized_segments_are_rejected() {
+    let app = spawn_app().await;
+    app.login().await;
+    let mut segment = json!({"tag": "beta"});
+    for _ in 0..10 {
+        segment = json!({"not": segment});
+    }
+
+    let response = app.post_newsletters(&issue_for(segment)).await;
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
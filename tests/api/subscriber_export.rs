@@ -0,0 +1,126 @@
+use reqwest::StatusCode;
+use serde_json::Value;
+
+use crate::helper::{TestApp, assert_is_redirect_to, spawn_app};
+
+async fn import(app: &TestApp, query: &str, csv: &str) {
+    let response = app.post_subscriber_import(query, csv).await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_export_subscribers() {
+    let app = spawn_app().await;
+
+    let response = app.get_subscriber_export("").await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn subscribers_are_exported_as_csv_by_default() {
+    let app = spawn_app().await;
+    app.login().await;
+    import(
+        &app,
+        "confirmed=true",
+        "email,name\nursula@example.com,Ursula\noctavia@example.com,\"Butler, Octavia\"\n",
+    )
+    .await;
+
+    let response = app.get_subscriber_export("").await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()["content-type"],
+        "text/csv; charset=utf-8"
+    );
+    let body = response.text().await.unwrap();
+    let lines: Vec<_> = body.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0], "id,email,name,status,subscribed_at");
+    assert!(lines[1].contains(",ursula@example.com,Ursula,confirmed,"));
+    assert!(
+        lines[2]
+            .contains(",octavia@example.com,\"Butler, Octavia\",confirmed,")
+    );
+}
+
+#[tokio::test]
+async fn subscribers_can_be_exported_as_ndjson_filtered_by_status() {
+    let app = spawn_app().await;
+    app.login().await;
+    import(
+        &app,
+        "confirmed=true",
+        "email,name\nursula@example.com,Ursula\n",
+    )
+    .await;
+    import(&app, "", "email,name\noctavia@example.com,Octavia\n").await;
+
+    let response = app
+        .get_subscriber_export("format=ndjson&status=pending_confirmation")
+        .await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["content-type"], "application/x-ndjson");
+    let body = response.text().await.unwrap();
+    let subscribers: Vec<Value> = body
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(subscribers.len(), 1);
+    assert_eq!(subscribers[0]["email"], "octavia@example.com");
+    assert_eq!(subscribers[0]["name"], "Octavia");
+    assert_eq!(subscribers[0]["status"], "pending_confirmation");
+    assert!(subscribers[0]["id"].is_string());
+    assert!(subscribers[0]["subscribed_at"].is_string());
+}
+
+#[tokio::test]
+async fn exports_span_several_cursor_batches() {
+    let app = spawn_app().await;
+    app.login().await;
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions
+            (id, email, name, subscribed_at, status, unsubscribe_token)
+        SELECT
+            gen_random_uuid(),
+            'reader' || i || '@example.com',
+            'reader',
+            NOW() + i * INTERVAL '1 second',
+            'confirmed',
+            gen_random_uuid()::text
+        FROM generate_series(1, 1234) AS i
+        "#
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+
+    let response = app.get_subscriber_export("format=ndjson").await;
+
+    let body = response.text().await.unwrap();
+    let emails: Vec<String> = body
+        .lines()
+        .map(|line| {
+            let subscriber: Value = serde_json::from_str(line).unwrap();
+            subscriber["email"].as_str().unwrap().to_owned()
+        })
+        .collect();
+    assert_eq!(emails.len(), 1234);
+    assert_eq!(emails[0], "reader1@example.com");
+    assert_eq!(emails[1233], "reader1234@example.com");
+}
+
+#[tokio::test]
+async fn unknown_formats_and_statuses_are_rejected() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    for query in ["format=xml", "status=deleted"] {
+        let response = app.get_subscriber_export(query).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,165 @@
+use reqwest::StatusCode;
+use serde_json::{Value, json};
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helper::{
+    assert_is_redirect_to, create_confirmed_subscriber, spawn_app,
+};
+
+const CSV: &str = "\
+Name,Email,Company
+Ursula Le Guin,ursula@example.com,Earthsea
+\"Butler, Octavia\",octavia@example.com,
+Nobody,not-an-email,
+Ursula again,ursula@example.com,
+Ursula shouting,URSULA@EXAMPLE.COM,
+";
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_import_subscribers() {
+    let app = spawn_app().await;
+
+    let response = app.post_subscriber_import("", CSV).await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn each_line_is_reported_as_accepted_duplicate_or_rejected() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    let response = app.post_subscriber_import("", CSV).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let report: Value = response.json().await.unwrap();
+    assert_eq!(report["accepted"], 2);
+    assert_eq!(report["duplicates"], 2);
+    assert_eq!(report["rejected"], 1);
+    let rows = report["rows"].as_array().unwrap();
+    let outcomes: Vec<_> = rows
+        .iter()
+        .map(|r| (r["line"].as_u64().unwrap(), r["outcome"].as_str().unwrap()))
+        .collect();
+    assert_eq!(
+        outcomes,
+        [
+            (2, "accepted"),
+            (3, "accepted"),
+            (4, "rejected"),
+            (5, "duplicate"),
+            (6, "duplicate")
+        ]
+    );
+    assert_eq!(rows[2]["email"], "not-an-email");
+    assert!(rows[2]["reason"].is_string());
+
+    let name = sqlx::query!(
+        "SELECT name FROM subscriptions WHERE email = 'octavia@example.com'"
+    )
+    .fetch_one(&app.pool)
+    .await
+    .unwrap()
+    .name;
+    assert_eq!(name, "Butler, Octavia");
+}
+
+#[tokio::test]
+async fn imported_subscribers_are_sent_a_confirmation_email_by_default() {
+    let app = spawn_app().await;
+    app.login().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriber_import("", CSV).await;
+    app.dispatch_all_outbox_emails().await;
+
+    let statuses = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_all(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(statuses.len(), 2);
+    assert!(statuses.iter().all(|s| s.status == "pending_confirmation"));
+}
+
+#[tokio::test]
+async fn subscribers_imported_as_confirmed_get_newsletters_right_away() {
+    let app = spawn_app().await;
+    app.login().await;
+    let _guard = Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    let response = app.post_subscriber_import("confirmed=true", CSV).await;
+    app.dispatch_all_outbox_emails().await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = app
+        .post_newsletters(&json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            },
+            "idempotency_key": uuid::Uuid::new_v4().to_string(),
+        }))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let queued =
+        sqlx::query!("SELECT subscriber_email FROM issue_delivery_queue")
+            .fetch_all(&app.pool)
+            .await
+            .unwrap();
+    assert_eq!(queued.len(), 2);
+}
+
+#[tokio::test]
+async fn existing_subscribers_are_left_untouched() {
+    let app = spawn_app().await;
+    app.login().await;
+    create_confirmed_subscriber(&app).await;
+    let existing = sqlx::query!("SELECT email, name FROM subscriptions")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    let _guard = Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    let csv = format!(
+        "email,name\n{},Someone else\n{},Someone louder\n",
+        existing.email,
+        existing.email.to_uppercase(),
+    );
+    let response = app.post_subscriber_import("", &csv).await;
+    app.dispatch_all_outbox_emails().await;
+
+    let report: Value = response.json().await.unwrap();
+    assert_eq!(report["duplicates"], 2);
+    let stored = sqlx::query!("SELECT name, status FROM subscriptions")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(stored.name, existing.name);
+    assert_eq!(stored.status, "confirmed");
+}
+
+#[tokio::test]
+async fn files_without_email_and_name_columns_are_rejected() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    for csv in ["", "mail,name\nursula@example.com,Ursula\n"] {
+        let response = app.post_subscriber_import("", csv).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -1,5 +1,7 @@
 use std::collections::HashMap;
 
+use craft::domain::subscriber::SubscriberStatus;
+
 use wiremock::matchers::{method, path};
 use wiremock::{Mock, ResponseTemplate};
 
@@ -128,6 +130,7 @@ async fn send_confirm_email_for_valid_subscription() {
         .await;
 
     app.post_subscriptions(&subscriber).await;
+    app.dispatch_all_outbox_emails().await;
 }
 
 #[tokio::test]
@@ -142,6 +145,7 @@ async fn confirm_email_contains_link() {
         .await;
 
     app.post_subscriptions(&subscriber).await;
+    app.dispatch_all_outbox_emails().await;
 
     let request = &app.email_server.received_requests().await.unwrap()[0];
 
@@ -169,3 +173,204 @@ async fn subscribe_fails_if_there_is_a_fatal_database_error() {
 
     // tracing::info!("Response: {:?}", response.text().await);
 }
+
+#[tokio::test]
+async fn subscribe_fails_with_503_if_the_confirmation_email_cannot_be_queued() {
+    let app = spawn_app().await;
+    sqlx::query!("ALTER TABLE email_outbox DROP COLUMN html_body;")
+        .execute(&app.pool)
+        .await
+        .unwrap();
+
+    let response = app.post_subscriptions(&valid_subscriber()).await;
+
+    assert_eq!(response.status().as_u16(), 503);
+    // Nothing was stored, so that trying again starts afresh
+    let saved = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_all(&app.pool)
+        .await
+        .unwrap();
+    assert!(saved.is_empty());
+}
+
+#[tokio::test]
+async fn subscribing_twice_while_pending_resends_the_confirmation_email() {
+    let app = spawn_app().await;
+    let subscriber = valid_subscriber();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_subscriptions(&subscriber).await;
+    app.dispatch_all_outbox_emails().await;
+    assert_eq!(response.status().as_u16(), 200);
+    let response = app.post_subscriptions(&subscriber).await;
+    app.dispatch_all_outbox_emails().await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let requests = app.email_server.received_requests().await.unwrap();
+    let first_link = app.extract_links(&requests[0]).html;
+    let second_link = app.extract_links(&requests[1]).html;
+    assert_ne!(first_link, second_link);
+
+    // the token is rotated, only the latest link confirms the subscription
+    let response = reqwest::get(first_link).await.unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+    let response = reqwest::get(second_link).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn addresses_are_matched_regardless_of_case() {
+    let app = spawn_app().await;
+    let mut subscriber = valid_subscriber();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(&subscriber).await;
+    let email = subscriber["email"].to_uppercase();
+    subscriber.insert("email".to_string(), email);
+    let response = app.post_subscriptions(&subscriber).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_all(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.len(), 1);
+}
+
+#[tokio::test]
+async fn subscribing_again_once_confirmed_has_no_side_effects() {
+    let app = spawn_app().await;
+    let subscriber = valid_subscriber();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(&subscriber).await;
+    app.dispatch_all_outbox_emails().await;
+    let request = &app.email_server.received_requests().await.unwrap()[0];
+    reqwest::get(app.extract_links(request).html).await.unwrap();
+
+    let response = app.post_subscriptions(&subscriber).await;
+    app.dispatch_all_outbox_emails().await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, SubscriberStatus::Confirmed.to_string());
+}
+
+#[tokio::test]
+async fn unsubscribed_subscribers_are_opted_in_again_through_confirmation() {
+    let app = spawn_app().await;
+    let subscriber = valid_subscriber();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(&subscriber).await;
+    app.dispatch_all_outbox_emails().await;
+    let token = sqlx::query!("SELECT unsubscribe_token FROM subscriptions")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap()
+        .unsubscribe_token;
+    app.post_unsubscribe(Some(&token)).await;
+
+    let response = app.post_subscriptions(&subscriber).await;
+    app.dispatch_all_outbox_emails().await;
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(
+        saved.status,
+        SubscriberStatus::PendingConfirmation.to_string()
+    );
+
+    let request = &app.email_server.received_requests().await.unwrap()[1];
+    reqwest::get(app.extract_links(request).html).await.unwrap();
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, SubscriberStatus::Confirmed.to_string());
+}
+
+#[tokio::test]
+async fn subscribe_does_not_wait_for_the_email_provider() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_subscriptions(&valid_subscriber()).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let outbox = sqlx::query!("SELECT n_retries FROM email_outbox")
+        .fetch_one(&app.pool)
+        .await
+        .expect("The confirmation email was not stored in the outbox");
+    assert_eq!(outbox.n_retries, 0);
+}
+
+#[tokio::test]
+async fn confirmation_emails_are_retried_when_the_provider_fails() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(&valid_subscriber()).await;
+    app.dispatch_all_outbox_emails().await;
+
+    let outbox = sqlx::query!("SELECT n_retries FROM email_outbox")
+        .fetch_one(&app.pool)
+        .await
+        .expect("The confirmation email was dropped after one failure");
+    assert_eq!(outbox.n_retries, 1);
+
+    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_outbox_emails().await;
+
+    let outbox = sqlx::query!("SELECT n_retries FROM email_outbox")
+        .fetch_optional(&app.pool)
+        .await
+        .unwrap();
+    assert!(outbox.is_none());
+}
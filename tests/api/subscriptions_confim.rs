@@ -1,8 +1,9 @@
 use craft::domain::subscriber::SubscriberStatus;
+use reqwest::Url;
 use wiremock::matchers::{method, path};
 use wiremock::{Mock, ResponseTemplate};
 
-use crate::helper::{spawn_app, valid_subscriber};
+use crate::helper::{TestApp, spawn_app, valid_subscriber};
 
 #[tokio::test]
 async fn reject_with_400_for_confirmation_without_token() {
@@ -33,6 +34,7 @@ async fn the_link_returned_by_subscribe_returns_a_200_if_called() {
         .await;
 
     app.post_subscriptions(&subscriber).await;
+    app.dispatch_all_outbox_emails().await;
 
     let request = &app.email_server.received_requests().await.unwrap()[0];
 
@@ -60,6 +62,7 @@ async fn clicking_the_link_confirms_the_subscription() {
         .await;
 
     app.post_subscriptions(&subscriber).await;
+    app.dispatch_all_outbox_emails().await;
 
     let request = &app.email_server.received_requests().await.unwrap()[0];
     let links = app.extract_links(request);
@@ -79,3 +82,125 @@ async fn clicking_the_link_confirms_the_subscription() {
     assert_eq!(saved.name, subscriber["name"]);
     assert_eq!(saved.status, SubscriberStatus::Confirmed.to_string())
 }
+
+async fn confirmation_link(app: &TestApp) -> Url {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(&valid_subscriber()).await;
+    app.dispatch_all_outbox_emails().await;
+
+    let request = &app.email_server.received_requests().await.unwrap()[0];
+    app.extract_links(request).html
+}
+
+#[tokio::test]
+async fn confirmation_links_are_single_use() {
+    let app = spawn_app().await;
+    let link = confirmation_link(&app).await;
+
+    let response = reqwest::get(link.clone()).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = reqwest::get(link).await.unwrap();
+    assert_eq!(response.status().as_u16(), 409);
+}
+
+#[tokio::test]
+async fn confirmation_links_do_not_bring_back_unsubscribed_subscribers() {
+    let app = spawn_app().await;
+    let link = confirmation_link(&app).await;
+    let token = sqlx::query!("SELECT unsubscribe_token FROM subscriptions")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap()
+        .unsubscribe_token;
+    let response = app.post_unsubscribe(Some(&token)).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = reqwest::get(link).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 409);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, SubscriberStatus::Unsubscribed.to_string());
+}
+
+#[tokio::test]
+async fn confirmation_links_do_not_override_a_complaint() {
+    let app = spawn_app().await;
+    let link = confirmation_link(&app).await;
+    // Even with the link left unspent
+    sqlx::query!("UPDATE subscriptions SET status = 'complained'")
+        .execute(&app.pool)
+        .await
+        .unwrap();
+
+    let response = reqwest::get(link).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 409);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, SubscriberStatus::Complained.to_string());
+}
+
+#[tokio::test]
+async fn expired_confirmation_links_are_rejected_with_410() {
+    let app = spawn_app().await;
+    let link = confirmation_link(&app).await;
+
+    sqlx::query!(
+        "UPDATE subscription_tokens SET created_at = NOW() - INTERVAL '2 days'"
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+
+    let response = reqwest::get(link).await.unwrap();
+    assert_eq!(response.status().as_u16(), 410);
+
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(
+        saved.status,
+        SubscriberStatus::PendingConfirmation.to_string()
+    );
+}
+
+#[tokio::test]
+async fn unknown_confirmation_tokens_are_rejected_with_401() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!(
+        "{}/subscriptions/confirm?token=unknown",
+        app.address
+    ))
+    .await
+    .unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn expired_subscription_tokens_are_cleaned() {
+    let app = spawn_app().await;
+    confirmation_link(&app).await;
+
+    app.clean_all_subscription_tokens().await;
+
+    let remaining =
+        sqlx::query!("SELECT subscription_token FROM subscription_tokens")
+            .fetch_all(&app.pool)
+            .await
+            .unwrap();
+    assert!(remaining.is_empty());
+}
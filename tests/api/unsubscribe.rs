@@ -0,0 +1,74 @@
+use craft::domain::subscriber::SubscriberStatus;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helper::{TestApp, spawn_app, valid_subscriber};
+
+async fn create_subscriber(app: &TestApp) -> String {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(&valid_subscriber()).await;
+
+    sqlx::query!("SELECT unsubscribe_token FROM subscriptions")
+        .fetch_one(&app.pool)
+        .await
+        .expect("Failed to fetch unsubscribe token")
+        .unsubscribe_token
+}
+
+#[tokio::test]
+async fn unsubscribe_without_token_is_rejected_with_400() {
+    let app = spawn_app().await;
+
+    let response = app.post_unsubscribe(None).await;
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn unsubscribe_with_unknown_token_is_rejected_with_401() {
+    let app = spawn_app().await;
+
+    let response = app.post_unsubscribe(Some("unknown-token")).await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = app.get_unsubscribe("unknown-token").await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn unsubscribe_page_does_not_change_the_subscription() {
+    let app = spawn_app().await;
+    let token = create_subscriber(&app).await;
+
+    let response = app.get_unsubscribe(&token).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(
+        saved.status,
+        SubscriberStatus::PendingConfirmation.to_string()
+    );
+}
+
+#[tokio::test]
+async fn one_click_unsubscribe_marks_the_subscriber_as_unsubscribed() {
+    let app = spawn_app().await;
+    let token = create_subscriber(&app).await;
+
+    let response = app.post_unsubscribe(Some(&token)).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, SubscriberStatus::Unsubscribed.to_string());
+}
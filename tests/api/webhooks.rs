@@ -0,0 +1,200 @@
+use craft::domain::subscriber::SubscriberStatus;
+use reqwest::StatusCode;
+use serde_json::{Value, json};
+use wiremock::{Mock, ResponseTemplate, matchers::any};
+
+use crate::helper::{
+    TestApp, WEBHOOK_PASSWORD, WEBHOOK_USERNAME, create_confirmed_subscriber,
+    spawn_app,
+};
+
+const CREDENTIALS: Option<(&str, &str)> =
+    Some((WEBHOOK_USERNAME, WEBHOOK_PASSWORD));
+
+async fn subscriber_email(app: &TestApp) -> String {
+    sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap()
+        .email
+}
+
+async fn subscriber_status(app: &TestApp) -> String {
+    sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap()
+        .status
+}
+
+fn bounce(id: i64, bounce_type: &str, email: &str) -> Value {
+    json!({
+        "RecordType": "Bounce",
+        "ID": id,
+        "Type": bounce_type,
+        "TypeCode": 1,
+        "Email": email,
+        "BouncedAt": "2026-10-17T12:00:00Z",
+        "Inactive": bounce_type == "HardBounce",
+    })
+}
+
+#[tokio::test]
+async fn webhooks_without_credentials_are_rejected() {
+    let app = spawn_app().await;
+
+    let body = bounce(1, "HardBounce", "ursula@example.com");
+    let response = app.post_postmark_webhook(&body, None).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = app
+        .post_postmark_webhook(&body, Some((WEBHOOK_USERNAME, "guess")))
+        .await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn hard_bounces_mark_the_subscriber_as_bounced() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let email = subscriber_email(&app).await;
+
+    let response = app
+        .post_postmark_webhook(&bounce(1, "HardBounce", &email), CREDENTIALS)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    assert_eq!(
+        subscriber_status(&app).await,
+        SubscriberStatus::Bounced.to_string()
+    );
+    let event =
+        sqlx::query!("SELECT record_type, event_type FROM email_events")
+            .fetch_one(&app.pool)
+            .await
+            .unwrap();
+    assert_eq!(event.record_type, "Bounce");
+    assert_eq!(event.event_type.as_deref(), Some("HardBounce"));
+}
+
+#[tokio::test]
+async fn signing_up_again_does_not_reactivate_a_bounced_subscriber() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let email = subscriber_email(&app).await;
+    app.post_postmark_webhook(&bounce(1, "HardBounce", &email), CREDENTIALS)
+        .await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscriptions_json(&json!({ "name": "le guin", "email": email }))
+        .await;
+    app.dispatch_all_outbox_emails().await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        subscriber_status(&app).await,
+        SubscriberStatus::Bounced.to_string()
+    );
+}
+
+#[tokio::test]
+async fn soft_bounces_are_recorded_without_changing_the_subscriber() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let email = subscriber_email(&app).await;
+
+    let response = app
+        .post_postmark_webhook(&bounce(1, "SoftBounce", &email), CREDENTIALS)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    assert_eq!(
+        subscriber_status(&app).await,
+        SubscriberStatus::Confirmed.to_string()
+    );
+    let events = sqlx::query!("SELECT event_id FROM email_events")
+        .fetch_all(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(events.len(), 1);
+}
+
+#[tokio::test]
+async fn spam_complaints_stop_further_newsletters() {
+    let app = spawn_app().await;
+    app.login().await;
+    create_confirmed_subscriber(&app).await;
+    let email = subscriber_email(&app).await;
+
+    let body = json!({
+        "RecordType": "SpamComplaint",
+        "ID": 42,
+        "Type": "SpamComplaint",
+        "Email": email,
+        "BouncedAt": "2026-10-17T12:00:00Z",
+    });
+    let response = app.post_postmark_webhook(&body, CREDENTIALS).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        subscriber_status(&app).await,
+        SubscriberStatus::Complained.to_string()
+    );
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletters(&json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        },
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    }))
+    .await;
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn redelivered_events_are_recorded_once() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let email = subscriber_email(&app).await;
+
+    let body = bounce(7, "HardBounce", &email);
+    for _ in 0..2 {
+        let response = app.post_postmark_webhook(&body, CREDENTIALS).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    let events = sqlx::query!("SELECT event_id FROM email_events")
+        .fetch_all(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(events.len(), 1);
+}
+
+#[tokio::test]
+async fn other_record_types_are_acknowledged_and_ignored() {
+    let app = spawn_app().await;
+
+    let body = json!({
+        "RecordType": "Delivery",
+        "Recipient": "ursula@example.com",
+    });
+    let response = app.post_postmark_webhook(&body, CREDENTIALS).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let events = sqlx::query!("SELECT event_id FROM email_events")
+        .fetch_all(&app.pool)
+        .await
+        .unwrap();
+    assert!(events.is_empty());
+}